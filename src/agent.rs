@@ -0,0 +1,369 @@
+use std::{
+    any::{Any, TypeId},
+    collections::BTreeMap,
+};
+
+use glam::{vec2, Vec2};
+
+use crate::{flow_field::FlowField, spatial_hash::SpatialHash, CellType, Grid};
+
+// Top speed of an agent in pixels per second
+pub const AGENT_SPEED: f32 = 60.0;
+pub const AGENT_RADIUS: f32 = 4.0;
+// Agents closer than this push each other apart. Has to stay under half a cell for
+// the spatial hash to find everyone in range.
+const SEPARATION_RADIUS: f32 = 3.0 * AGENT_RADIUS;
+// How strongly separation competes with the flow direction
+const SEPARATION_WEIGHT: f32 = 1.5;
+// How quickly velocity turns towards the steering target, per second
+const STEERING_RATE: f32 = 8.0;
+
+// Every agent's state, one column per component, so updates and drawing run down
+// plain slices. An agent is its index, which changes when agents before it despawn.
+// Columns can't be resized from outside, so they always stay the same length.
+#[derive(Default)]
+pub struct Agents {
+    positions: Vec<Vec2>,
+    velocities: Vec<Vec2>,
+    // Source the field is steering the agent to, or the one it reached once it arrived
+    goals: Vec<Option<(usize, usize)>>,
+    arrived: Vec<bool>,
+    // Components added by users of the library, by type
+    extra: Vec<(TypeId, Box<dyn Column>)>,
+}
+
+// A column of some component, type erased so the store can keep it in step
+trait Column {
+    fn push_default(&mut self);
+    fn swap_remove(&mut self, index: usize);
+    fn clear(&mut self);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Default + 'static> Column for Vec<T> {
+    fn push_default(&mut self) {
+        self.push(T::default());
+    }
+
+    fn swap_remove(&mut self, index: usize) {
+        Vec::swap_remove(self, index);
+    }
+
+    fn clear(&mut self) {
+        Vec::clear(self);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+impl Agents {
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    // Adds a standing agent and returns its index. Extra components start out at
+    // their default.
+    pub fn spawn(&mut self, position: Vec2) -> usize {
+        self.positions.push(position);
+        self.velocities.push(Vec2::ZERO);
+        self.goals.push(None);
+        self.arrived.push(false);
+        for (_, column) in &mut self.extra {
+            column.push_default();
+        }
+        self.positions.len() - 1
+    }
+
+    // The last agent takes over the index
+    pub fn despawn(&mut self, index: usize) {
+        self.positions.swap_remove(index);
+        self.velocities.swap_remove(index);
+        self.goals.swap_remove(index);
+        self.arrived.swap_remove(index);
+        for (_, column) in &mut self.extra {
+            column.swap_remove(index);
+        }
+    }
+
+    // Sends an agent back to `position`, standing and not headed anywhere yet. Extra
+    // components are left as they are.
+    pub fn restart(&mut self, index: usize, position: Vec2) {
+        self.positions[index] = position;
+        self.velocities[index] = Vec2::ZERO;
+        self.goals[index] = None;
+        self.arrived[index] = false;
+    }
+
+    // Despawns every agent `keep` says no to
+    pub fn retain(&mut self, mut keep: impl FnMut(&Agents, usize) -> bool) {
+        let removed: Vec<usize> = (0..self.len()).filter(|&index| !keep(self, index)).collect();
+        for index in removed.into_iter().rev() {
+            self.despawn(index);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.positions.clear();
+        self.velocities.clear();
+        self.goals.clear();
+        self.arrived.clear();
+        for (_, column) in &mut self.extra {
+            column.clear();
+        }
+    }
+
+    pub fn positions(&self) -> &[Vec2] {
+        &self.positions
+    }
+
+    pub fn positions_mut(&mut self) -> &mut [Vec2] {
+        &mut self.positions
+    }
+
+    pub fn velocities(&self) -> &[Vec2] {
+        &self.velocities
+    }
+
+    pub fn velocities_mut(&mut self) -> &mut [Vec2] {
+        &mut self.velocities
+    }
+
+    pub fn goals(&self) -> &[Option<(usize, usize)>] {
+        &self.goals
+    }
+
+    pub fn arrived(&self) -> &[bool] {
+        &self.arrived
+    }
+
+    // Adds a column of `T` for every agent, starting at the default. Does nothing if
+    // there already is one.
+    pub fn add_component<T: Default + 'static>(&mut self) {
+        if self.component::<T>().is_none() {
+            let column: Vec<T> = (0..self.len()).map(|_| T::default()).collect();
+            self.extra.push((TypeId::of::<T>(), Box::new(column)));
+        }
+    }
+
+    pub fn component<T: 'static>(&self) -> Option<&[T]> {
+        self.extra
+            .iter()
+            .find(|(type_id, _)| *type_id == TypeId::of::<T>())
+            .and_then(|(_, column)| column.as_any().downcast_ref::<Vec<T>>())
+            .map(Vec::as_slice)
+    }
+
+    pub fn component_mut<T: 'static>(&mut self) -> Option<&mut [T]> {
+        self.extra
+            .iter_mut()
+            .find(|(type_id, _)| *type_id == TypeId::of::<T>())
+            .and_then(|(_, column)| column.as_any_mut().downcast_mut::<Vec<T>>())
+            .map(Vec::as_mut_slice)
+    }
+}
+
+// Agents headed for one source, and how many of them made it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct GoalStats {
+    pub goal: (usize, usize),
+    pub on_the_way: usize,
+    pub arrived: usize,
+}
+
+// One entry per source any agent is headed for or has reached, ordered by position
+pub fn goal_stats(agents: &Agents) -> Vec<GoalStats> {
+    let mut counts = BTreeMap::<(usize, usize), GoalStats>::new();
+    for (&goal, &arrived) in agents.goals.iter().zip(&agents.arrived) {
+        let Some(goal) = goal else {
+            continue;
+        };
+        let stats = counts.entry(goal).or_insert(GoalStats {
+            goal,
+            on_the_way: 0,
+            arrived: 0,
+        });
+        if arrived {
+            stats.arrived += 1;
+        } else {
+            stats.on_the_way += 1;
+        }
+    }
+    counts.into_values().collect()
+}
+
+// Sum of pushes away from every neighbor inside SEPARATION_RADIUS, stronger the closer
+// they are. Agents sitting on the exact same spot get pushed apart by index so stacks
+// still break up. `wrap_size` is the world size when the map wraps around.
+fn separation(grid: &Grid, positions: &[Vec2], hash: &SpatialHash, index: usize, wrap_size: Option<Vec2>) -> Vec2 {
+    let position = positions[index];
+    let mut push = Vec2::ZERO;
+
+    for other_index in hash.nearby(grid, position, SEPARATION_RADIUS) {
+        if other_index == index {
+            continue;
+        }
+        let mut offset = position - positions[other_index];
+        if let Some(size) = wrap_size {
+            if offset.x.abs() * 2.0 > size.x {
+                offset.x -= offset.x.signum() * size.x;
+            }
+            if offset.y.abs() * 2.0 > size.y {
+                offset.y -= offset.y.signum() * size.y;
+            }
+        }
+        let distance = offset.length();
+        if distance >= SEPARATION_RADIUS {
+            continue;
+        }
+
+        let away = if distance > f32::EPSILON {
+            offset / distance
+        } else {
+            Vec2::from_angle(index as f32)
+        };
+        push += away * (1.0 - distance / SEPARATION_RADIUS);
+    }
+
+    push
+}
+
+// Around the level the agent is on, which wraps on its own
+fn wrap_position(grid: &Grid, level: usize, position: Vec2) -> Vec2 {
+    let (origin, size) = grid.level_area(level);
+    let local = position - origin;
+    origin + vec2(local.x.rem_euclid(size.x), local.y.rem_euclid(size.y))
+}
+
+// Agents only change levels by taking the stairs
+fn is_walkable(grid: &Grid, level: Option<usize>, position: Vec2) -> bool {
+    grid.get_cell_at(position).is_some_and(|cell| {
+        grid.grid[cell.1][cell.0].cell_type != CellType::Barrier && level.is_none_or(|level| grid.level_of(cell) == level)
+    })
+}
+
+// Steers every agent along the field while keeping its distance from the others. The
+// steering targets are all computed before anyone moves so the result doesn't depend
+// on update order.
+pub fn update_agents(agents: &mut Agents, flow_field: &FlowField, grid: &Grid, delta_time: f32) {
+    let wrap_size = grid.wrap.then(|| grid.level_area(0).1);
+    let hash = SpatialHash::build(grid, &agents.positions);
+    let targets: Vec<Vec2> = (0..agents.len())
+        .map(|index| {
+            let field_direction = flow_field.sample(agents.positions[index]);
+            let separation = separation(grid, &agents.positions, &hash, index, wrap_size);
+            let steering = field_direction + separation * SEPARATION_WEIGHT;
+            steering.clamp_length_max(1.0) * AGENT_SPEED
+        })
+        .collect();
+
+    let Agents {
+        positions,
+        velocities,
+        goals,
+        arrived,
+        ..
+    } = agents;
+    for ((((position, velocity), goal), arrived), target) in positions
+        .iter_mut()
+        .zip(velocities.iter_mut())
+        .zip(goals.iter_mut())
+        .zip(arrived.iter_mut())
+        .zip(targets)
+    {
+        let blend = (STEERING_RATE * delta_time).min(1.0);
+        *velocity = velocity.lerp(target, blend);
+
+        // Move one axis at a time so agents slide along walls instead of sticking
+        let step = *velocity * delta_time;
+        let level = grid.get_cell_at(*position).map(|cell| grid.level_of(cell));
+        let moved = |position: Vec2| match (grid.wrap, level) {
+            (true, Some(level)) => wrap_position(grid, level, position),
+            _ => position,
+        };
+        if is_walkable(grid, level, moved(*position + vec2(step.x, 0.0))) {
+            *position = moved(*position + vec2(step.x, 0.0));
+        } else {
+            velocity.x = 0.0;
+        }
+        if is_walkable(grid, level, moved(*position + vec2(0.0, step.y))) {
+            *position = moved(*position + vec2(0.0, step.y));
+        } else {
+            velocity.y = 0.0;
+        }
+
+        // Stepping into a portal the field leads through comes out the same spot of the twin
+        if let Some((col_x, row_y)) = grid.get_cell_at(*position) {
+            if let Some(twin) = flow_field.next_cell(col_x, row_y).filter(|&next| grid.is_portal_jump((col_x, row_y), next)) {
+                let offset = *position - grid.topology.cell_center((col_x, row_y));
+                *position = grid.topology.cell_center(twin) + offset;
+            }
+        }
+
+        // Reaching any source counts as arriving there, for good
+        if !*arrived {
+            if let Some((col_x, row_y)) = grid.get_cell_at(*position) {
+                if grid.grid[row_y][col_x].cell_type == CellType::Source {
+                    *goal = Some((col_x, row_y));
+                    *arrived = true;
+                } else {
+                    *goal = flow_field.goal(col_x, row_y);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Default, Debug, PartialEq)]
+    struct Tag(u32);
+
+    #[test]
+    fn extra_components_stay_in_step() {
+        let mut agents = Agents::default();
+        agents.spawn(Vec2::ZERO);
+        agents.add_component::<Tag>();
+        agents.spawn(Vec2::X);
+        agents.spawn(Vec2::Y);
+        for (index, tag) in agents.component_mut::<Tag>().unwrap().iter_mut().enumerate() {
+            tag.0 = index as u32;
+        }
+
+        // The last agent moves into the hole, along with its tag
+        agents.despawn(0);
+        assert_eq!(agents.positions(), [Vec2::Y, Vec2::X]);
+        assert_eq!(agents.component::<Tag>().unwrap(), [Tag(2), Tag(1)]);
+
+        agents.retain(|agents, index| agents.positions()[index] != Vec2::Y);
+        assert_eq!(agents.component::<Tag>().unwrap(), [Tag(1)]);
+        assert_eq!(agents.goals().len(), 1);
+
+        agents.clear();
+        assert!(agents.is_empty());
+        assert_eq!(agents.component::<Tag>().unwrap(), []);
+        assert!(agents.component::<u8>().is_none());
+    }
+
+    #[test]
+    fn adding_a_component_twice_keeps_the_first() {
+        let mut agents = Agents::default();
+        agents.spawn(Vec2::ZERO);
+        agents.add_component::<Tag>();
+        agents.component_mut::<Tag>().unwrap()[0] = Tag(7);
+        agents.add_component::<Tag>();
+        assert_eq!(agents.component::<Tag>().unwrap(), [Tag(7)]);
+    }
+}
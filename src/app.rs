@@ -0,0 +1,1726 @@
+#[cfg(not(feature = "fs"))]
+use std::collections::HashMap;
+
+use macroquad::prelude::*;
+
+#[cfg(feature = "fs")]
+use crate::capture::Capture;
+#[cfg(feature = "fs")]
+use flowfields::export::{self, Format};
+use crate::{
+    chunks::ChunkRenderer,
+    gpu::GpuSolver,
+    input::{Pointer, PointerActions},
+    keymap::{Action, Keymap},
+    minimap::Minimap,
+    perf::{FieldWork, PerfOverlay},
+    view::{Hud, View},
+};
+use flowfields::{
+    agent::{self, Agents, GoalStats, AGENT_RADIUS},
+    astar,
+    colormap::Colormap,
+    cost_script::CostScript,
+    field_cache::FieldCache,
+    fill,
+    flow_field::{FieldLayer, FlowField},
+    map_file,
+    mapgen::MapKind,
+    metric::Metric,
+    obstacle::{self, Obstacle},
+    recording::{Recorder, Replay},
+    region::{self, Region},
+    server::{self, Server},
+    solver::{Propagation, Solver},
+    run_stats::{Arrival, RunStats},
+    spawner::Spawning,
+    streamline::{self, Streamline},
+    stroke::{Stroke, StrokeKind},
+    symmetry::Symmetry,
+    topology::{HexTopology, SquareTopology, Topology},
+    Cell, CellType, Edit, Grid, Heading, PaintTool, Terrain, CELL_SIZE, MAX_ELEVATION, MAX_GRID_SIZE, MIN_GRID_SIZE,
+};
+
+// Amount the highlighted cells are dimmed when hovered. Lower value = more dim.
+const HIGHLIGHT_DIM_AMOUNT: f32 = 0.75; 
+// Numbers are left out when cells are narrower than this on screen, in pixels
+const MIN_NUMBER_PIXELS: f32 = 16.0;
+const CELLS_HORIZONTAL: usize = 20;
+const CELLS_VERTICAL: usize = 20;
+// Step used when adjusting the danger layer weight with [ and ]
+const DANGER_WEIGHT_STEP: f32 = 0.5;
+// Number of agents dropped into the hovered cell with G
+const AGENT_SPAWN_BATCH: usize = 10;
+// Frames between agents from spawner cells to start with, changed with , and .
+const SPAWN_INTERVAL: u32 = 30;
+const MAX_SPAWN_INTERVAL: u32 = 480;
+// Spawners hold off while there are this many agents
+const SPAWNER_AGENT_CAP: usize = 500;
+// Cells per second for obstacles spawned with Enter
+const OBSTACLE_SPEED: f32 = 3.0;
+// Fields kept around for goal setups that might come back
+const FIELD_CACHE_SIZE: usize = 16;
+// Sources scattered over a generated map while ScatterSources is held
+const RANDOM_SOURCE_COUNT: usize = 3;
+// Where F5 saves recordings and F6/F7 replay them from
+const RECORDING_PATH: &str = "recording.txt";
+// Where F2 saves the map and F3 loads it from
+const MAP_PATH: &str = "map.txt";
+// Where F12 writes the finished run's trips
+const RUN_STATS_PATH: &str = "run_stats.csv";
+// Image F8 sets the elevation from, brighter is higher
+#[cfg(feature = "fs")]
+const HEIGHTMAP_PATH: &str = "heightmap.png";
+// Extra heatmap gradients read on startup, see Colormap::parse_list for the format
+const GRADIENTS_PATH: &str = "gradients.txt";
+// Key bindings read on startup, see Keymap::parse for the format
+const KEYS_PATH: &str = "keys.txt";
+// Cost script replacing the terrain costs, see CostScript for the language. It's read
+// again every COST_SCRIPT_POLL_INTERVAL seconds so edits show up right away.
+const COSTS_PATH: &str = "costs.txt";
+const COST_SCRIPT_POLL_INTERVAL: f64 = 1.0;
+// Zoom change per scroll notch or zoom key press, and how far in it goes
+const ZOOM_STEP: f32 = 1.25;
+const MAX_ZOOM: f32 = 16.0;
+// How much of the terrain color shows through the heatmap
+const TERRAIN_SHOW_THROUGH: f32 = 0.3;
+// How steep hillshading makes a level of elevation look, in cells
+const HILLSHADE_RELIEF: f32 = 0.5;
+// Cells between streamline seeds
+const STREAMLINE_SPACING: usize = 2;
+// How much smoothing blurs the directions, stepped through in turn
+const SMOOTHING_STRENGTHS: [f32; 4] = [0.25, 0.5, 0.75, 1.0];
+// Extra cost of a cell for every agent in it, one grass step each
+const CROWDING_WEIGHT: f32 = 2.0;
+
+// Text saves for maps and recordings. With the fs feature they're files in the working
+// directory, without it (the browser build) they only live until the page is closed.
+#[derive(Default)]
+struct Saves {
+    #[cfg(not(feature = "fs"))]
+    memory: HashMap<&'static str, String>,
+}
+
+impl Saves {
+    #[cfg(feature = "fs")]
+    fn write(&mut self, path: &'static str, contents: String) -> Result<(), String> {
+        std::fs::write(path, contents).map_err(|error| error.to_string())
+    }
+
+    #[cfg(feature = "fs")]
+    fn read(&self, path: &'static str) -> Result<String, String> {
+        std::fs::read_to_string(path).map_err(|error| error.to_string())
+    }
+
+    #[cfg(not(feature = "fs"))]
+    fn write(&mut self, path: &'static str, contents: String) -> Result<(), String> {
+        self.memory.insert(path, contents);
+        Ok(())
+    }
+
+    #[cfg(not(feature = "fs"))]
+    fn read(&self, path: &'static str) -> Result<String, String> {
+        self.memory.get(path).cloned().ok_or_else(|| "nothing saved yet".to_string())
+    }
+}
+
+// What gets drawn on top of the map, cycled with Tab
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum RenderMode {
+    Heatmap,
+    Arrows,
+    Both,
+    Numbers,
+    Plain,
+    // Heatmap lit from the top left as if elevation were terrain
+    Hillshade,
+}
+
+impl RenderMode {
+    const ALL: [RenderMode; 6] = [
+        RenderMode::Heatmap,
+        RenderMode::Arrows,
+        RenderMode::Both,
+        RenderMode::Numbers,
+        RenderMode::Plain,
+        RenderMode::Hillshade,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            RenderMode::Heatmap => "heatmap",
+            RenderMode::Arrows => "arrows",
+            RenderMode::Both => "heatmap + arrows",
+            RenderMode::Numbers => "numbers",
+            RenderMode::Plain => "plain",
+            RenderMode::Hillshade => "hillshade",
+        }
+    }
+
+    fn heatmap(self) -> bool {
+        matches!(self, RenderMode::Heatmap | RenderMode::Both | RenderMode::Hillshade)
+    }
+
+    fn arrows(self) -> bool {
+        matches!(self, RenderMode::Arrows | RenderMode::Both)
+    }
+}
+
+fn terrain_color(terrain: Terrain) -> macroquad::color::Color {
+    match terrain {
+        Terrain::Road => macroquad::color::Color::new(0.8, 0.8, 0.75, 1.0),
+        Terrain::Grass => macroquad::color::Color::new(0.85, 1.0, 0.85, 1.0),
+        Terrain::Mud => macroquad::color::Color::new(0.6, 0.45, 0.3, 1.0),
+        Terrain::Water => macroquad::color::Color::new(0.4, 0.6, 1.0, 1.0),
+    }
+}
+
+// `heatmap` is the gradient to color distances with and the largest distance on the
+// map, which lands on the far end of the gradient
+fn cell_color(cell: &Cell, heatmap: Option<(&Colormap, f32)>) -> macroquad::color::Color {
+    match cell.cell_type {
+        CellType::Barrier => macroquad::color::colors::BLACK,
+
+        CellType::Source => macroquad::color::colors::RED, 
+
+        CellType::Danger => macroquad::color::colors::ORANGE,
+
+        CellType::Spawner => macroquad::color::colors::LIME,
+        
+        CellType::Active | CellType::Inactive => {
+            let terrain_color = terrain_color(cell.terrain);
+            match (cell.cell_number, heatmap) {
+                (Some(value), Some((colormap, max_distance))) => {
+                    // Seeds start at 1
+                    let t = if max_distance > 1.0 { (value - 1.0) / (max_distance - 1.0) } else { 0.0 };
+                    let [r, g, b] = colormap.sample(t);
+                    let blend = |heat: f32, terrain: f32| heat + (terrain - heat) * TERRAIN_SHOW_THROUGH;
+                    macroquad::color::Color {
+                        r: blend(r, terrain_color.r),
+                        g: blend(g, terrain_color.g),
+                        b: blend(b, terrain_color.b),
+                        a: 1.0,
+                    }
+                }
+
+                _ => terrain_color,
+            }
+        },
+    }
+}
+
+
+// Brightness of a cell lit from the top left, 1 on flat ground. Slopes come from the
+// cells on either side, which only lines up with the neighbors on square grids.
+fn hillshade(grid: &Grid, (col_x, row_y): (usize, usize)) -> f32 {
+    let elevation = |col_x: usize, row_y: usize| grid.grid[row_y][col_x].elevation as f32 * HILLSHADE_RELIEF;
+    let light = vec3(-1.0, -1.0, 1.0).normalize();
+    let (left, right) = (col_x.saturating_sub(1), (col_x + 1).min(grid.column_count_x - 1));
+    let (up, down) = (row_y.saturating_sub(1), (row_y + 1).min(grid.row_count_y - 1));
+    let slope_x = (elevation(right, row_y) - elevation(left, row_y)) / (right - left).max(1) as f32;
+    let slope_y = (elevation(col_x, down) - elevation(col_x, up)) / (down - up).max(1) as f32;
+    let normal = vec3(-slope_x, -slope_y, 1.0).normalize();
+    normal.dot(light).max(0.0) / light.z
+}
+
+// Largest distance from a source, for normalizing the heatmap
+fn largest_distance(grid: &Grid) -> f32 {
+    grid.grid
+        .iter()
+        .flatten()
+        .filter_map(|cell| cell.cell_number)
+        .fold(1.0, f32::max)
+}
+
+// Shows a propagation's distances so far in the cells, unreached cells get none
+fn show_propagation(grid: &mut Grid, propagation: &Propagation) {
+    for (cell, distance) in grid.grid.iter_mut().flatten().zip(propagation.distances().iter().flatten()) {
+        cell.cell_number = *distance;
+    }
+}
+
+// Draws a path through the cell centers, shifted by `offset` pixels on both axes.
+// Steps that wrap around the edge of the map are left out.
+fn draw_path(grid: &Grid, path: &[(usize, usize)], offset: f32, color: macroquad::color::Color) {
+    for step in path.windows(2) {
+        let from = grid.topology.cell_center(step[0]) + offset;
+        let to = grid.topology.cell_center(step[1]) + offset;
+        if from.distance(to) > CELL_SIZE * 1.5 {
+            continue;
+        }
+        draw_line(from.x, from.y, to.x, to.y, 4.0, color);
+    }
+}
+
+#[cfg(feature = "fs")]
+fn load_heightmap(path: &str) -> Result<Image, String> {
+    let bytes = std::fs::read(path).map_err(|error| error.to_string())?;
+    // macroquad panics on images it can't decode
+    std::panic::catch_unwind(|| Image::from_file_with_format(&bytes, None)).map_err(|_| "couldn't decode it".to_string())
+}
+
+// What the inspector shows about a cell
+fn inspector_lines(
+    grid: &Grid,
+    flow_field: &FlowField,
+    goals: &[GoalStats],
+    agents_here: usize,
+    (col_x, row_y): (usize, usize),
+) -> Vec<String> {
+    let cell = &grid.grid[row_y][col_x];
+    let mut lines = vec![
+        format!("Cell {},{}", col_x, row_y),
+        format!("Type: {:?}", cell.cell_type),
+        format!("Terrain: {:?} (cost {:.2})", cell.terrain, grid.step_cost((col_x, row_y))),
+        format!("Elevation: {}/{}", cell.elevation, MAX_ELEVATION),
+        match cell.cell_number {
+            Some(distance) => format!("Distance: {:.2}", distance),
+            None => "Distance: unreached".to_string(),
+        },
+    ];
+    let direction = flow_field.direction(col_x, row_y);
+    lines.push(match flow_field.next_cell(col_x, row_y) {
+        Some((next_x, next_y)) => format!("Flow: {:.2},{:.2} to {},{}", direction.x, direction.y, next_x, next_y),
+        None => "Flow: none".to_string(),
+    });
+    if let Some((goal_x, goal_y)) = flow_field.goal(col_x, row_y).filter(|_| cell.cell_type != CellType::Source) {
+        lines.push(format!("Goal: {},{}", goal_x, goal_y));
+    }
+    if agents_here > 0 {
+        lines.push(format!("Agents here: {}", agents_here));
+    }
+    if let Some(stats) = goals.iter().find(|stats| stats.goal == (col_x, row_y)) {
+        lines.push(format!("Agents: {} arrived, {} on the way", stats.arrived, stats.on_the_way));
+    }
+    if let Some(heading) = cell.one_way {
+        lines.push(format!("One-way: {:?}", heading));
+    }
+    if let Some((twin_x, twin_y)) = cell.portal {
+        lines.push(format!("Portal to {},{}", twin_x, twin_y));
+    }
+    lines
+}
+
+fn fill_cell(grid: &Grid, cell: (usize, usize), color: macroquad::color::Color) {
+    let corners = grid.topology.cell_corners(cell);
+    for corner in 1..corners.len() - 1 {
+        draw_triangle(corners[0], corners[corner], corners[corner + 1], color);
+    }
+}
+
+// Three steps climbing to the right, for a stairs cell
+fn draw_stairs(center: Vec2) {
+    let step = CELL_SIZE * 0.2;
+    for index in 0..3 {
+        let corner = center + vec2(-1.5 + index as f32, 1.5 - index as f32) * step;
+        draw_line(corner.x, corner.y, corner.x + step, corner.y, 3.0, VIOLET);
+        draw_line(corner.x + step, corner.y, corner.x + step, corner.y - step, 3.0, VIOLET);
+    }
+}
+
+// Each line fades in along the way it flows and ends in an arrowhead. Seeds that
+// don't go anywhere get a cross, so dead zones stand out.
+fn draw_streamlines(streamlines: &[Streamline]) {
+    for line in streamlines {
+        if line.is_dead() {
+            let size = CELL_SIZE * 0.1;
+            let (x, y) = (line.seed.x, line.seed.y);
+            draw_line(x - size, y - size, x + size, y + size, 1.0, GRAY);
+            draw_line(x - size, y + size, x + size, y - size, 1.0, GRAY);
+            continue;
+        }
+        let segment_count = line.points.len() - 1;
+        for (index, segment) in line.points.windows(2).enumerate() {
+            let alpha = 0.2 + 0.7 * (index + 1) as f32 / segment_count as f32;
+            draw_line(segment[0].x, segment[0].y, segment[1].x, segment[1].y, 1.5, Color::new(0.1, 0.1, 0.4, alpha));
+        }
+        let [before, end] = [line.points[segment_count - 1], line.points[segment_count]];
+        let direction = (end - before).normalize_or_zero();
+        for side in [-1.0, 1.0] {
+            let barb = end - direction * CELL_SIZE * 0.15 + direction.perp() * side * CELL_SIZE * 0.1;
+            draw_line(end.x, end.y, barb.x, barb.y, 1.5, Color::new(0.1, 0.1, 0.4, 0.9));
+        }
+    }
+}
+
+// Edits for the whole map from the generate, clear and wrap keys
+fn map_edits(grid: &Grid, keymap: &Keymap) -> Vec<Edit> {
+    let mut edits = Vec::new();
+    for (action, map_kind) in [
+        (Action::Maze, MapKind::Maze),
+        (Action::Caves, MapKind::Caves),
+        (Action::Noise, MapKind::Noise),
+    ] {
+        if keymap.pressed(action) {
+            let scatter = keymap.down(Action::ScatterSources);
+            edits.push(Edit::Generate {
+                kind: map_kind,
+                source_count: if scatter { RANDOM_SOURCE_COUNT } else { 0 },
+                seed: rand::rand() as u64,
+            });
+        }
+    }
+
+    if keymap.pressed(Action::ClearField) {
+        edits.extend(grid.get_coordinates_of_type(CellType::Source).into_iter().map(Edit::ToggleSource));
+    }
+
+    if keymap.pressed(Action::ClearMap) {
+        edits.push(Edit::Clear);
+    }
+
+    if keymap.pressed(Action::Wrap) {
+        edits.push(Edit::ToggleWrap);
+    }
+    edits
+}
+
+// The arrow keys (by default) add and remove columns and rows. None when the size stays.
+fn resize_edit(grid: &Grid, keymap: &Keymap) -> Option<Edit> {
+    let (mut row_count, mut column_count) = (grid.row_count_y, grid.column_count_x);
+    if keymap.pressed(Action::AddColumn) {
+        column_count += 1;
+    }
+    if keymap.pressed(Action::RemoveColumn) {
+        column_count -= 1;
+    }
+    // Every level gets the row
+    if keymap.pressed(Action::AddRow) {
+        row_count += grid.levels;
+    }
+    if keymap.pressed(Action::RemoveRow) {
+        row_count -= grid.levels;
+    }
+    let row_count = row_count.clamp(MIN_GRID_SIZE * grid.levels, MAX_GRID_SIZE / grid.levels * grid.levels);
+    let column_count = column_count.clamp(MIN_GRID_SIZE, MAX_GRID_SIZE);
+    Some(Edit::Resize { row_count, column_count })
+        .filter(|_| (row_count, column_count) != (grid.row_count_y, grid.column_count_x))
+}
+
+// The paint tool and everything the pointer is in the middle of: the stroke being drawn,
+// the selection and clipboard, and the first end of a portal being linked
+struct Editing {
+    paint_tool: PaintTool,
+    // Picked with 7 and turned with `
+    one_way_heading: Heading,
+    // The paint or source drag in progress
+    stroke: Option<Stroke>,
+    // Dragging selects instead of painting, toggled with B
+    select_mode: bool,
+    // Corners of the selected rectangle, the first is where the drag started
+    selection: Option<((usize, usize), (usize, usize))>,
+    selecting: bool,
+    // Painting fills the whole region under the pointer instead, toggled with /
+    fill_mode: bool,
+    // Whether the current press has filled already, so holding doesn't fill over and over
+    filled: bool,
+    clipboard: Option<Region>,
+    // Mirrors paint strokes across the map, cycled with K
+    symmetry: Symmetry,
+    // First end of a portal picked with T, the next T links it to the hovered cell
+    portal_start: Option<(usize, usize)>,
+}
+
+impl Editing {
+    // This frame's edits from the pointer and the editing keys, `mouse_cell` being the
+    // hovered cell on the level shown
+    fn edits(
+        &mut self,
+        grid: &Grid,
+        keymap: &Keymap,
+        pointer_actions: &PointerActions,
+        mouse_cell: Option<(usize, usize)>,
+        shown_level: usize,
+    ) -> Vec<Edit> {
+        for (action, tool) in [
+            (Action::ToolBarrier, PaintTool::Barrier),
+            (Action::ToolRoad, PaintTool::Terrain(Terrain::Road)),
+            (Action::ToolGrass, PaintTool::Terrain(Terrain::Grass)),
+            (Action::ToolMud, PaintTool::Terrain(Terrain::Mud)),
+            (Action::ToolWater, PaintTool::Terrain(Terrain::Water)),
+            (Action::ToolDanger, PaintTool::Danger),
+            (Action::ToolOneWay, PaintTool::OneWay(self.one_way_heading)),
+            (Action::ToolSpawner, PaintTool::Spawner),
+            (Action::ToolRaise, PaintTool::Raise),
+            (Action::ToolLower, PaintTool::Lower),
+        ] {
+            if keymap.pressed(action) {
+                self.paint_tool = tool;
+            }
+        }
+
+        if keymap.pressed(Action::TurnOneWay) {
+            self.one_way_heading = self.one_way_heading.rotated_clockwise();
+            if let PaintTool::OneWay(_) = self.paint_tool {
+                self.paint_tool = PaintTool::OneWay(self.one_way_heading);
+            }
+        }
+
+        let mut edits = Vec::new();
+        let stroke_kind = if pointer_actions.place_source {
+            Some(StrokeKind::Source)
+        } else if pointer_actions.paint && !self.select_mode && !self.fill_mode {
+            Some(StrokeKind::Paint(self.paint_tool))
+        } else {
+            None
+        };
+        match (stroke_kind, mouse_cell) {
+            (Some(kind), Some(hovered)) => match &mut self.stroke {
+                Some(active) if active.kind() == kind => edits.extend(active.extend(grid, hovered)),
+                _ => {
+                    let (started, first_edits) = Stroke::start(grid, kind, self.symmetry, hovered);
+                    self.stroke = Some(started);
+                    edits.extend(first_edits);
+                }
+            },
+            // Leaving the grid mid drag picks the line back up where it comes back in
+            (Some(_), None) => {}
+            (None, _) => self.stroke = None,
+        }
+
+        if keymap.pressed(Action::Symmetry) {
+            let next = Symmetry::ALL.iter().position(|&mode| mode == self.symmetry).map_or(0, |index| index + 1);
+            self.symmetry = Symmetry::ALL[next % Symmetry::ALL.len()];
+        }
+
+        if keymap.pressed(Action::SelectMode) {
+            self.select_mode = !self.select_mode;
+            self.selection = None;
+        }
+        if keymap.pressed(Action::FillMode) {
+            self.fill_mode = !self.fill_mode;
+        }
+        if self.fill_mode && !self.select_mode && pointer_actions.paint {
+            if let (false, Some(hovered)) = (self.filled, mouse_cell) {
+                edits.extend(fill::fill_edits(grid, self.paint_tool, self.symmetry, hovered));
+                self.filled = true;
+            }
+        } else {
+            self.filled = false;
+        }
+
+        if self.select_mode && pointer_actions.paint {
+            if let Some(hovered) = mouse_cell {
+                self.selection = match self.selection {
+                    Some((start, _)) if self.selecting => Some((start, hovered)),
+                    _ => Some((hovered, hovered)),
+                };
+                self.selecting = true;
+            }
+        } else {
+            self.selecting = false;
+        }
+
+        if let Some((corner, opposite)) = self.selection {
+            if keymap.pressed(Action::Copy) || keymap.pressed(Action::Cut) {
+                self.clipboard = Some(Region::copy(grid, corner, opposite));
+            }
+            if keymap.pressed(Action::Cut) {
+                edits.extend(region::clear_edits(corner, opposite));
+            }
+        }
+        if let Some(copied) = &mut self.clipboard {
+            if keymap.pressed(Action::FlipHorizontal) {
+                *copied = copied.flipped_horizontally();
+            }
+            if keymap.pressed(Action::FlipVertical) {
+                *copied = copied.flipped_vertically();
+            }
+            if let Some(hovered) = mouse_cell.filter(|_| keymap.pressed(Action::Paste)) {
+                edits.extend(copied.paste_edits(grid, hovered));
+            }
+        }
+
+        if let Some(hovered) = mouse_cell {
+            if keymap.pressed(Action::LinkPortal) {
+                self.portal_start = match self.portal_start {
+                    Some(start) if start == hovered => None,
+                    Some(start) => {
+                        edits.push(Edit::TogglePortal(start, hovered));
+                        None
+                    }
+                    None => Some(hovered),
+                };
+            }
+            if keymap.pressed(Action::Stairs) {
+                let (col_x, row_y) = hovered;
+                let other_end = if shown_level + 1 < grid.levels {
+                    Some((col_x, row_y + grid.level_rows()))
+                } else {
+                    row_y.checked_sub(grid.level_rows()).map(|row_y| (col_x, row_y))
+                };
+                edits.extend(other_end.map(|other_end| Edit::TogglePortal(hovered, other_end)));
+            }
+        }
+        edits
+    }
+}
+
+// Everything the side panel reports on, borrowed from `run` for the frame
+struct HudStatus<'a> {
+    keymap: &'a Keymap,
+    grid: &'a Grid,
+    editing: &'a Editing,
+    agents: &'a Agents,
+    goal_stats: &'a [GoalStats],
+    astar_comparison: bool,
+    // Flow field and A* paths side by side, empty while the comparison is off
+    astar_lines: &'a [String],
+    danger_weight: f32,
+    recorder: Option<&'a Recorder>,
+    replay: Option<&'a Replay>,
+    scripted_costs: bool,
+    gpu_on: bool,
+    gpu_supported: bool,
+    render_mode: RenderMode,
+    show_streamlines: bool,
+    colormap: &'a Colormap,
+    #[cfg(feature = "fs")]
+    capture: &'a Capture,
+    propagation: Option<&'a Propagation>,
+    obstacle_count: usize,
+    patrol_route: &'a [(usize, usize)],
+    letterbox: bool,
+    inspector: bool,
+    show_perf: bool,
+    zoom: f32,
+    spawning: &'a Spawning,
+    run_stats: &'a RunStats,
+    arrival: Arrival,
+    shown_level: usize,
+    smoothing: bool,
+    smoothing_strength: f32,
+    crowding_feedback: bool,
+    busiest_cell: f32,
+}
+
+// Side panel text, one line after another
+fn draw_hud(status: &HudStatus) {
+    let key = |action| status.keymap.label(action);
+    let mut hud = Hud::start();
+    hud.line(&format!("Tool: {} ({} turns)", status.editing.paint_tool.name(), key(Action::TurnOneWay)));
+    hud.line(&format!("Danger weight: {:.1} ({} {})", status.danger_weight, key(Action::DangerDown), key(Action::DangerUp)));
+    hud.line(&format!("Path: {}, {}", key(Action::PreviewPath), key(Action::PinPath)));
+    hud.line(&format!("A* compare: {} ({})", if status.astar_comparison { "on" } else { "off" }, key(Action::Astar)));
+    for line in status.astar_lines {
+        hud.line(line);
+    }
+    let tool_keys: Vec<&str> = [
+        Action::ToolBarrier,
+        Action::ToolRoad,
+        Action::ToolGrass,
+        Action::ToolMud,
+        Action::ToolWater,
+        Action::ToolDanger,
+        Action::ToolOneWay,
+        Action::ToolSpawner,
+        Action::ToolRaise,
+        Action::ToolLower,
+    ]
+    .map(key)
+    .to_vec();
+    hud.line(&format!("Tools: {}", tool_keys.join(" ")));
+    hud.line(&format!(
+        "Agents: {}, {} arrived at {} goals ({}, {})",
+        status.agents.len(),
+        status.goal_stats.iter().map(|stats| stats.arrived).sum::<usize>(),
+        status.goal_stats.len(),
+        key(Action::SpawnAgents),
+        key(Action::ClearAgents)
+    ));
+    hud.line(&format!(
+        "Maps: {}, {}, {} (+{}), clear {}, sources {}",
+        key(Action::Maze),
+        key(Action::Caves),
+        key(Action::Noise),
+        key(Action::ScatterSources),
+        key(Action::ClearMap),
+        key(Action::ClearField)
+    ));
+    hud.line(&format!("Wrap: {} ({})", if status.grid.wrap { "on" } else { "off" }, key(Action::Wrap)));
+    let recording_status = match (status.recorder, status.replay) {
+        (Some(recorder), _) => format!("Recording: {} edits", recorder.edit_count()),
+        (None, Some(replay)) => format!("Replay: {}/{}", replay.position(), replay.edit_count()),
+        (None, None) => format!(
+            "Record: {}, Replay: {}/{}",
+            key(Action::Record),
+            key(Action::Replay),
+            key(Action::ReplayStep)
+        ),
+    };
+    hud.line(&recording_status);
+    hud.line(&format!("Map: {} save, {} load", key(Action::SaveMap), key(Action::LoadMap)));
+    hud.line(&format!(
+        "Solver: {} ({}), {} ({}), {} costs, GPU {} ({})",
+        status.grid.solver.name(),
+        key(Action::Solver),
+        status.grid.metric.name(),
+        key(Action::Metric),
+        if status.scripted_costs { "script" } else { "terrain" },
+        match status.gpu_on {
+            false => "off",
+            true if status.gpu_supported => "on",
+            true => "unsupported",
+        },
+        key(Action::Gpu)
+    ));
+    hud.line(&format!("Fit: {} ({})", if status.letterbox { "letterbox" } else { "stretch" }, key(Action::Letterbox)));
+    hud.line(&format!(
+        "Size: {}x{} ({} {} {} {})",
+        status.grid.column_count_x,
+        status.grid.row_count_y,
+        key(Action::RemoveColumn),
+        key(Action::AddColumn),
+        key(Action::RemoveRow),
+        key(Action::AddRow)
+    ));
+    hud.line(&format!(
+        "View: {} ({}), streamlines {} ({})",
+        status.render_mode.name(),
+        key(Action::View),
+        if status.show_streamlines { "on" } else { "off" },
+        key(Action::Streamlines)
+    ));
+    hud.line(&format!("Colors: {} ({})", status.colormap.name(), key(Action::Gradient)));
+    // Screenshots, GIFs and field exports need a filesystem to go to
+    #[cfg(feature = "fs")]
+    hud.line(&if status.capture.is_capturing() {
+        format!("Capturing: {} frames ({} stops)", status.capture.frame_count(), key(Action::CaptureGif))
+    } else {
+        format!(
+            "Capture: {} png, {} gif, {} field",
+            key(Action::Screenshot),
+            key(Action::CaptureGif),
+            key(Action::ExportField)
+        )
+    });
+    hud.line(&match status.propagation {
+        Some(active) if active.is_finished() => format!("Stepping: done ({} exits)", key(Action::StepMode)),
+        Some(_) => format!("Stepping: {} ({} exits)", key(Action::StepRing), key(Action::StepMode)),
+        None => format!("Step through: {}", key(Action::StepMode)),
+    });
+    hud.line(&format!(
+        "Obstacles: {}, route {} ({}, {}, {})",
+        status.obstacle_count,
+        status.patrol_route.len(),
+        key(Action::AddWaypoint),
+        key(Action::SpawnObstacle),
+        key(Action::ClearObstacles)
+    ));
+    hud.line(&match status.editing.portal_start {
+        Some(_) => format!("Portal: {} on the other end", key(Action::LinkPortal)),
+        None => format!("Portals: {} ({}, {})", status.grid.portal_pairs().len(), key(Action::LinkPortal), key(Action::LinkPortal)),
+    });
+    hud.line(&format!(
+        "Inspector: {} ({}), perf {} ({})",
+        if status.inspector { "on" } else { "off" },
+        key(Action::Inspector),
+        if status.show_perf { "on" } else { "off" },
+        key(Action::Perf)
+    ));
+    hud.line(&format!(
+        "Select: {} ({}), fill: {} ({}), clipboard {}",
+        if status.editing.select_mode { "on" } else { "off" },
+        key(Action::SelectMode),
+        if status.editing.fill_mode { "on" } else { "off" },
+        key(Action::FillMode),
+        status.editing.clipboard.as_ref().map_or("empty".to_string(), |copied| {
+            format!("{}x{}", copied.column_count(), copied.row_count())
+        })
+    ));
+    hud.line(&format!(
+        "Copy {}, cut {}, paste {}, flip {} {}",
+        key(Action::Copy),
+        key(Action::Cut),
+        key(Action::Paste),
+        key(Action::FlipHorizontal),
+        key(Action::FlipVertical)
+    ));
+    hud.line(&format!("Symmetry: {} ({})", status.editing.symmetry.name(), key(Action::Symmetry)));
+    hud.line(&format!(
+        "Zoom: {:.1}x ({} {}, {} resets)",
+        status.zoom,
+        key(Action::ZoomIn),
+        key(Action::ZoomOut),
+        key(Action::ResetView)
+    ));
+    hud.line(&format!("Pan: drag with {} held", key(Action::Pan)));
+    hud.line(&format!(
+        "Spawners: every {} frames ({} {})",
+        status.spawning.interval,
+        key(Action::SpawnSlower),
+        key(Action::SpawnFaster)
+    ));
+    let summary = status.run_stats.summary();
+    hud.line(&format!(
+        "Run: {} trips, arrivals {} ({}), end {}",
+        summary.trips,
+        status.arrival.name(),
+        key(Action::Arrival),
+        key(Action::EndRun)
+    ));
+    hud.line(&format!(
+        "  {:.1} s, {:.0} long, {:.1} s congested",
+        summary.travel_time, summary.path_length, summary.congested_time
+    ));
+    hud.line(&format!(
+        "Level: {}/{} ({} {}), stairs {}, remove top {}",
+        status.shown_level + 1,
+        status.grid.levels,
+        key(Action::LevelDown),
+        key(Action::LevelUp),
+        key(Action::Stairs),
+        key(Action::RemoveLevel)
+    ));
+    hud.line(&format!(
+        "Smoothing: {} ({}), strength {:.2} ({})",
+        if status.smoothing { "on" } else { "off" },
+        key(Action::Smooth),
+        status.smoothing_strength,
+        key(Action::SmoothStrength)
+    ));
+    hud.line(&if status.crowding_feedback {
+        format!("Crowding: on ({}), busiest cell {:.0}", key(Action::Crowding), status.busiest_cell)
+    } else {
+        format!("Crowding: off ({})", key(Action::Crowding))
+    });
+}
+
+pub async fn run(hex: bool, serve_address: Option<String>) {
+    rand::srand(macroquad::miniquad::date::now() as u64);
+
+    // Kept in sync with the grid on every recalculation
+    let mut source_cells = Vec::<(usize, usize)>::new();
+
+    let topology: &'static dyn Topology = if hex {
+        &HexTopology
+    } else {
+        &SquareTopology
+    };
+
+    let grid = &mut Grid::new(CELLS_VERTICAL, CELLS_HORIZONTAL);
+    grid.topology = topology;
+
+    grid.grid[0][0].cell_type = CellType::Barrier;
+
+    let mut editing = Editing {
+        paint_tool: PaintTool::Barrier,
+        one_way_heading: Heading::East,
+        stroke: None,
+        select_mode: false,
+        selection: None,
+        selecting: false,
+        fill_mode: false,
+        filled: false,
+        clipboard: None,
+        symmetry: Symmetry::Off,
+        portal_start: None,
+    };
+    // Cell whose path to the nearest source stays on screen, picked with middle click
+    let mut pinned_path_start: Option<(usize, usize)> = None;
+    // Draws the A* path next to the flow field path, toggled with A
+    let mut astar_comparison = false;
+    let mut agents = Agents::default();
+    let mut spawning = Spawning::new(SPAWN_INTERVAL, SPAWNER_AGENT_CAP);
+    // Trips finished since the last F12, and what arriving agents do, cycled with Insert
+    let mut run_stats = RunStats::default();
+    let mut arrival = Arrival::Stay;
+    let mut obstacles = Vec::<Obstacle>::new();
+    // Details of the hovered cell next to the pointer, toggled with I
+    let mut inspector = true;
+    let mut show_perf = false;
+    let mut perf = PerfOverlay::default();
+    let mut chunks = ChunkRenderer::default();
+    // Render mode and colormap the chunks were built for
+    let mut drawn_look = (RenderMode::Heatmap, 0);
+    // Curves through the field, traced again whenever it changes
+    let mut show_streamlines = false;
+    let mut streamlines = Vec::new();
+    // Waypoints picked with O for the next obstacle, which Enter spawns
+    let mut patrol_route = Vec::<(usize, usize)>::new();
+    let mut danger_weight = 1.0;
+    let mut smoothing = false;
+    let mut smoothing_strength = SMOOTHING_STRENGTHS[1];
+    let mut crowding_feedback = false;
+    // Most agents in one cell, as of the last frame
+    let mut busiest_cell = 0.0;
+    // The text it was parsed from is kept to notice when the file changes
+    let mut cost_script: Option<CostScript> = None;
+    let mut cost_script_text: Option<String> = None;
+    let mut cost_script_checked_at = f64::NEG_INFINITY;
+    // Integrates on the graphics card when it can, toggled with U
+    let mut gpu: Option<GpuSolver> = None;
+    // Whether the GPU can take the grid, checked along with every recalculation
+    let mut gpu_supported = GpuSolver::supports(grid);
+    let mut field_cache = FieldCache::new(FIELD_CACHE_SIZE);
+    let mut server = serve_address.and_then(|address| match Server::bind(&address) {
+        Ok(server) => {
+            println!("Serving on ws://{}", address);
+            Some(server)
+        }
+        Err(error) => {
+            eprintln!("Couldn't serve on {}: {}", address, error);
+            None
+        }
+    });
+    let mut saves = Saves::default();
+    // Falls back to the default bindings if the keys file is missing or broken
+    let keymap = match saves.read(KEYS_PATH) {
+        Ok(text) => Keymap::parse(&text).unwrap_or_else(|error| {
+            eprintln!("Couldn't load key bindings from {}: {}", KEYS_PATH, error);
+            Keymap::default()
+        }),
+        Err(_) => Keymap::default(),
+    };
+    let mut pointer = Pointer::new();
+    // Keeps cells square when fitting the grid to the window, toggled with L
+    let mut letterbox = true;
+    // 1 fits the whole grid. The focus is the point kept in the middle of the view,
+    // as a fraction of the grid size so it stays put when the grid is resized.
+    let mut zoom = 1.0;
+    let mut focus = Vec2::splat(0.5);
+    let mut minimap = Minimap::default();
+    // Grid version and level the minimap was last drawn from, None when it needs redrawing
+    let mut minimap_version: Option<(u64, usize)> = None;
+    // Level on screen, the others are only reached by the stairs
+    let mut shown_level = 0;
+    // Where the pointer was last frame, for dragging the view around
+    let mut last_pointer_position = Vec2::from(mouse_position());
+    let mut render_mode = RenderMode::Heatmap;
+    let mut recorder: Option<Recorder> = None;
+    let mut replay: Option<Replay> = None;
+    let mut flow_field = FlowField::compute(grid, danger_weight);
+    let mut max_distance = largest_distance(grid);
+
+    let mut colormaps = Colormap::builtins();
+    // Not having any extra gradients is fine, a broken file isn't
+    if let Ok(text) = saves.read(GRADIENTS_PATH) {
+        match Colormap::parse_list(&text) {
+            Ok(extra) => colormaps.extend(extra),
+            Err(error) => eprintln!("Couldn't load gradients from {}: {}", GRADIENTS_PATH, error),
+        }
+    }
+    let mut colormap_index = 0;
+    // Integration being stepped through ring by ring, toggled with P
+    let mut propagation: Option<Propagation> = None;
+    #[cfg(feature = "fs")]
+    let mut capture = Capture::default();
+
+    loop {
+        clear_background(WHITE);
+        // Replays and clients can take levels away
+        shown_level = shown_level.min(grid.levels - 1);
+        let (level_origin, world_size) = grid.level_area(shown_level);
+        let mut pointer_actions = pointer.update(get_time(), &keymap);
+
+        // Zooming keeps whatever is under the pointer in place
+        let mut zoom_steps = 0;
+        if keymap.pressed(Action::ZoomIn) {
+            zoom_steps += 1;
+        }
+        if keymap.pressed(Action::ZoomOut) {
+            zoom_steps -= 1;
+        }
+        let wheel = mouse_wheel().1;
+        if wheel != 0.0 {
+            zoom_steps += wheel.signum() as i32;
+        }
+        if zoom_steps != 0 {
+            let before = View::fit(world_size, letterbox, zoom, level_origin + focus * world_size).screen_to_world(pointer_actions.position);
+            zoom = (zoom * ZOOM_STEP.powi(zoom_steps)).clamp(1.0, MAX_ZOOM);
+            let after = View::fit(world_size, letterbox, zoom, level_origin + focus * world_size).screen_to_world(pointer_actions.position);
+            focus += (before - after) / world_size;
+        }
+        if keymap.pressed(Action::ResetView) {
+            zoom = 1.0;
+        }
+        if keymap.down(Action::Pan) && pointer_actions.paint {
+            let view = View::fit(world_size, letterbox, zoom, level_origin + focus * world_size);
+            let moved = view.screen_to_world(pointer_actions.position) - view.screen_to_world(last_pointer_position);
+            focus -= moved / world_size;
+            pointer_actions.paint = false;
+        }
+        // The minimap covers the grid under it, so clicks on it only ever move the view
+        if zoom > 1.0 {
+            if let Some(target) = Minimap::screen_to_world(world_size, pointer_actions.position) {
+                if pointer_actions.paint {
+                    focus = target / world_size;
+                }
+                pointer_actions.paint = false;
+                pointer_actions.place_source = false;
+                pointer_actions.pin_path = false;
+            }
+        }
+        last_pointer_position = pointer_actions.position;
+        // Stops the view from leaving the grid, at zoom 1 that leaves it centered
+        let margin = 0.5 / zoom;
+        focus = focus.clamp(Vec2::splat(margin), Vec2::splat(1.0 - margin));
+
+        let view = View::fit(world_size, letterbox, zoom, level_origin + focus * world_size);
+        set_camera(&view.camera());
+        let pointer_world = view.screen_to_world(pointer_actions.position);
+        let mut grid_recalculation_needed = false;
+
+        let mut edits = map_edits(grid, &keymap);
+
+        if keymap.pressed(Action::Solver) {
+            let next = Solver::ALL
+                .iter()
+                .position(|&solver| solver == grid.solver)
+                .map_or(0, |index| index + 1);
+            grid.solver = Solver::ALL[next % Solver::ALL.len()];
+            grid.mark_changed();
+            grid_recalculation_needed = true;
+        }
+
+        if keymap.pressed(Action::Metric) {
+            let next = Metric::ALL
+                .iter()
+                .position(|&metric| metric == grid.metric)
+                .map_or(0, |index| index + 1);
+            grid.metric = Metric::ALL[next % Metric::ALL.len()];
+            grid.mark_changed();
+            grid_recalculation_needed = true;
+        }
+
+        if keymap.pressed(Action::Gpu) {
+            gpu = match gpu {
+                Some(_) => None,
+                None => GpuSolver::new()
+                    .map_err(|error| eprintln!("Couldn't set up the GPU solver: {}", error))
+                    .ok(),
+            };
+            grid_recalculation_needed = true;
+        }
+
+        // Queued after this frame's other edits, which still refer to the old size
+        let resize = resize_edit(grid, &keymap);
+
+        if keymap.pressed(Action::View) {
+            let next = RenderMode::ALL.iter().position(|&mode| mode == render_mode).map_or(0, |index| index + 1);
+            render_mode = RenderMode::ALL[next % RenderMode::ALL.len()];
+        }
+
+        if keymap.pressed(Action::Gradient) {
+            colormap_index = (colormap_index + 1) % colormaps.len();
+        }
+
+        if keymap.pressed(Action::StepMode) {
+            propagation = match propagation {
+                Some(_) => None,
+                // Seeded from the sources with the recalculation below
+                None => Some(Propagation::new(grid, &[], grid.solver)),
+            };
+            grid_recalculation_needed = true;
+        }
+
+        if keymap.pressed(Action::Letterbox) {
+            letterbox = !letterbox;
+        }
+
+        if keymap.pressed(Action::ClearAgents) {
+            agents.clear();
+        }
+
+        if keymap.pressed(Action::Astar) {
+            astar_comparison = !astar_comparison;
+        }
+
+        if keymap.pressed(Action::Inspector) {
+            inspector = !inspector;
+        }
+
+        if keymap.pressed(Action::Perf) {
+            show_perf = !show_perf;
+        }
+        perf.record_frame(get_frame_time());
+
+        if keymap.pressed(Action::Streamlines) {
+            show_streamlines = !show_streamlines;
+            streamlines.clear();
+        }
+
+        if keymap.pressed(Action::SpawnObstacle) {
+            match Obstacle::new(grid, std::mem::take(&mut patrol_route), OBSTACLE_SPEED) {
+                Some(spawned) => {
+                    chunks.mark_dirty(spawned.cell());
+                    obstacles.push(spawned);
+                    grid_recalculation_needed = true;
+                }
+                None => eprintln!("Obstacles need a route starting on an empty cell"),
+            }
+        }
+        if keymap.pressed(Action::ClearObstacles) {
+            for removed in obstacles.drain(..) {
+                chunks.mark_dirty(removed.remove(grid));
+            }
+            patrol_route.clear();
+            grid_recalculation_needed = true;
+        }
+
+        // The field is rebuilt from its raw directions (usually out of the cache) to be
+        // smoothed again
+        if keymap.pressed(Action::Smooth) {
+            smoothing = !smoothing;
+            grid_recalculation_needed = true;
+        }
+        if keymap.pressed(Action::SmoothStrength) {
+            let next = SMOOTHING_STRENGTHS
+                .iter()
+                .position(|&strength| strength == smoothing_strength)
+                .map_or(0, |index| index + 1);
+            smoothing_strength = SMOOTHING_STRENGTHS[next % SMOOTHING_STRENGTHS.len()];
+            grid_recalculation_needed |= smoothing;
+        }
+
+        // Turning it off goes back to the field without crowding, which is still cached
+        if keymap.pressed(Action::Crowding) {
+            crowding_feedback = !crowding_feedback;
+            if !crowding_feedback {
+                grid.extra_costs = None;
+                grid_recalculation_needed = true;
+            }
+        }
+
+        if keymap.pressed(Action::DangerDown) {
+            danger_weight = f32::max(danger_weight - DANGER_WEIGHT_STEP, 0.0);
+            grid_recalculation_needed = true;
+        }
+        if keymap.pressed(Action::DangerUp) {
+            danger_weight += DANGER_WEIGHT_STEP;
+            grid_recalculation_needed = true;
+        }
+        
+        let mouse_cell = grid.get_cell_at(pointer_world).filter(|&cell| grid.level_of(cell) == shown_level);
+
+        edits.extend(editing.edits(grid, &keymap, &pointer_actions, mouse_cell, shown_level));
+
+        if keymap.pressed(Action::LevelUp) {
+            if shown_level + 1 < grid.levels {
+                shown_level += 1;
+            } else if (grid.levels + 1) * grid.level_rows() <= MAX_GRID_SIZE {
+                edits.push(Edit::SetLevels(grid.levels + 1));
+                shown_level += 1;
+            }
+        }
+        if keymap.pressed(Action::LevelDown) {
+            shown_level = shown_level.saturating_sub(1);
+        }
+        // Always the top one, whatever level is shown
+        if keymap.pressed(Action::RemoveLevel) && grid.levels > 1 {
+            edits.push(Edit::SetLevels(grid.levels - 1));
+        }
+
+        if keymap.pressed(Action::Record) {
+            match recorder.take() {
+                Some(finished) => {
+                    if let Err(error) = saves.write(RECORDING_PATH, finished.to_text()) {
+                        eprintln!("Couldn't save recording to {}: {}", RECORDING_PATH, error);
+                    }
+                }
+                None => recorder = Some(Recorder::start(grid, get_time())),
+            }
+        }
+
+        // Replay plays the last recording in real time, replay step goes through it one
+        // edit at a time
+        let replay_action = [Action::Replay, Action::ReplayStep].into_iter().find(|&action| keymap.pressed(action));
+        if replay_action.is_some() && (replay_action == Some(Action::Replay) || replay.is_none()) {
+            match saves.read(RECORDING_PATH).and_then(|text| Replay::parse(&text, get_time())) {
+                Ok(loaded) => {
+                    // Recordings start with a clear, so the map rebuilds itself from here
+                    agents.clear();
+                    pinned_path_start = None;
+                    replay = Some(loaded);
+                }
+                Err(error) => eprintln!("Couldn't load recording from {}: {}", RECORDING_PATH, error),
+            }
+        }
+        if let Some(active_replay) = &mut replay {
+            if replay_action == Some(Action::ReplayStep) {
+                edits.extend(active_replay.step());
+            } else {
+                edits.extend(active_replay.due_edits(get_time()));
+            }
+            if active_replay.is_finished() {
+                replay = None;
+            }
+        }
+
+        if keymap.pressed(Action::SaveMap) {
+            if let Err(error) = saves.write(MAP_PATH, map_file::format_map(grid)) {
+                eprintln!("Couldn't save map to {}: {}", MAP_PATH, error);
+            }
+        }
+        if keymap.pressed(Action::LoadMap) {
+            match saves.read(MAP_PATH).and_then(|text| map_file::parse_map(&text)) {
+                Ok(mut loaded) => {
+                    // The obstacles' cells are gone with the old map
+                    obstacles.clear();
+                    patrol_route.clear();
+                    editing.portal_start = None;
+                    editing.selection = None;
+                    loaded.topology = topology;
+                    loaded.solver = grid.solver;
+                    loaded.metric = grid.metric;
+                    loaded.wrap = grid.wrap;
+                    *grid = loaded;
+                    chunks.invalidate();
+                    if let Some(recorder) = &mut recorder {
+                        recorder.record_snapshot(grid, get_time());
+                    }
+                    agents.clear();
+                    pinned_path_start = None;
+                    grid_recalculation_needed = true;
+                }
+                Err(error) => eprintln!("Couldn't load map from {}: {}", MAP_PATH, error),
+            }
+        }
+
+        // Goes in as edits so it's recorded and sent to clients like painting
+        #[cfg(feature = "fs")]
+        if keymap.pressed(Action::LoadHeightmap) {
+            match load_heightmap(HEIGHTMAP_PATH) {
+                Ok(image) => edits.extend(map_file::heightmap_edits(grid, image.width(), image.height(), &image.bytes)),
+                Err(error) => eprintln!("Couldn't load heightmap from {}: {}", HEIGHTMAP_PATH, error),
+            }
+        }
+
+        // Anything clients ask for goes in with this frame's edits, if it fits the grid
+        let mut clients_joined = false;
+        if let Some(server) = &mut server {
+            let incoming = server.poll();
+            for error in incoming.errors {
+                eprintln!("Ignoring a message from a client: {}", error);
+            }
+            edits.extend(incoming.edits.into_iter().map(|edit| match edit {
+                Edit::Resize { row_count, column_count } => Edit::Resize {
+                    row_count: row_count.clamp(MIN_GRID_SIZE, MAX_GRID_SIZE),
+                    column_count: column_count.clamp(MIN_GRID_SIZE, MAX_GRID_SIZE),
+                },
+                Edit::SetLevels(levels) => Edit::SetLevels(levels.min(MAX_GRID_SIZE / grid.level_rows())),
+                edit => edit,
+            }));
+            clients_joined = incoming.joined > 0;
+        }
+
+        edits.extend(resize);
+        let mut applied = Vec::with_capacity(edits.len());
+        for edit in edits {
+            // Checked as they go, an earlier edit may have shrunk the grid
+            if !server::fits(grid, edit) {
+                continue;
+            }
+            applied.push(edit);
+            if let Some(recorder) = &mut recorder {
+                recorder.record(edit, get_time());
+            }
+            if let Edit::Generate { .. } | Edit::Clear = edit {
+                pinned_path_start = None;
+                agents.clear();
+            }
+            // Obstacles don't survive map wide edits, their cells go back to normal first
+            if let Edit::Generate { .. } | Edit::Clear | Edit::Resize { .. } | Edit::SetLevels(_) | Edit::Reshape { .. } = edit {
+                for removed in obstacles.drain(..) {
+                    removed.remove(grid);
+                }
+                patrol_route.clear();
+                editing.portal_start = None;
+                editing.selection = None;
+            }
+            grid.apply_edit(edit);
+            match edit {
+                Edit::Paint(_, cell) | Edit::ToggleSource(cell) | Edit::SetCell(cell, _) | Edit::SetElevation(cell, _) => {
+                    chunks.mark_dirty(cell)
+                }
+                Edit::TogglePortal(..) => {}
+                _ => chunks.invalidate(),
+            }
+            if let Edit::Resize { .. } | Edit::SetLevels(_) | Edit::Reshape { .. } = edit {
+                editing.stroke = None;
+                pinned_path_start = pinned_path_start
+                    .filter(|&(col_x, row_y)| col_x < grid.column_count_x && row_y < grid.row_count_y);
+                agents.retain(|agents, index| grid.get_cell_at(agents.positions()[index]).is_some());
+            }
+            grid_recalculation_needed = true;
+        }
+        if let Some(server) = server.as_mut().filter(|_| !applied.is_empty()) {
+            server.broadcast(&server::edits_message(&applied));
+        }
+
+        // A broken script is reported and the terrain costs are used until it's fixed
+        if get_time() - cost_script_checked_at >= COST_SCRIPT_POLL_INTERVAL {
+            cost_script_checked_at = get_time();
+            let text = saves.read(COSTS_PATH).ok();
+            if text != cost_script_text {
+                cost_script = match text.as_deref().map(CostScript::parse) {
+                    Some(Ok(script)) => Some(script),
+                    Some(Err(error)) => {
+                        eprintln!("Couldn't load costs from {}: {}", COSTS_PATH, error);
+                        None
+                    }
+                    None => None,
+                };
+                cost_script_text = text;
+                grid.mark_changed();
+                grid_recalculation_needed = true;
+            }
+        }
+
+        let mut field_changed = false;
+        // Obstacles hold still while stepping through an integration. Their moves are
+        // patched into the field unless it's being rebuilt anyway.
+        if propagation.is_none() {
+            let mut obstacle_changes = Vec::new();
+            for obstacle in &mut obstacles {
+                obstacle_changes.extend(obstacle.update(grid, get_frame_time()));
+            }
+            // A script can price cells by how far they are from barriers, so moving
+            // obstacles changes costs away from them too. Those cells need repairing as well.
+            if let (false, Some(script)) = (obstacle_changes.is_empty(), &cost_script) {
+                let costs = script.costs(grid);
+                if let Some(old_costs) = &grid.cell_costs {
+                    for (row_y, (row, old_row)) in costs.iter().zip(old_costs).enumerate() {
+                        for (col_x, (cost, old_cost)) in row.iter().zip(old_row).enumerate() {
+                            if cost != old_cost && !obstacle_changes.contains(&(col_x, row_y)) {
+                                obstacle_changes.push((col_x, row_y));
+                            }
+                        }
+                    }
+                }
+                grid.cell_costs = Some(costs);
+            }
+            for &changed in &obstacle_changes {
+                chunks.mark_dirty(changed);
+            }
+            // Fields from the GPU can't be repaired, they're recomputed there instead
+            if !obstacle_changes.is_empty() && gpu.is_some() {
+                grid_recalculation_needed = true;
+            } else if !obstacle_changes.is_empty() && !grid_recalculation_needed {
+                let timings = flow_field.update_timed(grid, &obstacle_changes, danger_weight, get_time);
+                perf.record_field(FieldWork::Repaired, timings);
+                max_distance = largest_distance(grid);
+                field_changed = true;
+            }
+        }
+
+        let step_pressed = propagation.is_some() && keymap.pressed(Action::StepRing);
+        if grid_recalculation_needed || step_pressed {
+            grid.cell_costs = cost_script.as_ref().map(|script| script.costs(grid));
+            source_cells = grid.get_coordinates_of_type(CellType::Source);
+            gpu_supported = GpuSolver::supports(grid);
+            match &mut propagation {
+                // Any change starts the propagation over from the sources
+                Some(active) => {
+                    if grid_recalculation_needed {
+                        *active = Propagation::new(grid, &source_cells, grid.solver);
+                    }
+                    if step_pressed {
+                        active.step_ring(grid);
+                    }
+                    show_propagation(grid, active);
+                    flow_field = FlowField::from_grid(grid, danger_weight);
+                }
+                None => match gpu.as_mut().and_then(|gpu| gpu.compute(grid, danger_weight)) {
+                    Some((computed, timings)) => {
+                        flow_field = computed;
+                        perf.record_field(FieldWork::Gpu, timings);
+                        set_camera(&view.camera());
+                    }
+                    // Crowding isn't part of the cache's key, so crowded fields aren't cached
+                    None if grid.extra_costs.is_some() => {
+                        let (computed, timings) = FlowField::compute_timed(grid, danger_weight, get_time);
+                        flow_field = computed;
+                        perf.record_field(FieldWork::Computed, timings);
+                    }
+                    None => {
+                        let (computed, timings) = field_cache.get_or_compute_timed(grid, danger_weight, get_time);
+                        flow_field = computed.clone();
+                        match timings {
+                            Some(timings) => perf.record_field(FieldWork::Computed, timings),
+                            None => perf.record_field(FieldWork::Cached, Default::default()),
+                        }
+                    }
+                },
+            }
+            max_distance = largest_distance(grid);
+            field_changed = true;
+        }
+        // Agents on their way make the cells they're in cost more, so the ones behind
+        // them spread out over other routes. The field is only repaired around the cells
+        // whose crowd changed, and not at all while stepping.
+        if crowding_feedback && propagation.is_none() {
+            let walking: Vec<Vec2> = agents
+                .positions()
+                .iter()
+                .zip(agents.arrived())
+                .filter(|(_, &arrived)| !arrived)
+                .map(|(&position, _)| position)
+                .collect();
+            let crowding = FieldLayer::crowding(grid, &walking);
+            busiest_cell = crowding.peak();
+            let crowding = crowding.with_weight(CROWDING_WEIGHT);
+            if let Some(timings) = flow_field.update_crowding(grid, &crowding, danger_weight, get_time) {
+                perf.record_field(FieldWork::Repaired, timings);
+                max_distance = largest_distance(grid);
+                field_changed = true;
+            }
+        }
+        if field_changed && smoothing {
+            flow_field.smooth(grid, smoothing_strength);
+        }
+        // Newcomers get the whole picture, everyone else only when it changes
+        if let Some(server) = server.as_mut().filter(|_| field_changed || clients_joined) {
+            server.broadcast(&server::field_message(grid, &flow_field));
+        }
+        if show_streamlines && (field_changed || streamlines.is_empty()) {
+            streamlines = streamline::trace_all(grid, &flow_field, STREAMLINE_SPACING);
+        }
+
+        let drawing_started = get_time();
+        // Heatmap colors and arrows come from the field, so a new one means new meshes
+        if field_changed && (render_mode.heatmap() || render_mode.arrows()) {
+            chunks.invalidate();
+        }
+        if drawn_look != (render_mode, colormap_index) {
+            drawn_look = (render_mode, colormap_index);
+            chunks.invalidate();
+        }
+        let visible = view.visible_area();
+        let cell_pixels = CELL_SIZE * view.pixels_per_unit();
+        let heatmap = Some((&colormaps[colormap_index], max_distance)).filter(|_| render_mode.heatmap());
+        chunks.draw(
+            grid,
+            shown_level,
+            visible,
+            cell_pixels,
+            |cell| {
+                let color = cell_color(cell, heatmap);
+                if render_mode != RenderMode::Hillshade {
+                    return color;
+                }
+                let shade = hillshade(grid, (cell.x_position, cell.y_position));
+                Color::new(
+                    (color.r * shade).min(1.0),
+                    (color.g * shade).min(1.0),
+                    (color.b * shade).min(1.0),
+                    color.a,
+                )
+            },
+            |cell| render_mode.arrows().then(|| flow_field.direction(cell.x_position, cell.y_position)),
+        );
+        if let Some(hovered) = mouse_cell {
+            fill_cell(grid, hovered, Color::new(0.0, 0.0, 0.0, 1.0 - HIGHLIGHT_DIM_AMOUNT));
+        }
+        // Text can't be batched, so it's only drawn on screen and big enough to read
+        if render_mode == RenderMode::Numbers && cell_pixels >= MIN_NUMBER_PIXELS {
+            for (col_x, row_y) in chunks.visible_cells(grid, shown_level, visible) {
+                let cell_center = topology.cell_center((col_x, row_y));
+                draw_text(
+                    &format!("{:.0}", grid.grid[row_y][col_x].cell_number.unwrap_or(0.0)),
+                    cell_center.x - CELL_SIZE / 2.0,
+                    cell_center.y,
+                    25.0,
+                    BLACK,
+                );
+            }
+        }
+
+        if show_streamlines {
+            draw_streamlines(&streamlines);
+        }
+
+        for (from, to) in grid.portal_pairs() {
+            // Stairs get steps drawn on the end that's on this level
+            if grid.level_of(from) != grid.level_of(to) {
+                for end in [from, to].into_iter().filter(|&end| grid.level_of(end) == shown_level) {
+                    draw_stairs(topology.cell_center(end));
+                }
+                continue;
+            }
+            if grid.level_of(from) != shown_level {
+                continue;
+            }
+            let (from, to) = (topology.cell_center(from), topology.cell_center(to));
+            draw_line(from.x, from.y, to.x, to.y, 1.0, VIOLET);
+            for center in [from, to] {
+                draw_circle_lines(center.x, center.y, CELL_SIZE * 0.35, 3.0, VIOLET);
+            }
+        }
+        if let Some(start) = editing.portal_start.filter(|&start| grid.level_of(start) == shown_level) {
+            let center = topology.cell_center(start);
+            draw_circle_lines(center.x, center.y, CELL_SIZE * 0.35, 2.0, PINK);
+        }
+
+        // Mirror lines through the middle of the level
+        let (top, bottom) = (level_origin.y, level_origin.y + world_size.y);
+        let center = level_origin + world_size / 2.0;
+        if matches!(editing.symmetry, Symmetry::MirrorX | Symmetry::Rotate4) {
+            draw_line(center.x, top, center.x, bottom, 2.0, SKYBLUE);
+        }
+        if matches!(editing.symmetry, Symmetry::MirrorY | Symmetry::Rotate4) {
+            draw_line(0.0, center.y, world_size.x, center.y, 2.0, SKYBLUE);
+        }
+
+        if let Some((corner, opposite)) = editing.selection {
+            for cell in region::cells_between(corner, opposite) {
+                fill_cell(grid, cell, Color::new(0.0, 0.5, 1.0, 0.25));
+            }
+        }
+        // Where a paste would land
+        if let (true, Some(copied), Some((col_x, row_y))) = (editing.select_mode, &editing.clipboard, mouse_cell) {
+            let far_corner = (
+                (col_x + copied.column_count() - 1).min(grid.column_count_x - 1),
+                (row_y + copied.row_count() - 1).min((shown_level + 1) * grid.level_rows() - 1),
+            );
+            for cell in region::cells_between((col_x, row_y), far_corner) {
+                fill_cell(grid, cell, Color::new(1.0, 0.6, 0.0, 0.25));
+            }
+        }
+
+        for obstacle in &obstacles {
+            let center = topology.cell_center(obstacle.cell());
+            draw_circle(center.x, center.y, CELL_SIZE * 0.35, ORANGE);
+        }
+        for (index, &waypoint) in patrol_route.iter().enumerate() {
+            let center = topology.cell_center(waypoint);
+            draw_circle_lines(center.x, center.y, CELL_SIZE * 0.25, 2.0, ORANGE);
+            if let Some(&previous) = index.checked_sub(1).and_then(|previous| patrol_route.get(previous)) {
+                let from = topology.cell_center(previous);
+                draw_line(from.x, from.y, center.x, center.y, 2.0, ORANGE);
+            }
+        }
+
+        if keymap.pressed(Action::AddWaypoint) {
+            if let Some(hovered) = mouse_cell {
+                if obstacle::can_occupy(grid, hovered) || !patrol_route.is_empty() {
+                    patrol_route.push(hovered);
+                }
+            }
+        }
+
+        if keymap.pressed(Action::SpawnAgents) {
+            if let Some((col_x, row_y)) = mouse_cell {
+                if grid.grid[row_y][col_x].cell_type != CellType::Barrier {
+                    for _ in 0..AGENT_SPAWN_BATCH {
+                        let jitter = vec2(rand::gen_range(-0.3, 0.3), rand::gen_range(-0.3, 0.3)) * CELL_SIZE;
+                        agents.spawn(topology.cell_center((col_x, row_y)) + jitter);
+                    }
+                }
+            }
+        }
+
+        if keymap.pressed(Action::SpawnSlower) {
+            spawning.interval = (spawning.interval * 2).min(MAX_SPAWN_INTERVAL);
+        }
+        if keymap.pressed(Action::SpawnFaster) {
+            spawning.interval = (spawning.interval / 2).max(1);
+        }
+        spawning.update(grid, &mut agents);
+
+        if keymap.pressed(Action::Arrival) {
+            let next = Arrival::ALL.iter().position(|&mode| mode == arrival).map_or(0, |index| index + 1);
+            arrival = Arrival::ALL[next % Arrival::ALL.len()];
+        }
+        agent::update_agents(&mut agents, &flow_field, grid, get_frame_time());
+        run_stats.update(&mut agents, grid, arrival, get_frame_time());
+        if keymap.pressed(Action::EndRun) {
+            let summary = run_stats.summary();
+            println!(
+                "Run over: {} trips, {:.2} s on average ({:.2} s longest), {:.1} long, {:.2} s congested",
+                summary.trips,
+                summary.travel_time,
+                summary.longest_travel_time,
+                summary.path_length,
+                summary.congested_time
+            );
+            if let Err(error) = saves.write(RUN_STATS_PATH, run_stats.to_csv()) {
+                eprintln!("Couldn't write the run to {}: {}", RUN_STATS_PATH, error);
+            }
+            run_stats = RunStats::default();
+        }
+        let on_shown_level = |position: &&Vec2| grid.get_cell_at(**position).is_some_and(|cell| grid.level_of(cell) == shown_level);
+        for position in agents.positions().iter().filter(on_shown_level) {
+            draw_circle(position.x, position.y, AGENT_RADIUS, DARKPURPLE);
+        }
+
+        if pointer_actions.pin_path {
+            pinned_path_start = match (pinned_path_start, mouse_cell) {
+                (Some(pinned), Some(hovered)) if pinned == hovered => None,
+                (_, hovered) => hovered,
+            };
+        }
+
+        // Holding shift previews the path from the hovered cell
+        let preview_start = if keymap.down(Action::PreviewPath) {
+            mouse_cell
+        } else {
+            None
+        };
+
+        for path_start in [pinned_path_start, preview_start].into_iter().flatten() {
+            draw_path(grid, &flow_field.trace_path(path_start), 0.0, YELLOW);
+        }
+
+        // Drawn with the rest of the side panel once the world is done
+        let mut astar_lines = Vec::new();
+        if astar_comparison {
+            match pinned_path_start.or(preview_start).or(mouse_cell) {
+                Some(start) => {
+                    let flow_path = flow_field.trace_path(start);
+                    draw_path(grid, &flow_path, 0.0, YELLOW);
+
+                    match astar::find_path(grid, start, &source_cells) {
+                        Some(astar_path) => {
+                            // Nudged so overlapping stretches of both paths stay visible
+                            draw_path(grid, &astar_path, 4.0, SKYBLUE);
+
+                            let flow_cost = grid.path_cost(&flow_path);
+                            let astar_cost = grid.path_cost(&astar_path);
+                            let reached_source = flow_path.last().is_some_and(|end| source_cells.contains(end));
+                            let lines = [
+                                format!("Flow:  {} steps, cost {:.1}", flow_path.len() - 1, flow_cost),
+                                format!("A*:    {} steps, cost {:.1}", astar_path.len() - 1, astar_cost),
+                                if !reached_source {
+                                    "Flow path misses the source".to_string()
+                                } else if astar_cost == 0.0 {
+                                    "Extra cost: 0.0%".to_string()
+                                } else {
+                                    format!(
+                                        "Extra cost: {:.1}%",
+                                        (flow_cost - astar_cost) / astar_cost * 100.0
+                                    )
+                                },
+                            ];
+                            astar_lines.extend(lines);
+                        }
+                        None => astar_lines.push("A*: no path".to_string()),
+                    }
+                }
+                None => astar_lines.push("A*: hover a cell".to_string()),
+            }
+        }
+
+        // Show the interpolated flow direction under the cursor
+        let sampled_direction = flow_field.sample(pointer_world);
+        if sampled_direction != Vec2::ZERO {
+            let arrow_end = pointer_world + sampled_direction * CELL_SIZE;
+            draw_line(pointer_world.x, pointer_world.y, arrow_end.x, arrow_end.y, 2.0, MAGENTA);
+            draw_circle(arrow_end.x, arrow_end.y, 3.0, MAGENTA);
+        }
+
+        perf.record_drawing(get_time() - drawing_started);
+
+        // Minimap and side panel, in screen space
+        set_default_camera();
+        // The cells only change along with the version or the field (sources don't bump the version)
+        if field_changed || minimap_version != Some((grid.version(), shown_level)) {
+            minimap_version = None;
+        }
+        if zoom > 1.0 {
+            if minimap_version.is_none() {
+                minimap.update(grid, shown_level, |cell| cell_color(cell, None));
+                minimap_version = Some((grid.version(), shown_level));
+            }
+            minimap.draw(level_origin, world_size, &view);
+        }
+        let goal_stats = agent::goal_stats(&agents);
+        draw_hud(&HudStatus {
+            keymap: &keymap,
+            grid,
+            editing: &editing,
+            agents: &agents,
+            goal_stats: &goal_stats,
+            astar_comparison,
+            astar_lines: &astar_lines,
+            danger_weight,
+            recorder: recorder.as_ref(),
+            replay: replay.as_ref(),
+            scripted_costs: cost_script.is_some(),
+            gpu_on: gpu.is_some(),
+            gpu_supported,
+            render_mode,
+            show_streamlines,
+            colormap: &colormaps[colormap_index],
+            #[cfg(feature = "fs")]
+            capture: &capture,
+            propagation: propagation.as_ref(),
+            obstacle_count: obstacles.len(),
+            patrol_route: &patrol_route,
+            letterbox,
+            inspector,
+            show_perf,
+            zoom,
+            spawning: &spawning,
+            run_stats: &run_stats,
+            arrival,
+            shown_level,
+            smoothing,
+            smoothing_strength,
+            crowding_feedback,
+            busiest_cell,
+        });
+
+        if let Some(hovered) = mouse_cell.filter(|_| inspector) {
+            let agents_here = agents
+                .positions()
+                .iter()
+                .filter(|&&position| grid.get_cell_at(position) == Some(hovered))
+                .count();
+            let lines = inspector_lines(grid, &flow_field, &goal_stats, agents_here, hovered);
+            let (width, height) = (230.0, lines.len() as f32 * 18.0 + 8.0);
+            // Next to the pointer, flipped to the other side near the window edges
+            let mut corner = pointer_actions.position + vec2(16.0, 16.0);
+            if corner.x + width > screen_width() {
+                corner.x -= width + 32.0;
+            }
+            if corner.y + height > screen_height() {
+                corner.y -= height + 32.0;
+            }
+            draw_rectangle(corner.x, corner.y, width, height, Color::new(1.0, 1.0, 1.0, 0.85));
+            draw_rectangle_lines(corner.x, corner.y, width, height, 1.0, DARKGRAY);
+            for (index, line) in lines.iter().enumerate() {
+                draw_text(line, corner.x + 6.0, corner.y + 18.0 + index as f32 * 18.0, 18.0, BLACK);
+            }
+        }
+        if show_perf {
+            perf.draw(grid.column_count_x * grid.row_count_y, agents.len());
+        }
+
+        // Screenshots, GIFs and field exports need a filesystem to go to
+        #[cfg(feature = "fs")]
+        {
+            // Taken before anything from this frame's capture keys shows up on screen
+            let mut finished = capture.capture_frame(get_frame_time());
+            if keymap.pressed(Action::Screenshot) {
+                match capture.screenshot() {
+                    Ok(path) => println!("Saved screenshot to {}", path),
+                    Err(error) => eprintln!("Couldn't save screenshot: {}", error),
+                }
+            }
+            if keymap.pressed(Action::ExportField) {
+                for format in Format::ALL {
+                    let path = format!("field.{}", format.extension());
+                    match std::fs::write(&path, export::export(grid, &flow_field, format)) {
+                        Ok(()) => println!("Exported the field to {}", path),
+                        Err(error) => eprintln!("Couldn't export the field to {}: {}", path, error),
+                    }
+                }
+            }
+            if keymap.pressed(Action::CaptureGif) {
+                if capture.is_capturing() {
+                    finished = capture.finish_gif();
+                } else {
+                    capture.start_gif();
+                }
+            }
+            match finished {
+                Some(Ok(path)) => println!("Saved capture to {}", path),
+                Some(Err(error)) => eprintln!("Couldn't save capture: {}", error),
+                None => {}
+            }
+        }
+
+        next_frame().await;
+    }
+}
\ No newline at end of file
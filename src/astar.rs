@@ -0,0 +1,119 @@
+use std::collections::BinaryHeap;
+
+use crate::{solver::Candidate, CellType, Grid};
+
+// A* over the same costs as the integration pass: stepping onto a cell costs its
+// terrain cost and barriers can't be entered. With several goals it finds the path to
+// the cheapest one, which is the source the flow field should be leading to as well.
+pub fn find_path(grid: &Grid, start: (usize, usize), goals: &[(usize, usize)]) -> Option<Vec<(usize, usize)>> {
+    if goals.is_empty() {
+        return None;
+    }
+
+    // Shortest way to the closest goal times the cheapest terrain never overestimates.
+    // Portals can shortcut anywhere though, so with any on the map it's plain Dijkstra.
+    let cheapest_step = grid.cheapest_step_cost();
+    let has_portals = grid.grid.iter().flatten().any(|cell| cell.portal.is_some());
+    let heuristic = |cell: (usize, usize)| {
+        if has_portals {
+            return 0.0;
+        }
+        goals
+            .iter()
+            .map(|&goal| grid.distance(cell, goal) * cheapest_step)
+            .min_by(f32::total_cmp)
+            .unwrap_or(0.0)
+    };
+
+    let mut best_costs = vec![vec![None; grid.column_count_x]; grid.row_count_y];
+    let mut came_from = vec![vec![None; grid.column_count_x]; grid.row_count_y];
+    let mut frontier = BinaryHeap::new();
+
+    best_costs[start.1][start.0] = Some(0.0);
+    frontier.push(Candidate {
+        priority: heuristic(start),
+        item: (0.0, start.0, start.1),
+    });
+
+    while let Some(Candidate { item: (cost, col_x, row_y), .. }) = frontier.pop() {
+        if best_costs[row_y][col_x].is_some_and(|best| cost > best) {
+            continue;
+        }
+
+        if goals.contains(&(col_x, row_y)) {
+            let mut current = (col_x, row_y);
+            let mut path = vec![current];
+            while let Some(previous) = came_from[current.1][current.0] {
+                path.push(previous);
+                current = previous;
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        let cell = &grid.grid[row_y][col_x];
+        for (neighbor_x, neighbor_y) in grid.get_step_neighbors(cell) {
+            let neighbor = &grid.grid[neighbor_y][neighbor_x];
+            if neighbor.cell_type == CellType::Barrier {
+                continue;
+            }
+
+            let new_cost = cost + grid.move_cost((col_x, row_y), (neighbor_x, neighbor_y));
+            if best_costs[neighbor_y][neighbor_x].is_none_or(|best| new_cost < best) {
+                best_costs[neighbor_y][neighbor_x] = Some(new_cost);
+                came_from[neighbor_y][neighbor_x] = Some((col_x, row_y));
+                frontier.push(Candidate {
+                    priority: new_cost + heuristic((neighbor_x, neighbor_y)),
+                    item: (new_cost, neighbor_x, neighbor_y),
+                });
+            }
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{flow_field::FlowField, map_file, metric::Metric};
+
+    fn path_cost(grid: &Grid, path: &[(usize, usize)]) -> f32 {
+        path.windows(2).map(|step| grid.move_cost(step[0], step[1])).sum()
+    }
+
+    #[test]
+    fn matches_the_path_the_field_leads_along() {
+        let text = "S....=....\n..~~.=.%%.\n.....=....\n####.###=#\n.....=....\n.%%..=.~~.\n.....=...S";
+        for metric in Metric::ALL {
+            let mut grid = map_file::parse_map(text).unwrap();
+            grid.metric = metric;
+            let flow_field = FlowField::compute(&mut grid, 0.0);
+            let sources = grid.get_coordinates_of_type(CellType::Source);
+            for start in [(0, 6), (9, 0), (4, 4), (2, 1)] {
+                let field_path = flow_field.trace_path(start);
+                let astar_path = find_path(&grid, start, &sources).unwrap();
+                assert_eq!(field_path.first(), astar_path.first());
+                assert!(sources.contains(field_path.last().unwrap()));
+                assert!(sources.contains(astar_path.last().unwrap()));
+                let (field_cost, astar_cost) = (path_cost(&grid, &field_path), path_cost(&grid, &astar_path));
+                assert!(
+                    (field_cost - astar_cost).abs() < 1e-4,
+                    "{:?} from {:?}: {} vs {}",
+                    metric,
+                    start,
+                    field_cost,
+                    astar_cost
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn no_path_past_barriers() {
+        let grid = map_file::parse_map("S#.").unwrap();
+        assert_eq!(find_path(&grid, (2, 0), &[(0, 0)]), None);
+        assert_eq!(find_path(&grid, (2, 0), &[]), None);
+        assert_eq!(find_path(&grid, (0, 0), &[(0, 0)]), Some(vec![(0, 0)]));
+    }
+}
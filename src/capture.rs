@@ -0,0 +1,100 @@
+use std::path::Path;
+
+use macroquad::prelude::*;
+
+use flowfields::{gif::GifEncoder, png};
+
+// Captures stop on their own after this many frames, 10 seconds at 60 fps
+const MAX_GIF_FRAMES: usize = 600;
+
+// Screenshots (F9) and GIF captures (F10) of the whole window, written next to the
+// binary with the first free number in their name
+#[derive(Debug, Default)]
+pub struct Capture {
+    gif: Option<GifCapture>,
+}
+
+#[derive(Debug, Default)]
+struct GifCapture {
+    // Made on the first frame, once the size of the screen data is known
+    encoder: Option<GifEncoder>,
+    frame_count: usize,
+    // Frame time not yet handed out as delay, GIF delays only count hundredths
+    pending_delay: f32,
+}
+
+impl Capture {
+    pub fn screenshot(&self) -> Result<String, String> {
+        let (width, height, rgba) = screen_pixels();
+        let path = free_path("screenshot", "png");
+        std::fs::write(&path, png::encode_rgba(width as u32, height as u32, &rgba))
+            .map_err(|error| format!("{}: {}", path, error))?;
+        Ok(path)
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.gif.is_some()
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.gif.as_ref().map_or(0, |gif| gif.frame_count)
+    }
+
+    pub fn start_gif(&mut self) {
+        self.gif = Some(GifCapture::default());
+    }
+
+    // Call once the frame is fully drawn. Returns the path once the capture hits its
+    // frame limit and gets written out.
+    pub fn capture_frame(&mut self, frame_time: f32) -> Option<Result<String, String>> {
+        let gif = self.gif.as_mut()?;
+        let (width, height, rgba) = screen_pixels();
+        let encoder = gif.encoder.get_or_insert_with(|| GifEncoder::new(width, height));
+        // The window was resized mid capture, a GIF can't change size so stop here
+        if (width, height) != (encoder.width(), encoder.height()) {
+            return self.finish_gif();
+        }
+
+        gif.pending_delay += frame_time * 100.0;
+        let delay = gif.pending_delay.round().max(1.0);
+        gif.pending_delay -= delay;
+        encoder.add_frame(&rgba, delay as u16);
+        gif.frame_count += 1;
+
+        if gif.frame_count >= MAX_GIF_FRAMES {
+            return self.finish_gif();
+        }
+        None
+    }
+
+    pub fn finish_gif(&mut self) -> Option<Result<String, String>> {
+        let Some(encoder) = self.gif.take()?.encoder else {
+            return Some(Err("no frames were captured".to_string()));
+        };
+        let path = free_path("capture", "gif");
+        Some(
+            std::fs::write(&path, encoder.finish())
+                .map(|_| path.clone())
+                .map_err(|error| format!("{}: {}", path, error)),
+        )
+    }
+}
+
+// The window's pixels, top row first
+fn screen_pixels() -> (u16, u16, Vec<u8>) {
+    let image = get_screen_data();
+    let row_length = image.width as usize * 4;
+    // The screen comes back bottom row first, and its alpha isn't meaningful
+    let mut rgba: Vec<u8> = image.bytes.chunks_exact(row_length).rev().flatten().copied().collect();
+    for pixel in rgba.chunks_exact_mut(4) {
+        pixel[3] = 255;
+    }
+    (image.width, image.height, rgba)
+}
+
+fn free_path(name: &str, extension: &str) -> String {
+    (1..)
+        .map(|number| format!("{}-{}.{}", name, number, extension))
+        .find(|path| !Path::new(path).exists())
+        .unwrap()
+}
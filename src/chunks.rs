@@ -0,0 +1,248 @@
+use std::ops::Range;
+
+use macroquad::{models::Vertex, prelude::*};
+
+use flowfields::{Cell, Grid, Heading, CELL_SIZE};
+
+// Cells along each side of a chunk
+const CHUNK_SIZE: usize = 32;
+// What macroquad fits in one draw call, meshes are split to stay under it
+const MAX_VERTICES: usize = 10000;
+const MAX_INDICES: usize = 5000;
+// Borders are left out once cells are smaller than this on screen, in pixels, since
+// they'd cover the cells up
+const MIN_BORDER_PIXELS: f32 = 4.0;
+
+// Triangles of one color, split into meshes small enough to draw in one go
+#[derive(Default)]
+struct Batch {
+    meshes: Vec<Mesh>,
+}
+
+impl Batch {
+    fn push(&mut self, points: &[Vec2], indices: &[u16], color: Color) {
+        let full = self.meshes.last().is_none_or(|mesh| {
+            mesh.vertices.len() + points.len() >= MAX_VERTICES || mesh.indices.len() + indices.len() >= MAX_INDICES
+        });
+        if full {
+            self.meshes.push(Mesh {
+                vertices: Vec::new(),
+                indices: Vec::new(),
+                texture: None,
+            });
+        }
+        let mesh = self.meshes.last_mut().unwrap();
+        let first = mesh.vertices.len() as u16;
+        mesh.vertices.extend(points.iter().map(|point| Vertex {
+            position: point.extend(0.0),
+            uv: Vec2::ZERO,
+            color,
+        }));
+        mesh.indices.extend(indices.iter().map(|index| first + index));
+    }
+
+    fn polygon(&mut self, corners: &[Vec2], color: Color) {
+        let indices: Vec<u16> = (1..corners.len() as u16 - 1)
+            .flat_map(|corner| [0, corner, corner + 1])
+            .collect();
+        self.push(corners, &indices, color);
+    }
+
+    // Same as `draw_line`
+    fn line(&mut self, from: Vec2, to: Vec2, thickness: f32, color: Color) {
+        let normal = (to - from).perp().normalize_or_zero() * thickness / 2.0;
+        if normal == Vec2::ZERO {
+            return;
+        }
+        let corners = [from + normal, from - normal, to + normal, to - normal];
+        self.push(&corners, &[0, 1, 2, 2, 1, 3], color);
+    }
+
+    fn draw(&self) {
+        for mesh in &self.meshes {
+            draw_mesh(mesh);
+        }
+    }
+}
+
+// A chunk's cells with everything that only changes along with them: the colors, one-way
+// chevrons and arrows, and the borders kept apart so they can be skipped
+#[derive(Default)]
+struct Chunk {
+    cells: Batch,
+    borders: Batch,
+}
+
+// Draws the map a chunk at a time out of cached meshes, so a frame costs a handful of
+// draw calls per chunk on screen instead of a few per cell. Chunks are built the first
+// time they're on screen after being marked dirty, ones out of view aren't touched.
+// They never reach across two levels, so one level can be drawn on its own.
+#[derive(Default)]
+pub struct ChunkRenderer {
+    // Columns, rows and levels the chunks were laid out for
+    layout: (usize, usize, usize),
+    // Rows of each row of chunks, with the level they're on
+    bands: Vec<(usize, Range<usize>)>,
+    // Row by row, None when dirty
+    chunks: Vec<Option<Chunk>>,
+}
+
+impl ChunkRenderer {
+    // Everything gets rebuilt, for when the colors or directions have changed
+    pub fn invalidate(&mut self) {
+        self.chunks.iter_mut().for_each(|chunk| *chunk = None);
+    }
+
+    // Rebuilds the chunk holding `cell` and those next to it, which share its borders
+    pub fn mark_dirty(&mut self, (col_x, row_y): (usize, usize)) {
+        let chunk_columns = chunk_count(self.layout.0);
+        for (band_index, (_, rows)) in self.bands.iter().enumerate() {
+            if rows.end < row_y || rows.start > row_y + 1 {
+                continue;
+            }
+            for chunk_x in col_x.saturating_sub(1) / CHUNK_SIZE..=(col_x + 1) / CHUNK_SIZE {
+                if chunk_x < chunk_columns {
+                    self.chunks[band_index * chunk_columns + chunk_x] = None;
+                }
+            }
+        }
+    }
+
+    // Cells on `level` in the chunks that overlap `visible`, an area in world space
+    pub fn visible_cells(&self, grid: &Grid, level: usize, visible: Rect) -> Vec<(usize, usize)> {
+        self.visible_chunks(grid, level, visible)
+            .into_iter()
+            .flat_map(|(chunk_x, band_index)| chunk_cells(grid, chunk_x, self.bands[band_index].1.clone()))
+            .collect()
+    }
+
+    // Draws the cells on `level`. `cell_pixels` is how wide a cell is on screen, `color`
+    // gives each cell's fill and `direction` the arrow drawn on it, if any.
+    pub fn draw(
+        &mut self,
+        grid: &Grid,
+        level: usize,
+        visible: Rect,
+        cell_pixels: f32,
+        color: impl Fn(&Cell) -> Color,
+        direction: impl Fn(&Cell) -> Option<Vec2>,
+    ) {
+        let layout = (grid.column_count_x, grid.row_count_y, grid.levels);
+        if layout != self.layout {
+            self.layout = layout;
+            self.bands = (0..grid.levels)
+                .flat_map(|level| {
+                    let level_rows = level * grid.level_rows()..(level + 1) * grid.level_rows();
+                    level_rows
+                        .clone()
+                        .step_by(CHUNK_SIZE)
+                        .map(move |first| (level, first..(first + CHUNK_SIZE).min(level_rows.end)))
+                })
+                .collect();
+            self.chunks.clear();
+            self.chunks.resize_with(chunk_count(layout.0) * self.bands.len(), || None);
+        }
+        let chunk_columns = chunk_count(layout.0);
+        for (chunk_x, band_index) in self.visible_chunks(grid, level, visible) {
+            let rows = self.bands[band_index].1.clone();
+            let chunk = self.chunks[band_index * chunk_columns + chunk_x]
+                .get_or_insert_with(|| build_chunk(grid, chunk_x, rows, &color, &direction));
+            chunk.cells.draw();
+            if cell_pixels >= MIN_BORDER_PIXELS {
+                chunk.borders.draw();
+            }
+        }
+    }
+
+    // As chunk columns and indices into `bands`
+    fn visible_chunks(&self, grid: &Grid, level: usize, visible: Rect) -> Vec<(usize, usize)> {
+        let mut chunks = Vec::new();
+        for (band_index, (band_level, rows)) in self.bands.iter().enumerate() {
+            if *band_level != level {
+                continue;
+            }
+            for chunk_x in 0..chunk_count(grid.column_count_x) {
+                if chunk_bounds(grid, chunk_x, rows.clone()).overlaps(&visible) {
+                    chunks.push((chunk_x, band_index));
+                }
+            }
+        }
+        chunks
+    }
+}
+
+fn chunk_count(cells: usize) -> usize {
+    cells.div_ceil(CHUNK_SIZE)
+}
+
+fn chunk_cells(grid: &Grid, chunk_x: usize, rows: Range<usize>) -> impl Iterator<Item = (usize, usize)> {
+    let columns = chunk_x * CHUNK_SIZE..((chunk_x + 1) * CHUNK_SIZE).min(grid.column_count_x);
+    rows.flat_map(move |row_y| columns.clone().map(move |col_x| (col_x, row_y)))
+}
+
+// Covers the corners of the chunk's corner cells with half a cell to spare, which is
+// enough for the rows hex grids shift sideways
+fn chunk_bounds(grid: &Grid, chunk_x: usize, rows: Range<usize>) -> Rect {
+    let columns = [chunk_x * CHUNK_SIZE, ((chunk_x + 1) * CHUNK_SIZE).min(grid.column_count_x) - 1];
+    let (mut min, mut max) = (Vec2::splat(f32::MAX), Vec2::splat(f32::MIN));
+    for row_y in [rows.start, rows.end - 1] {
+        for col_x in columns {
+            for corner in grid.topology.cell_corners((col_x, row_y)) {
+                min = min.min(corner);
+                max = max.max(corner);
+            }
+        }
+    }
+    let (min, max) = (min - CELL_SIZE / 2.0, max + CELL_SIZE / 2.0);
+    Rect::new(min.x, min.y, max.x - min.x, max.y - min.y)
+}
+
+fn build_chunk(
+    grid: &Grid,
+    chunk_x: usize,
+    rows: Range<usize>,
+    color: impl Fn(&Cell) -> Color,
+    direction: impl Fn(&Cell) -> Option<Vec2>,
+) -> Chunk {
+    let mut chunk = Chunk::default();
+    for (col_x, row_y) in chunk_cells(grid, chunk_x, rows) {
+        let cell = &grid.grid[row_y][col_x];
+        let center = grid.topology.cell_center((col_x, row_y));
+        let corners = grid.topology.cell_corners((col_x, row_y));
+        chunk.cells.polygon(&corners, color(cell));
+        for corner in 0..corners.len() {
+            chunk
+                .borders
+                .line(corners[corner], corners[(corner + 1) % corners.len()], 1.0, DARKGRAY);
+        }
+        if let Some(heading) = cell.one_way {
+            for (from, to) in chevron_lines(center, heading) {
+                chunk.cells.line(from, to, 3.0, DARKBLUE);
+            }
+        }
+        if let Some(direction) = direction(cell).filter(|&direction| direction != Vec2::ZERO) {
+            for (from, to) in arrow_lines(center, direction) {
+                chunk.cells.line(from, to, 1.5, DARKGRAY);
+            }
+        }
+    }
+    chunk
+}
+
+// Chevron pointing the way a one-way cell is entered
+fn chevron_lines(center: Vec2, heading: Heading) -> [(Vec2, Vec2); 2] {
+    let direction = heading.vector();
+    let tip = center + direction * CELL_SIZE * 0.25;
+    [-1.0, 1.0].map(|side| {
+        let wing = center - direction * CELL_SIZE * 0.1 + direction.perp() * side * CELL_SIZE * 0.3;
+        (wing, tip)
+    })
+}
+
+// Arrow along `direction` (a unit vector) centered on `center`
+fn arrow_lines(center: Vec2, direction: Vec2) -> [(Vec2, Vec2); 3] {
+    let tail = center - direction * CELL_SIZE * 0.3;
+    let tip = center + direction * CELL_SIZE * 0.3;
+    let barb = |side: f32| tip - direction * CELL_SIZE * 0.15 + direction.perp() * side * CELL_SIZE * 0.1;
+    [(tail, tip), (tip, barb(-1.0)), (tip, barb(1.0))]
+}
@@ -0,0 +1,299 @@
+use std::time::{Duration, Instant};
+
+use flowfields::{
+    cost_script::CostScript,
+    export::{self, Format},
+    flow_field::FlowField,
+    map_file, mapgen,
+    metric::Metric,
+    solver::Solver,
+    topology::HexTopology,
+    CellType, Grid,
+};
+
+pub const USAGE: &str = "\
+usage:
+  flowfields [--hex] [--serve [ADDRESS]] open the visualizer, optionally streaming it over
+                                         WebSocket (default address 127.0.0.1:9001)
+  flowfields compute <map> [options]     compute the field for a map file and print it
+  flowfields bench [options]             time the solvers on randomly generated maps
+
+compute options:
+  --source X,Y         add a source on top of the ones in the map, repeatable
+  --hex                read the map as a hex grid
+  --wrap               wrap the map around its edges
+  --solver NAME        bfs, dijkstra or fmm (default dijkstra)
+  --metric NAME        manhattan, chebyshev or octile (default manhattan)
+  --danger-weight W    weight of the danger layer (default 1)
+  --costs PATH         cost script to use instead of the terrain costs
+  --format NAME        text, csv, binary or png (default text, the last two need --output)
+  --output PATH        write the result to PATH instead of stdout
+
+bench options:
+  --size N             width and height of the maps (default 512)
+  --sources N          sources per map (default 8)
+  --density D          chance of each cell being a barrier (default 0.2)
+  --iterations N       maps to time each solver on (default 10)
+  --solver NAME        bfs, dijkstra, fmm or all (default all)
+  --seed N             seed for the map generator (default 1)";
+
+// Runs a headless subcommand if the arguments start with one
+pub fn run_subcommand(args: &[String]) -> Option<Result<(), String>> {
+    match args.first().map(String::as_str) {
+        Some("compute") => Some(compute(&args[1..])),
+        Some("bench") => Some(bench(&args[1..])),
+        Some("help" | "--help" | "-h") => {
+            println!("{}", USAGE);
+            Some(Ok(()))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(feature = "render")]
+const DEFAULT_SERVE_ADDRESS: &str = "127.0.0.1:9001";
+
+// Where the visualizer should serve from, if `--serve` was given
+#[cfg(feature = "render")]
+pub fn serve_address(args: &[String]) -> Option<String> {
+    let index = args.iter().position(|arg| arg == "--serve")?;
+    let address = args.get(index + 1).filter(|address| !address.starts_with("--"));
+    Some(address.map_or(DEFAULT_SERVE_ADDRESS, String::as_str).to_string())
+}
+
+fn parse_number<T: std::str::FromStr>(option: &str, text: &str) -> Result<T, String> {
+    text.parse()
+        .map_err(|_| format!("{} expects a number, got \"{}\"", option, text))
+}
+
+fn parse_solver(text: &str) -> Result<Solver, String> {
+    Solver::from_name(text)
+        .ok_or_else(|| format!("unknown solver \"{}\", expected bfs, dijkstra or fmm", text))
+}
+
+fn parse_metric(text: &str) -> Result<Metric, String> {
+    Metric::from_name(text)
+        .ok_or_else(|| format!("unknown metric \"{}\", expected manhattan, chebyshev or octile", text))
+}
+
+fn parse_coordinate(text: &str) -> Result<(usize, usize), String> {
+    text.split_once(',')
+        .and_then(|(col_x, row_y)| Some((col_x.trim().parse().ok()?, row_y.trim().parse().ok()?)))
+        .ok_or_else(|| format!("expected a coordinate like 3,4, got \"{}\"", text))
+}
+
+fn compute(args: &[String]) -> Result<(), String> {
+    let mut map_path = None;
+    let mut sources = Vec::new();
+    let mut hex = false;
+    let mut wrap = false;
+    let mut solver = Solver::default();
+    let mut metric = Metric::default();
+    let mut danger_weight = 1.0;
+    let mut output_path = None;
+    let mut costs_path = None;
+    // None for the plain text report
+    let mut format = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--source" => sources.push(parse_coordinate(value()?)?),
+            "--hex" => hex = true,
+            "--wrap" => wrap = true,
+            "--solver" => solver = parse_solver(value()?)?,
+            "--metric" => metric = parse_metric(value()?)?,
+            "--danger-weight" => danger_weight = parse_number(arg, value()?)?,
+            "--output" => output_path = Some(value()?.clone()),
+            "--costs" => costs_path = Some(value()?.clone()),
+            "--format" => {
+                let name = value()?;
+                format = match name.as_str() {
+                    "text" => None,
+                    _ => Some(Format::from_name(name).ok_or_else(|| {
+                        format!("unknown format \"{}\", expected text, csv, binary or png", name)
+                    })?),
+                };
+            }
+            _ if map_path.is_none() && !arg.starts_with("--") => map_path = Some(arg.clone()),
+            _ => return Err(format!("unexpected argument \"{}\"\n\n{}", arg, USAGE)),
+        }
+    }
+
+    let map_path = map_path.ok_or_else(|| format!("compute needs a map file\n\n{}", USAGE))?;
+    // Binary output would only garble the terminal
+    if let (Some(format @ (Format::Binary | Format::Png)), None) = (format, &output_path) {
+        return Err(format!("--format {} needs --output", format.name()));
+    }
+    // Reads the file directly rather than through map_file::load_map, which is left
+    // out of builds without the fs feature
+    let text = std::fs::read_to_string(&map_path).map_err(|error| format!("{}: {}", map_path, error))?;
+    let mut grid = map_file::parse_map(&text).map_err(|error| format!("{}: {}", map_path, error))?;
+    if hex {
+        grid.topology = &HexTopology;
+    }
+    grid.wrap = wrap;
+    grid.solver = solver;
+    grid.metric = metric;
+
+    for (col_x, row_y) in sources {
+        if col_x >= grid.column_count_x || row_y >= grid.row_count_y {
+            return Err(format!("source {},{} is outside the map", col_x, row_y));
+        }
+        let cell = &mut grid.grid[row_y][col_x];
+        if cell.cell_type == CellType::Barrier {
+            return Err(format!("source {},{} is on a barrier", col_x, row_y));
+        }
+        cell.cell_type = CellType::Source;
+        cell.cell_number = Some(1.0);
+    }
+
+    if let Some(path) = costs_path {
+        let text = std::fs::read_to_string(&path).map_err(|error| format!("{}: {}", path, error))?;
+        let script = CostScript::parse(&text).map_err(|error| format!("{}: {}", path, error))?;
+        grid.cell_costs = Some(script.costs(&grid));
+    }
+
+    let flow_field = FlowField::compute(&mut grid, danger_weight);
+    let report = match format {
+        Some(format) => export::export(&grid, &flow_field, format),
+        None => format_report(&grid, &flow_field).into_bytes(),
+    };
+
+    match output_path {
+        Some(path) => std::fs::write(&path, report).map_err(|error| format!("{}: {}", path, error)),
+        None => {
+            print!("{}", String::from_utf8_lossy(&report));
+            Ok(())
+        }
+    }
+}
+
+// Min, mean and max of a set of timings, in milliseconds
+fn timing_summary(timings: &[Duration]) -> (f64, f64, f64) {
+    let milliseconds: Vec<f64> = timings
+        .iter()
+        .map(|timing| timing.as_secs_f64() * 1000.0)
+        .collect();
+    let min = milliseconds.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = milliseconds.iter().copied().fold(0.0, f64::max);
+    let mean = milliseconds.iter().sum::<f64>() / milliseconds.len() as f64;
+    (min, mean, max)
+}
+
+fn bench(args: &[String]) -> Result<(), String> {
+    let mut size = 512;
+    let mut source_count = 8;
+    let mut density = 0.2;
+    let mut iterations = 10;
+    let mut solvers = Solver::ALL.to_vec();
+    let mut seed = 1;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--size" => size = parse_number(arg, value()?)?,
+            "--sources" => source_count = parse_number(arg, value()?)?,
+            "--density" => density = parse_number(arg, value()?)?,
+            "--iterations" => iterations = parse_number(arg, value()?)?,
+            "--solver" => {
+                let name = value()?;
+                if name != "all" {
+                    solvers = vec![parse_solver(name)?];
+                }
+            }
+            "--seed" => seed = parse_number(arg, value()?)?,
+            _ => return Err(format!("unexpected argument \"{}\"\n\n{}", arg, USAGE)),
+        }
+    }
+    if size == 0 || iterations == 0 {
+        return Err("--size and --iterations must be at least 1".to_string());
+    }
+
+    // Every solver runs on the same maps
+    quad_rand::srand(seed);
+    let maps: Vec<Grid> = (0..iterations)
+        .map(|_| mapgen::scatter(size, size, density, source_count))
+        .collect();
+
+    println!(
+        "{0}x{0} cells, {1} sources, density {2:.2}, {3} iterations",
+        size, source_count, density, iterations
+    );
+    println!(
+        "{:<10} {:<12} {:>10} {:>10} {:>10}",
+        "solver", "phase", "min ms", "mean ms", "max ms"
+    );
+
+    for solver in solvers {
+        let mut integration_timings = Vec::with_capacity(iterations);
+        let mut flow_timings = Vec::with_capacity(iterations);
+
+        for map in &maps {
+            let mut grid = Grid::new(map.row_count_y, map.column_count_x);
+            grid.grid = map.grid.clone();
+            grid.solver = solver;
+            let source_coordinates = grid.get_coordinates_of_type(CellType::Source);
+
+            let started = Instant::now();
+            grid.source_cells(&source_coordinates);
+            integration_timings.push(started.elapsed());
+
+            let started = Instant::now();
+            let flow_field = FlowField::from_grid(&grid, 1.0);
+            flow_timings.push(started.elapsed());
+            drop(flow_field);
+        }
+
+        for (phase, timings) in [
+            ("integration", &integration_timings),
+            ("flow", &flow_timings),
+        ] {
+            let (min, mean, max) = timing_summary(timings);
+            println!(
+                "{:<10} {:<12} {:>10.3} {:>10.3} {:>10.3}",
+                solver.name(),
+                phase,
+                min,
+                mean,
+                max
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// Integration values (`#` for barriers, `-` for unreached cells) followed by the flow
+// direction of every cell
+fn format_report(grid: &Grid, flow_field: &FlowField) -> String {
+    let mut report = String::from("; integration\n");
+    for row in &grid.grid {
+        let values: Vec<String> = row
+            .iter()
+            .map(|cell| match (cell.cell_type, cell.cell_number) {
+                (CellType::Barrier, _) => format!("{:>6}", "#"),
+                (_, Some(number)) => format!("{:>6.1}", number),
+                (_, None) => format!("{:>6}", "-"),
+            })
+            .collect();
+        report.push_str(&values.join(" "));
+        report.push('\n');
+    }
+
+    report.push_str("; directions\n");
+    for (row_y, row) in grid.grid.iter().enumerate() {
+        let directions: Vec<String> = (0..row.len())
+            .map(|col_x| {
+                let direction = flow_field.direction(col_x, row_y);
+                format!("{:>5.2},{:<5.2}", direction.x, direction.y)
+            })
+            .collect();
+        report.push_str(&directions.join(" "));
+        report.push('\n');
+    }
+
+    report
+}
@@ -0,0 +1,82 @@
+// Gradients for coloring distances, as evenly spaced RGB stops
+#[derive(Clone, Debug, PartialEq)]
+pub struct Colormap {
+    name: String,
+    stops: Vec<[f32; 3]>,
+}
+
+impl Colormap {
+    pub fn new(name: &str, stops: Vec<[f32; 3]>) -> Result<Self, String> {
+        if stops.len() < 2 {
+            return Err(format!("gradient \"{}\" needs at least two colors", name));
+        }
+        Ok(Colormap {
+            name: name.to_string(),
+            stops,
+        })
+    }
+
+    // Viridis and magma are sampled from matplotlib's, the first stop is the near end
+    pub fn builtins() -> Vec<Colormap> {
+        [
+            ("viridis", ["#440154", "#3b528b", "#21918c", "#5ec962", "#fde725"].as_slice()),
+            ("magma", ["#000004", "#51127c", "#b73779", "#fc8961", "#fcfdbf"].as_slice()),
+            ("grayscale", ["#202020", "#f0f0f0"].as_slice()),
+            ("ocean", ["#08306b", "#2171b5", "#6baed6", "#deebf7"].as_slice()),
+        ]
+        .into_iter()
+        .map(|(name, colors)| {
+            let stops = colors.iter().map(|color| parse_hex(color).unwrap()).collect();
+            Colormap::new(name, stops).unwrap()
+        })
+        .collect()
+    }
+
+    // One gradient per line: a name followed by two or more `#rrggbb` colors. Blank
+    // lines and lines starting with `;` are skipped.
+    pub fn parse_list(text: &str) -> Result<Vec<Colormap>, String> {
+        let mut colormaps = Vec::new();
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let name = parts.next().unwrap_or_default();
+            let colormap = parts
+                .map(parse_hex)
+                .collect::<Result<Vec<_>, _>>()
+                .and_then(|stops| Colormap::new(name, stops))
+                .map_err(|error| format!("line {}: {}", line_number + 1, error))?;
+            colormaps.push(colormap);
+        }
+        Ok(colormaps)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // Color at `t`, clamped to 0..=1, linearly blended between the neighboring stops
+    pub fn sample(&self, t: f32) -> [f32; 3] {
+        let position = t.clamp(0.0, 1.0) * (self.stops.len() - 1) as f32;
+        let index = (position.floor() as usize).min(self.stops.len() - 2);
+        let fraction = position - index as f32;
+        let (from, to) = (self.stops[index], self.stops[index + 1]);
+        [0, 1, 2].map(|channel| from[channel] + (to[channel] - from[channel]) * fraction)
+    }
+}
+
+fn parse_hex(text: &str) -> Result<[f32; 3], String> {
+    let digits = text.strip_prefix('#').unwrap_or(text);
+    let channel = |range: std::ops::Range<usize>| {
+        digits
+            .get(range)
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+            .map(|value| value as f32 / 255.0)
+    };
+    match (digits.len(), channel(0..2), channel(2..4), channel(4..6)) {
+        (6, Some(r), Some(g), Some(b)) => Ok([r, g, b]),
+        _ => Err(format!("\"{}\" isn't a #rrggbb color", text)),
+    }
+}
@@ -0,0 +1,471 @@
+use std::collections::VecDeque;
+
+use crate::{CellType, Grid, Terrain};
+
+// Script results are kept in this range, integration needs every step to cost something
+const MIN_COST: f32 = 0.05;
+const MAX_COST: f32 = 1_000_000.0;
+
+// A small expression language for working out what stepping onto a cell costs,
+// evaluated once per cell. The script is one expression, spread over as many lines
+// as it likes, with `;` starting a comment:
+//
+//   ; walls are unpleasant to walk along
+//   cost + 3 * max(0, 2 - barrier_distance)
+//
+// Numbers, + - * / % ^, comparisons and && || ! (true is 1, false is 0), parentheses,
+// and the functions min, max, abs, sqrt, floor, exp, clamp(x, low, high) and
+// if(condition, then, else). The variables are:
+//
+//   cost              the terrain's own cost
+//   road grass mud water   1 on that terrain, 0 elsewhere
+//   danger            1 on danger cells
+//   one_way           1 on one-way cells
+//   barrier_distance  steps to the nearest barrier, ignoring one-way cells
+//   elevation         the cell's height in levels (slopes are charged on top anyway)
+//   x y               column and row
+#[derive(Clone, Debug)]
+pub struct CostScript {
+    expression: Expression,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Variable {
+    Cost,
+    Road,
+    Grass,
+    Mud,
+    Water,
+    Danger,
+    OneWay,
+    BarrierDistance,
+    Elevation,
+    X,
+    Y,
+}
+
+const VARIABLE_NAMES: [(&str, Variable); 11] = [
+    ("cost", Variable::Cost),
+    ("road", Variable::Road),
+    ("grass", Variable::Grass),
+    ("mud", Variable::Mud),
+    ("water", Variable::Water),
+    ("danger", Variable::Danger),
+    ("one_way", Variable::OneWay),
+    ("barrier_distance", Variable::BarrierDistance),
+    ("elevation", Variable::Elevation),
+    ("x", Variable::X),
+    ("y", Variable::Y),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Function {
+    Min,
+    Max,
+    Abs,
+    Sqrt,
+    Floor,
+    Exp,
+    Clamp,
+    If,
+}
+
+// Name and number of arguments
+const FUNCTION_NAMES: [(&str, Function, usize); 8] = [
+    ("min", Function::Min, 2),
+    ("max", Function::Max, 2),
+    ("abs", Function::Abs, 1),
+    ("sqrt", Function::Sqrt, 1),
+    ("floor", Function::Floor, 1),
+    ("exp", Function::Exp, 1),
+    ("clamp", Function::Clamp, 3),
+    ("if", Function::If, 3),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operator {
+    Or,
+    And,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+    Power,
+}
+
+// Symbol, operator and how tightly it binds. Longer symbols come first so `<=`
+// isn't read as `<`.
+const OPERATORS: [(&str, Operator, u8); 14] = [
+    ("||", Operator::Or, 1),
+    ("&&", Operator::And, 2),
+    ("<=", Operator::LessOrEqual, 3),
+    (">=", Operator::GreaterOrEqual, 3),
+    ("==", Operator::Equal, 3),
+    ("!=", Operator::NotEqual, 3),
+    ("<", Operator::Less, 3),
+    (">", Operator::Greater, 3),
+    ("+", Operator::Add, 4),
+    ("-", Operator::Subtract, 4),
+    ("*", Operator::Multiply, 5),
+    ("/", Operator::Divide, 5),
+    ("%", Operator::Remainder, 5),
+    ("^", Operator::Power, 7),
+];
+
+// Binds tighter than * and / but looser than ^, so -2^2 is -4
+const UNARY_PRECEDENCE: u8 = 6;
+// How deep expressions can nest, counting parentheses, operators that group to the
+// right and unary operators. Parsing and evaluating both recurse that deep.
+const MAX_NESTING: usize = 100;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Expression {
+    Number(f32),
+    Variable(Variable),
+    Negate(Box<Expression>),
+    Not(Box<Expression>),
+    Binary(Operator, Box<Expression>, Box<Expression>),
+    Call(Function, Vec<Expression>),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Number(f32),
+    Name(String),
+    Operator(Operator),
+    Not,
+    OpenParen,
+    CloseParen,
+    Comma,
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    for line in text.lines() {
+        let line = line.split(';').next().unwrap_or("");
+        let mut rest = line.trim_start();
+        while let Some(next) = rest.chars().next() {
+            let length = if next.is_ascii_digit() || next == '.' {
+                let length = rest.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(rest.len());
+                let number = rest[..length].parse().map_err(|_| format!("bad number \"{}\"", &rest[..length]))?;
+                tokens.push(Token::Number(number));
+                length
+            } else if next.is_ascii_alphabetic() || next == '_' {
+                let length = rest.find(|c: char| !c.is_ascii_alphanumeric() && c != '_').unwrap_or(rest.len());
+                tokens.push(Token::Name(rest[..length].to_string()));
+                length
+            } else if let Some(&(symbol, operator, _)) = OPERATORS.iter().find(|(symbol, ..)| rest.starts_with(symbol)) {
+                tokens.push(Token::Operator(operator));
+                symbol.len()
+            } else {
+                tokens.push(match next {
+                    '!' => Token::Not,
+                    '(' => Token::OpenParen,
+                    ')' => Token::CloseParen,
+                    ',' => Token::Comma,
+                    _ => return Err(format!("unexpected '{}'", next)),
+                });
+                1
+            };
+            rest = rest[length..].trim_start();
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: VecDeque<Token>,
+    depth: usize,
+}
+
+impl Parser {
+    fn expect(&mut self, expected: Token, what: &str) -> Result<(), String> {
+        match self.tokens.pop_front() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(format!("expected {}, found {:?}", what, token)),
+            None => Err(format!("expected {}, found the end of the script", what)),
+        }
+    }
+
+    // Operators binding at least as tightly as `min_precedence`, by precedence climbing
+    fn expression(&mut self, min_precedence: u8) -> Result<Expression, String> {
+        if self.depth == MAX_NESTING {
+            return Err(format!("the script nests deeper than {} levels", MAX_NESTING));
+        }
+        self.depth += 1;
+        let mut left = self.unary()?;
+        while let Some(&Token::Operator(operator)) = self.tokens.front() {
+            let (_, _, precedence) = OPERATORS.iter().find(|(_, candidate, _)| *candidate == operator).unwrap();
+            if *precedence < min_precedence {
+                break;
+            }
+            self.tokens.pop_front();
+            // Powers group to the right, everything else to the left
+            let next_precedence = if operator == Operator::Power { *precedence } else { precedence + 1 };
+            let right = self.expression(next_precedence)?;
+            left = Expression::Binary(operator, Box::new(left), Box::new(right));
+        }
+        self.depth -= 1;
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expression, String> {
+        match self.tokens.front() {
+            Some(Token::Operator(Operator::Subtract)) => {
+                self.tokens.pop_front();
+                Ok(Expression::Negate(Box::new(self.expression(UNARY_PRECEDENCE)?)))
+            }
+            Some(Token::Not) => {
+                self.tokens.pop_front();
+                Ok(Expression::Not(Box::new(self.expression(UNARY_PRECEDENCE)?)))
+            }
+            _ => self.atom(),
+        }
+    }
+
+    fn atom(&mut self) -> Result<Expression, String> {
+        match self.tokens.pop_front() {
+            Some(Token::Number(number)) => Ok(Expression::Number(number)),
+            Some(Token::OpenParen) => {
+                let inner = self.expression(0)?;
+                self.expect(Token::CloseParen, "')'")?;
+                Ok(inner)
+            }
+            Some(Token::Name(name)) if self.tokens.front() == Some(&Token::OpenParen) => {
+                let &(_, function, arity) = FUNCTION_NAMES
+                    .iter()
+                    .find(|(candidate, ..)| *candidate == name)
+                    .ok_or_else(|| format!("unknown function \"{}\"", name))?;
+                self.tokens.pop_front();
+                let mut arguments = Vec::new();
+                if self.tokens.front() != Some(&Token::CloseParen) {
+                    arguments.push(self.expression(0)?);
+                    while self.tokens.front() == Some(&Token::Comma) {
+                        self.tokens.pop_front();
+                        arguments.push(self.expression(0)?);
+                    }
+                }
+                self.expect(Token::CloseParen, "')'")?;
+                if arguments.len() != arity {
+                    return Err(format!("{} takes {} arguments, got {}", name, arity, arguments.len()));
+                }
+                Ok(Expression::Call(function, arguments))
+            }
+            Some(Token::Name(name)) => VARIABLE_NAMES
+                .iter()
+                .find(|(candidate, _)| *candidate == name)
+                .map(|&(_, variable)| Expression::Variable(variable))
+                .ok_or_else(|| format!("unknown variable \"{}\"", name)),
+            Some(token) => Err(format!("unexpected {:?}", token)),
+            None => Err("the script ended early".to_string()),
+        }
+    }
+}
+
+// Everything a script can ask about one cell
+struct CellInputs {
+    cost: f32,
+    terrain: Terrain,
+    cell_type: CellType,
+    one_way: bool,
+    barrier_distance: f32,
+    elevation: i32,
+    position: (usize, usize),
+}
+
+fn truth(value: bool) -> f32 {
+    if value {
+        1.0
+    } else {
+        0.0
+    }
+}
+
+impl Expression {
+    fn evaluate(&self, inputs: &CellInputs) -> f32 {
+        match self {
+            Expression::Number(number) => *number,
+            Expression::Variable(variable) => match variable {
+                Variable::Cost => inputs.cost,
+                Variable::Road => truth(inputs.terrain == Terrain::Road),
+                Variable::Grass => truth(inputs.terrain == Terrain::Grass),
+                Variable::Mud => truth(inputs.terrain == Terrain::Mud),
+                Variable::Water => truth(inputs.terrain == Terrain::Water),
+                Variable::Danger => truth(inputs.cell_type == CellType::Danger),
+                Variable::OneWay => truth(inputs.one_way),
+                Variable::BarrierDistance => inputs.barrier_distance,
+                Variable::Elevation => inputs.elevation as f32,
+                Variable::X => inputs.position.0 as f32,
+                Variable::Y => inputs.position.1 as f32,
+            },
+            Expression::Negate(inner) => -inner.evaluate(inputs),
+            Expression::Not(inner) => truth(inner.evaluate(inputs) == 0.0),
+            Expression::Binary(operator, left, right) => {
+                let (a, b) = (left.evaluate(inputs), right.evaluate(inputs));
+                match operator {
+                    Operator::Or => truth(a != 0.0 || b != 0.0),
+                    Operator::And => truth(a != 0.0 && b != 0.0),
+                    Operator::Less => truth(a < b),
+                    Operator::LessOrEqual => truth(a <= b),
+                    Operator::Greater => truth(a > b),
+                    Operator::GreaterOrEqual => truth(a >= b),
+                    Operator::Equal => truth(a == b),
+                    Operator::NotEqual => truth(a != b),
+                    Operator::Add => a + b,
+                    Operator::Subtract => a - b,
+                    Operator::Multiply => a * b,
+                    Operator::Divide => a / b,
+                    Operator::Remainder => a % b,
+                    Operator::Power => a.powf(b),
+                }
+            }
+            Expression::Call(function, arguments) => {
+                let argument = |index: usize| arguments[index].evaluate(inputs);
+                match function {
+                    Function::Min => argument(0).min(argument(1)),
+                    Function::Max => argument(0).max(argument(1)),
+                    Function::Abs => argument(0).abs(),
+                    Function::Sqrt => argument(0).sqrt(),
+                    Function::Floor => argument(0).floor(),
+                    Function::Exp => argument(0).exp(),
+                    Function::Clamp => argument(0).max(argument(1)).min(argument(2)),
+                    // Only the branch that's taken gets evaluated
+                    Function::If if argument(0) != 0.0 => argument(1),
+                    Function::If => argument(2),
+                }
+            }
+        }
+    }
+}
+
+// Steps from every cell to the nearest barrier, infinite without any barriers
+fn barrier_distances(grid: &Grid) -> Vec<Vec<f32>> {
+    let mut distances = vec![vec![f32::INFINITY; grid.column_count_x]; grid.row_count_y];
+    let mut queue = VecDeque::new();
+    for cell in grid.grid.iter().flatten() {
+        if cell.cell_type == CellType::Barrier {
+            distances[cell.y_position][cell.x_position] = 0.0;
+            queue.push_back((cell.x_position, cell.y_position));
+        }
+    }
+    while let Some((col_x, row_y)) = queue.pop_front() {
+        let distance = distances[row_y][col_x];
+        for (neighbor_x, neighbor_y) in grid.get_neighbor_coordinates(&grid.grid[row_y][col_x]) {
+            if distances[neighbor_y][neighbor_x] > distance + 1.0 {
+                distances[neighbor_y][neighbor_x] = distance + 1.0;
+                queue.push_back((neighbor_x, neighbor_y));
+            }
+        }
+    }
+    distances
+}
+
+impl CostScript {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut parser = Parser {
+            tokens: tokenize(text)?.into(),
+            depth: 0,
+        };
+        if parser.tokens.is_empty() {
+            return Err("the script is empty".to_string());
+        }
+        let expression = parser.expression(0)?;
+        match parser.tokens.front() {
+            Some(token) => Err(format!("unexpected {:?} after the end of the expression", token)),
+            None => Ok(CostScript { expression }),
+        }
+    }
+
+    // The script's cost for every cell, row by row. Results that aren't a number fall
+    // back to the terrain cost, the rest are kept between MIN_COST and MAX_COST.
+    pub fn costs(&self, grid: &Grid) -> Vec<Vec<f32>> {
+        let barrier_distances = barrier_distances(grid);
+        grid.grid
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|cell| {
+                        let inputs = CellInputs {
+                            cost: cell.terrain.cost(),
+                            terrain: cell.terrain,
+                            cell_type: cell.cell_type,
+                            one_way: cell.one_way.is_some(),
+                            barrier_distance: barrier_distances[cell.y_position][cell.x_position],
+                            elevation: cell.elevation,
+                            position: (cell.x_position, cell.y_position),
+                        };
+                        let cost = self.expression.evaluate(&inputs);
+                        if cost.is_nan() {
+                            inputs.cost
+                        } else {
+                            cost.clamp(MIN_COST, MAX_COST)
+                        }
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The script's cost for the one cell of a 1x1 grid of the default terrain
+    fn cost(text: &str) -> f32 {
+        CostScript::parse(text).unwrap().costs(&Grid::new(1, 1))[0][0]
+    }
+
+    #[test]
+    fn precedence_and_grouping() {
+        assert_eq!(cost("1 + 2 * 3"), 7.0);
+        assert_eq!(cost("(1 + 2) * 3"), 9.0);
+        assert_eq!(cost("10 - 4 - 3"), 3.0);
+        assert_eq!(cost("2 ^ 3 ^ 2"), 512.0);
+        assert_eq!(cost("1 - -2 ^ 2"), 5.0);
+        assert_eq!(cost("1 + 2 < 4 && 3"), 1.0);
+        assert_eq!(cost("!0 + 1"), 2.0);
+        assert_eq!(cost("if(1 > 2, 5, 7)"), 7.0);
+        assert_eq!(cost("clamp(cost * 5, 2, 3) ; the rest is a comment"), 3.0);
+    }
+
+    #[test]
+    fn unknown_names() {
+        assert!(CostScript::parse("cost + height").unwrap_err().contains("unknown variable"));
+        assert!(CostScript::parse("round(cost)").unwrap_err().contains("unknown function"));
+        assert!(CostScript::parse("min(cost)").unwrap_err().contains("takes 2 arguments"));
+    }
+
+    #[test]
+    fn division_by_zero() {
+        assert_eq!(cost("1 / 0"), MAX_COST);
+        assert_eq!(cost("-1 / 0"), MIN_COST);
+        // Not a number, so the terrain's own cost
+        assert_eq!(cost("0 / 0"), cost("cost"));
+    }
+
+    #[test]
+    fn trailing_and_missing_tokens() {
+        assert!(CostScript::parse("1 2").unwrap_err().contains("after the end"));
+        assert!(CostScript::parse("(1 + 2))").unwrap_err().contains("after the end"));
+        assert!(CostScript::parse("(1 + 2").is_err());
+        assert!(CostScript::parse("1 +").is_err());
+        assert!(CostScript::parse("; only a comment").is_err());
+    }
+
+    #[test]
+    fn nesting_is_capped() {
+        let nested = |depth: usize| format!("{}1{}", "(".repeat(depth), ")".repeat(depth));
+        assert_eq!(cost(&nested(MAX_NESTING - 1)), 1.0);
+        assert!(CostScript::parse(&nested(100_000)).unwrap_err().contains("nests deeper"));
+        assert!(CostScript::parse(&"-".repeat(100_000)).unwrap_err().contains("nests deeper"));
+        assert!(CostScript::parse(&"2 ^ ".repeat(100_000)).unwrap_err().contains("nests deeper"));
+    }
+}
@@ -0,0 +1,149 @@
+use crate::{flow_field::FlowField, png, CellType, Grid};
+
+// Ways of writing out a computed field for loading into a game at runtime. Cells go
+// row by row from the top left, as they're stored in the grid.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    // A header line, then `x,y,distance,direction_x,direction_y` for every cell. The
+    // distance is left empty for barriers and cells no source reaches.
+    Csv,
+    // Little endian: the bytes `FFLD`, the column and row counts as u32, then three f32
+    // per cell: the distance (infinite for barriers and unreached cells) and the
+    // direction's x and y.
+    Binary,
+    // An RGBA image with a pixel per cell. Red and green are the direction's x and y
+    // mapped from -1..1 to 0..255, blue and alpha together are the distance as a 16 bit
+    // number (blue high), scaled so the furthest cell is 65534. 65535 is a barrier or an
+    // unreached cell.
+    Png,
+}
+
+impl Format {
+    pub const ALL: [Format; 3] = [Format::Csv, Format::Binary, Format::Png];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Binary => "binary",
+            Format::Png => "png",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Format::ALL.into_iter().find(|format| format.name() == name)
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Binary => "bin",
+            Format::Png => "png",
+        }
+    }
+}
+
+// Uses the distances stored in the grid's cells, so the grid has to be the one the
+// field was computed on
+pub fn export(grid: &Grid, flow_field: &FlowField, format: Format) -> Vec<u8> {
+    match format {
+        Format::Csv => to_csv(grid, flow_field).into_bytes(),
+        Format::Binary => to_binary(grid, flow_field),
+        Format::Png => to_png(grid, flow_field),
+    }
+}
+
+fn distance(grid: &Grid, (col_x, row_y): (usize, usize)) -> Option<f32> {
+    let cell = &grid.grid[row_y][col_x];
+    cell.cell_number.filter(|_| cell.cell_type != CellType::Barrier)
+}
+
+fn cells(grid: &Grid) -> impl Iterator<Item = (usize, usize)> + '_ {
+    (0..grid.row_count_y).flat_map(move |row_y| (0..grid.column_count_x).map(move |col_x| (col_x, row_y)))
+}
+
+pub fn to_csv(grid: &Grid, flow_field: &FlowField) -> String {
+    let mut csv = String::from("x,y,distance,direction_x,direction_y\n");
+    for (col_x, row_y) in cells(grid) {
+        let direction = flow_field.direction(col_x, row_y);
+        let distance = distance(grid, (col_x, row_y)).map_or(String::new(), |distance| distance.to_string());
+        csv.push_str(&format!("{},{},{},{},{}\n", col_x, row_y, distance, direction.x, direction.y));
+    }
+    csv
+}
+
+pub fn to_binary(grid: &Grid, flow_field: &FlowField) -> Vec<u8> {
+    let mut bytes = b"FFLD".to_vec();
+    bytes.extend((grid.column_count_x as u32).to_le_bytes());
+    bytes.extend((grid.row_count_y as u32).to_le_bytes());
+    for (col_x, row_y) in cells(grid) {
+        let direction = flow_field.direction(col_x, row_y);
+        bytes.extend(distance(grid, (col_x, row_y)).unwrap_or(f32::INFINITY).to_le_bytes());
+        bytes.extend(direction.x.to_le_bytes());
+        bytes.extend(direction.y.to_le_bytes());
+    }
+    bytes
+}
+
+pub fn to_png(grid: &Grid, flow_field: &FlowField) -> Vec<u8> {
+    let furthest = cells(grid)
+        .filter_map(|cell| distance(grid, cell))
+        .fold(0.0, f32::max);
+    let channel = |value: f32| ((value.clamp(-1.0, 1.0) + 1.0) * 127.5).round() as u8;
+
+    let mut rgba = Vec::with_capacity(grid.column_count_x * grid.row_count_y * 4);
+    for (col_x, row_y) in cells(grid) {
+        let direction = flow_field.direction(col_x, row_y);
+        let distance = match distance(grid, (col_x, row_y)) {
+            Some(distance) if furthest > 0.0 => (distance / furthest * 65534.0).round() as u16,
+            Some(_) => 0,
+            None => u16::MAX,
+        };
+        rgba.push(channel(direction.x));
+        rgba.push(channel(direction.y));
+        rgba.extend(distance.to_be_bytes());
+    }
+    png::encode_rgba(grid.column_count_x as u32, grid.row_count_y as u32, &rgba)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_file;
+
+    fn computed(text: &str) -> (Grid, FlowField) {
+        let mut grid = map_file::parse_map(text).unwrap();
+        let flow_field = FlowField::compute(&mut grid, 1.0);
+        (grid, flow_field)
+    }
+
+    #[test]
+    fn csv_leaves_barrier_distances_empty() {
+        let (grid, flow_field) = computed("S.#");
+        assert_eq!(
+            to_csv(&grid, &flow_field),
+            "x,y,distance,direction_x,direction_y\n0,0,1,0,0\n1,0,3,-1,0\n2,0,,0,0\n"
+        );
+    }
+
+    #[test]
+    fn binary_has_a_header_and_three_numbers_per_cell() {
+        let (grid, flow_field) = computed("S.#\n...");
+        let bytes = to_binary(&grid, &flow_field);
+        assert_eq!(&bytes[..12], b"FFLD\x03\0\0\0\x02\0\0\0");
+        assert_eq!(bytes.len(), 12 + 6 * 12);
+        let number = |index: usize| f32::from_le_bytes(bytes[12 + index * 4..16 + index * 4].try_into().unwrap());
+        // The second cell, then the barrier's distance
+        assert_eq!([number(3), number(4), number(5)], [3.0, -1.0, 0.0]);
+        assert_eq!(number(6), f32::INFINITY);
+    }
+
+    #[test]
+    fn formats_go_by_name() {
+        for format in Format::ALL {
+            assert_eq!(Format::from_name(format.name()), Some(format));
+        }
+        assert_eq!(Format::from_name("bin"), None);
+        let (grid, flow_field) = computed("S.");
+        assert!(export(&grid, &flow_field, Format::Png).starts_with(b"\x89PNG"));
+    }
+}
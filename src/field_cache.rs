@@ -0,0 +1,123 @@
+use crate::{
+    flow_field::{FlowField, PhaseTimings},
+    CellType, Grid,
+};
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct Key {
+    // In grid order
+    sources: Vec<(usize, usize)>,
+    version: u64,
+    danger_weight: u32,
+}
+
+// Fields computed for goal setups seen before, so switching back to one doesn't have to
+// integrate again. They're told apart by the sources, the grid's version and the danger
+// weight. Once there are `capacity` of them the least recently used one makes room.
+#[derive(Debug)]
+pub struct FieldCache {
+    capacity: usize,
+    // Least recently used first
+    entries: Vec<(Key, FlowField)>,
+}
+
+impl FieldCache {
+    pub fn new(capacity: usize) -> Self {
+        FieldCache {
+            capacity: capacity.max(1),
+            entries: Vec::new(),
+        }
+    }
+
+    // What `FlowField::compute` gives, cell numbers included
+    pub fn get_or_compute(&mut self, grid: &mut Grid, danger_weight: f32) -> &FlowField {
+        self.get_or_compute_timed(grid, danger_weight, || 0.0).0
+    }
+
+    // Same, with the timings from `FlowField::compute_timed` when the field wasn't cached
+    pub fn get_or_compute_timed(
+        &mut self,
+        grid: &mut Grid,
+        danger_weight: f32,
+        clock: impl Fn() -> f64,
+    ) -> (&FlowField, Option<PhaseTimings>) {
+        let mut timings = None;
+        let key = Key {
+            sources: grid.get_coordinates_of_type(CellType::Source),
+            version: grid.version(),
+            danger_weight: danger_weight.to_bits(),
+        };
+        match self.entries.iter().position(|(cached, _)| *cached == key) {
+            Some(index) => {
+                let entry = self.entries.remove(index);
+                grid.set_cell_numbers(entry.1.source_distances());
+                self.entries.push(entry);
+            }
+            None => {
+                let (flow_field, computed_timings) = FlowField::compute_timed(grid, danger_weight, clock);
+                timings = Some(computed_timings);
+                if self.entries.len() == self.capacity {
+                    self.entries.remove(0);
+                }
+                self.entries.push((key, flow_field));
+            }
+        }
+        (&self.entries[self.entries.len() - 1].1, timings)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Edit, PaintTool};
+
+    // Whether the field had to be integrated, rather than coming from the cache
+    fn computed(cache: &mut FieldCache, grid: &mut Grid, source: (usize, usize)) -> bool {
+        grid.apply_edit(Edit::ToggleSource(source));
+        let computed = cache.get_or_compute_timed(grid, 1.0, || 0.0).1.is_some();
+        grid.apply_edit(Edit::ToggleSource(source));
+        computed
+    }
+
+    #[test]
+    fn least_recently_used_field_makes_room() {
+        let mut grid = Grid::new(3, 3);
+        let mut cache = FieldCache::new(2);
+        assert!(computed(&mut cache, &mut grid, (0, 0)));
+        assert!(computed(&mut cache, &mut grid, (2, 2)));
+        assert!(!computed(&mut cache, &mut grid, (0, 0)));
+        // (2, 2) was used longest ago
+        assert!(computed(&mut cache, &mut grid, (1, 1)));
+        assert_eq!(cache.len(), 2);
+        assert!(!computed(&mut cache, &mut grid, (0, 0)));
+        assert!(computed(&mut cache, &mut grid, (2, 2)));
+    }
+
+    #[test]
+    fn edits_and_danger_weight_leave_old_fields_behind() {
+        let mut grid = Grid::new(3, 3);
+        let mut cache = FieldCache::new(4);
+        assert!(computed(&mut cache, &mut grid, (0, 0)));
+        grid.apply_edit(Edit::Paint(PaintTool::Barrier, (1, 1)));
+        assert!(computed(&mut cache, &mut grid, (0, 0)));
+
+        grid.apply_edit(Edit::ToggleSource((0, 0)));
+        assert!(cache.get_or_compute_timed(&mut grid, 2.0, || 0.0).1.is_some());
+        // Cached fields still put their distances on the grid
+        grid.grid[2][2].cell_number = None;
+        assert!(cache.get_or_compute_timed(&mut grid, 1.0, || 0.0).1.is_none());
+        assert!(grid.grid[2][2].cell_number.is_some());
+    }
+}
@@ -0,0 +1,87 @@
+use std::collections::{HashSet, VecDeque};
+
+use crate::{
+    symmetry::Symmetry,
+    Cell, CellType, Edit, Grid, PaintTool,
+};
+
+// Whether two cells look the same to `tool`: the same cell type for the tools that set
+// one, otherwise the same terrain, heading or elevation. Barriers only ever look like
+// other barriers, so they wall every fill in.
+fn alike(tool: PaintTool, a: &Cell, b: &Cell) -> bool {
+    let barrier = |cell: &Cell| cell.cell_type == CellType::Barrier;
+    match tool {
+        PaintTool::Barrier | PaintTool::Danger | PaintTool::Spawner => a.cell_type == b.cell_type,
+        _ if barrier(a) || barrier(b) => barrier(a) && barrier(b),
+        PaintTool::Terrain(_) => a.terrain == b.terrain,
+        PaintTool::OneWay(_) => a.one_way == b.one_way,
+        PaintTool::Raise | PaintTool::Lower => a.elevation == b.elevation,
+    }
+}
+
+// The cells joined to `start` across sides (and wrapped edges, but not portals, stairs
+// or diagonals) that look like it to `tool`, `start` first
+pub fn region(grid: &Grid, tool: PaintTool, start: (usize, usize)) -> Vec<(usize, usize)> {
+    let start_cell = &grid.grid[start.1][start.0];
+    let mut seen = HashSet::from([start]);
+    let mut queue = VecDeque::from([start]);
+    let mut cells = Vec::new();
+    while let Some(cell) = queue.pop_front() {
+        cells.push(cell);
+        for neighbor in grid.neighbors(cell) {
+            if alike(tool, start_cell, &grid.grid[neighbor.1][neighbor.0]) && seen.insert(neighbor) {
+                queue.push_back(neighbor);
+            }
+        }
+    }
+    cells
+}
+
+// Edits that paint `tool` over the region around `start` in one go. Like a stroke, it
+// erases instead when `start` already has what the tool puts down, and with symmetry on
+// the mirrored regions are filled too.
+pub fn fill_edits(grid: &Grid, tool: PaintTool, symmetry: Symmetry, start: (usize, usize)) -> Vec<Edit> {
+    let erasing = tool.is_applied(&grid.grid[start.1][start.0]);
+    let mut visited = HashSet::new();
+    let mut edits = Vec::new();
+    for transform in symmetry.transforms() {
+        let Some(image) = transform.apply_on_level(grid, start) else {
+            continue;
+        };
+        let tool = transform.apply_tool(tool);
+        for cell in region(grid, tool, image) {
+            if visited.insert(cell) && tool.is_applied(&grid.grid[cell.1][cell.0]) == erasing {
+                edits.push(Edit::Paint(tool, cell));
+            }
+        }
+    }
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{map_file, Terrain};
+
+    fn filled(text: &str, tool: PaintTool, symmetry: Symmetry, start: (usize, usize)) -> String {
+        let mut grid = map_file::parse_map(text).unwrap();
+        for edit in fill_edits(&grid, tool, symmetry, start) {
+            grid.apply_edit(edit);
+        }
+        map_file::format_map(&grid)
+    }
+
+    #[test]
+    fn stops_at_barriers_and_other_terrain() {
+        let mud = PaintTool::Terrain(Terrain::Mud);
+        assert_eq!(filled("..#..\n..#..\n=====", mud, Symmetry::Off, (0, 0)), "%%#..\n%%#..\n=====\n");
+        assert_eq!(filled("..#..\n..#..\n=====", mud, Symmetry::MirrorX, (0, 0)), "%%#%%\n%%#%%\n=====\n");
+    }
+
+    #[test]
+    fn barrier_fills_erase_from_a_barrier() {
+        let text = "##.\n#..\n..#";
+        assert_eq!(filled(text, PaintTool::Barrier, Symmetry::Off, (0, 0)), "...\n...\n..#\n");
+        assert_eq!(filled(text, PaintTool::Barrier, Symmetry::Off, (2, 0)), "###\n###\n###\n");
+    }
+}
@@ -0,0 +1,592 @@
+use glam::Vec2;
+
+use crate::{
+    solver::Origins,
+    topology::{self, Topology},
+    CellType, Grid, CELL_SIZE, DANGER_RADIUS,
+};
+
+// One potential per cell plus the weight it gets when layers are combined. Lower
+// potential is more attractive, cells with an infinite potential can't be reached.
+// Layers combined with each other have to be the size of the same grid. The
+// arithmetic works on weighted potentials and gives back layers weighing 1.
+#[derive(Clone, Debug)]
+pub struct FieldLayer {
+    potentials: Vec<Vec<f32>>,
+    weight: f32,
+}
+
+// An unweighted potential counts for nothing, even an infinite one
+fn apply_weight(potential: f32, weight: f32) -> f32 {
+    if weight == 0.0 {
+        0.0
+    } else {
+        weight * potential
+    }
+}
+
+impl FieldLayer {
+    // Row by row, like the grid's cells
+    pub fn new(potentials: Vec<Vec<f32>>) -> Self {
+        FieldLayer {
+            potentials,
+            weight: 1.0,
+        }
+    }
+
+    // Integrated distances as potentials, cells that weren't reached can't be entered
+    pub fn from_distances(distances: &[Vec<Option<f32>>]) -> Self {
+        FieldLayer::new(
+            distances
+                .iter()
+                .map(|row| row.iter().map(|distance| distance.unwrap_or(f32::INFINITY)).collect())
+                .collect(),
+        )
+    }
+
+    // Distance to the nearest source, taken from the grid's cell numbers
+    pub fn attraction(grid: &Grid) -> Self {
+        FieldLayer::new(
+            grid.grid
+                .iter()
+                .map(|row| row.iter().map(|cell| cell.cell_number.unwrap_or(f32::INFINITY)).collect())
+                .collect(),
+        )
+    }
+
+    // Peaks on the seeds of `distances` and falls off linearly to zero at `radius`
+    pub fn repulsion(distances: &[Vec<Option<f32>>], radius: f32) -> Self {
+        FieldLayer::new(
+            distances
+                .iter()
+                .map(|row| {
+                    row.iter()
+                        .map(|distance| match distance {
+                            Some(distance) => f32::max(radius - (distance - 1.0), 0.0),
+                            None => 0.0,
+                        })
+                        .collect()
+                })
+                .collect(),
+        )
+    }
+
+    // How many of `positions` are in each cell. Sources count as empty, agents
+    // gathering on one mustn't push the rest away from it.
+    pub fn crowding(grid: &Grid, positions: &[Vec2]) -> Self {
+        let mut potentials = vec![vec![0.0; grid.column_count_x]; grid.row_count_y];
+        for &position in positions {
+            if let Some((col_x, row_y)) = grid.get_cell_at(position) {
+                if grid.grid[row_y][col_x].cell_type != CellType::Source {
+                    potentials[row_y][col_x] += 1.0;
+                }
+            }
+        }
+        FieldLayer::new(potentials)
+    }
+
+    pub fn with_weight(mut self, weight: f32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    pub fn weight(&self) -> f32 {
+        self.weight
+    }
+
+    // Weighted potential of one cell
+    pub fn potential(&self, col_x: usize, row_y: usize) -> f32 {
+        apply_weight(self.potentials[row_y][col_x], self.weight)
+    }
+
+    // Highest weighted potential that isn't infinite, 0 when there's none
+    pub fn peak(&self) -> f32 {
+        self.potentials
+            .iter()
+            .flatten()
+            .map(|&potential| apply_weight(potential, self.weight))
+            .filter(|potential| potential.is_finite())
+            .fold(0.0, f32::max)
+    }
+
+    fn map(&self, mut f: impl FnMut((usize, usize), f32) -> f32) -> Self {
+        FieldLayer::new(
+            self.potentials
+                .iter()
+                .enumerate()
+                .map(|(row_y, row)| {
+                    row.iter()
+                        .enumerate()
+                        .map(|(col_x, &potential)| f((col_x, row_y), apply_weight(potential, self.weight)))
+                        .collect()
+                })
+                .collect(),
+        )
+    }
+
+    fn combine(&self, other: &FieldLayer, f: impl Fn(f32, f32) -> f32) -> Self {
+        self.map(|(col_x, row_y), potential| f(potential, other.potential(col_x, row_y)))
+    }
+
+    pub fn add(&self, other: &FieldLayer) -> Self {
+        self.combine(other, |a, b| a + b)
+    }
+
+    pub fn scale(&self, factor: f32) -> Self {
+        self.map(|_, potential| apply_weight(potential, factor))
+    }
+
+    pub fn max(&self, other: &FieldLayer) -> Self {
+        self.combine(other, f32::max)
+    }
+
+    pub fn min(&self, other: &FieldLayer) -> Self {
+        self.combine(other, f32::min)
+    }
+
+    // Barrier cells of `grid` become unreachable
+    pub fn mask(&self, grid: &Grid) -> Self {
+        self.map(|(col_x, row_y), potential| match grid.grid[row_y][col_x].cell_type {
+            CellType::Barrier => f32::INFINITY,
+            _ => potential,
+        })
+    }
+
+    // All the layers added up, all zeros for none. Needs the grid for its size.
+    pub fn sum(grid: &Grid, layers: &[FieldLayer]) -> Self {
+        let zero = FieldLayer::new(vec![vec![0.0; grid.column_count_x]; grid.row_count_y]);
+        layers.iter().fold(zero, |total, layer| total.add(layer))
+    }
+}
+
+// Per-cell flow directions. Each cell points towards the neighbor with the lowest
+// combined potential, sources and barriers have no direction. Directions are unit
+// vectors in world space so they mean the same thing on every topology. Cells that lead
+// into their portal's twin have no direction either, only a next cell.
+#[derive(Clone, Debug)]
+pub struct FlowField {
+    directions: Vec<Vec<Vec2>>,
+    // The integrations the field was built from, kept so `update` can repair them.
+    // Empty when they aren't known.
+    source_distances: Vec<Vec<Option<f32>>>,
+    danger_distances: Vec<Vec<Option<f32>>>,
+    // Which source or danger cell each of those distances came from
+    source_origins: Origins,
+    danger_origins: Origins,
+    next_cells: Vec<Vec<Option<(usize, usize)>>>,
+    row_count_y: usize,
+    column_count_x: usize,
+    topology: &'static dyn Topology,
+    wrap: bool,
+}
+
+// Seconds spent in each part of computing a field: integrating (sources and danger
+// together) and deriving the directions from the result
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub struct PhaseTimings {
+    pub integration: f64,
+    pub flow: f64,
+}
+
+impl FlowField {
+    // Runs the whole pipeline: integrates from the grid's sources (storing the result in
+    // the cells), then derives the directions
+    pub fn compute(grid: &mut Grid, danger_weight: f32) -> Self {
+        FlowField::compute_timed(grid, danger_weight, || 0.0).0
+    }
+
+    // Same, timing the phases with `clock` (seconds from any fixed point). It's passed
+    // in since browsers don't have the standard library's clock.
+    pub fn compute_timed(grid: &mut Grid, danger_weight: f32, clock: impl Fn() -> f64) -> (Self, PhaseTimings) {
+        let started = clock();
+        let source_coordinates = grid.get_coordinates_of_type(CellType::Source);
+        // Nothing to head for, so nothing left of the old field either
+        if source_coordinates.is_empty() {
+            grid.invalidate_field();
+            let timings = PhaseTimings {
+                integration: clock() - started,
+                flow: 0.0,
+            };
+            return (FlowField::empty(grid), timings);
+        }
+        let (source_distances, source_origins) = grid.source_cells(&source_coordinates);
+        let (danger_distances, danger_origins) = grid.integrate_with_origins(&grid.get_coordinates_of_type(CellType::Danger));
+        let integrated = clock();
+
+        let mut flow_field = FlowField::with_danger_distances(grid, danger_distances, danger_origins, danger_weight);
+        flow_field.source_distances = source_distances;
+        flow_field.source_origins = source_origins;
+        let timings = PhaseTimings {
+            integration: integrated - started,
+            flow: clock() - integrated,
+        };
+        (flow_field, timings)
+    }
+
+    // Builds the danger layer and combines it with the integration already stored in
+    // the cells
+    pub fn from_grid(grid: &Grid, danger_weight: f32) -> Self {
+        let (danger_distances, danger_origins) = grid.integrate_with_origins(&grid.get_coordinates_of_type(CellType::Danger));
+        FlowField::with_danger_distances(grid, danger_distances, danger_origins, danger_weight)
+    }
+
+    // Like `from_grid`, with danger distances integrated some other way. The field can't
+    // be repaired by `update` and gets computed from scratch instead.
+    pub fn from_danger_distances(grid: &Grid, danger_distances: Vec<Vec<Option<f32>>>, danger_weight: f32) -> Self {
+        FlowField::with_danger_distances(grid, danger_distances, Vec::new(), danger_weight)
+    }
+
+    fn with_danger_distances(
+        grid: &Grid,
+        danger_distances: Vec<Vec<Option<f32>>>,
+        danger_origins: Origins,
+        danger_weight: f32,
+    ) -> Self {
+        let layers = [
+            FieldLayer::attraction(grid),
+            FieldLayer::repulsion(&danger_distances, DANGER_RADIUS).with_weight(danger_weight),
+        ];
+        let mut flow_field = FlowField::from_layers(grid, &layers);
+        flow_field.danger_distances = danger_distances;
+        flow_field.danger_origins = danger_origins;
+        flow_field
+    }
+
+    // Catches the field up with the cells in `changed` turning into barriers, opening
+    // back up or changing cost, redoing only the parts of both integrations that ran
+    // through them. The
+    // grid must be otherwise unchanged since the field was computed, if the integrations
+    // aren't known (or the grid was resized) it's computed from scratch instead.
+    pub fn update(&mut self, grid: &mut Grid, changed: &[(usize, usize)], danger_weight: f32) {
+        self.update_timed(grid, changed, danger_weight, || 0.0);
+    }
+
+    // Same, timing the phases like `compute_timed`
+    pub fn update_timed(
+        &mut self,
+        grid: &mut Grid,
+        changed: &[(usize, usize)],
+        danger_weight: f32,
+        clock: impl Fn() -> f64,
+    ) -> PhaseTimings {
+        fn matches_grid<T>(rows: &[Vec<T>], grid: &Grid) -> bool {
+            rows.len() == grid.row_count_y && rows.iter().all(|row| row.len() == grid.column_count_x)
+        }
+        if !matches_grid(&self.source_distances, grid)
+            || !matches_grid(&self.danger_distances, grid)
+            || !matches_grid(&self.source_origins, grid)
+            || !matches_grid(&self.danger_origins, grid)
+        {
+            let (flow_field, timings) = FlowField::compute_timed(grid, danger_weight, clock);
+            *self = flow_field;
+            return timings;
+        }
+
+        let started = clock();
+        let mut source_distances = std::mem::take(&mut self.source_distances);
+        let mut source_origins = std::mem::take(&mut self.source_origins);
+        let source_coordinates = grid.get_coordinates_of_type(CellType::Source);
+        grid.repair_integration(&mut source_distances, &mut source_origins, &source_coordinates, changed);
+        grid.set_cell_numbers(&source_distances);
+
+        let mut danger_distances = std::mem::take(&mut self.danger_distances);
+        let mut danger_origins = std::mem::take(&mut self.danger_origins);
+        let danger_coordinates = grid.get_coordinates_of_type(CellType::Danger);
+        grid.repair_integration(&mut danger_distances, &mut danger_origins, &danger_coordinates, changed);
+        let integrated = clock();
+
+        *self = FlowField::with_danger_distances(grid, danger_distances, danger_origins, danger_weight);
+        self.source_distances = source_distances;
+        self.source_origins = source_origins;
+        PhaseTimings {
+            integration: integrated - started,
+            flow: clock() - integrated,
+        }
+    }
+
+    // No directions or distances anywhere
+    pub fn empty(grid: &Grid) -> Self {
+        FlowField {
+            directions: vec![vec![Vec2::ZERO; grid.column_count_x]; grid.row_count_y],
+            source_distances: vec![vec![None; grid.column_count_x]; grid.row_count_y],
+            danger_distances: Vec::new(),
+            source_origins: vec![vec![None; grid.column_count_x]; grid.row_count_y],
+            danger_origins: Vec::new(),
+            next_cells: vec![vec![None; grid.column_count_x]; grid.row_count_y],
+            row_count_y: grid.row_count_y,
+            column_count_x: grid.column_count_x,
+            topology: grid.topology,
+            wrap: grid.wrap,
+        }
+    }
+
+    // Sums the weighted potentials of every layer and derives directions from the result
+    pub fn from_layers(grid: &Grid, layers: &[FieldLayer]) -> Self {
+        FlowField::from_layer(grid, &FieldLayer::sum(grid, layers))
+    }
+
+    // Directions down the weighted potentials of a single (usually combined) layer
+    pub fn from_layer(grid: &Grid, layer: &FieldLayer) -> Self {
+        let mut directions = vec![vec![Vec2::ZERO; grid.column_count_x]; grid.row_count_y];
+        let mut next_cells = vec![vec![None; grid.column_count_x]; grid.row_count_y];
+
+        for row_y in &grid.grid {
+            for cell in row_y {
+                if cell.cell_type == CellType::Barrier || cell.cell_type == CellType::Source {
+                    continue;
+                }
+                let potential = layer.potential(cell.x_position, cell.y_position);
+                if !potential.is_finite() {
+                    continue;
+                }
+
+                let lowest_neighbor = grid
+                    .get_step_neighbors(cell)
+                    .into_iter()
+                    .filter(|&(col_x, row_y)| grid.grid[row_y][col_x].cell_type != CellType::Barrier)
+                    .map(|(col_x, row_y)| (layer.potential(col_x, row_y), col_x, row_y))
+                    .min_by(|a, b| a.0.total_cmp(&b.0));
+
+                if let Some((neighbor_potential, col_x, row_y)) = lowest_neighbor {
+                    if neighbor_potential < potential {
+                        let position = (cell.x_position, cell.y_position);
+                        if !grid.is_portal_jump(position, (col_x, row_y)) {
+                            directions[cell.y_position][cell.x_position] = grid.get_direction(position, (col_x, row_y));
+                        }
+                        next_cells[cell.y_position][cell.x_position] = Some((col_x, row_y));
+                    }
+                }
+            }
+        }
+
+        FlowField {
+            directions,
+            source_distances: Vec::new(),
+            danger_distances: Vec::new(),
+            source_origins: Vec::new(),
+            danger_origins: Vec::new(),
+            next_cells,
+            row_count_y: grid.row_count_y,
+            column_count_x: grid.column_count_x,
+            topology: grid.topology,
+            wrap: grid.wrap,
+        }
+    }
+
+    // Makes every cell cost its `crowding` potential on top of its usual step cost (as
+    // the grid's extra costs), so routes through a crowd get longer and agents further
+    // back pick others. Only the cells whose crowding changed are repaired, like
+    // `update` does, and nothing at all when none did. BFS leaves costs out, so it
+    // leaves crowding out too.
+    pub fn update_crowding(
+        &mut self,
+        grid: &mut Grid,
+        crowding: &FieldLayer,
+        danger_weight: f32,
+        clock: impl Fn() -> f64,
+    ) -> Option<PhaseTimings> {
+        let costs: Vec<Vec<f32>> = (0..grid.row_count_y)
+            .map(|row_y| (0..grid.column_count_x).map(|col_x| crowding.potential(col_x, row_y)).collect())
+            .collect();
+        let mut changed = Vec::new();
+        for (row_y, row) in costs.iter().enumerate() {
+            for (col_x, &cost) in row.iter().enumerate() {
+                if grid.extra_cost((col_x, row_y)) != cost {
+                    changed.push((col_x, row_y));
+                }
+            }
+        }
+        grid.extra_costs = Some(costs);
+        (!changed.is_empty()).then(|| self.update_timed(grid, &changed, danger_weight, clock))
+    }
+
+    // Blends every direction with the ones around it (a 3×3 blur weighing sides twice
+    // as much as corners, and the cell itself twice as much again), so agents turn
+    // gradually instead of snapping between neighbors. `strength` goes from 0 (the raw
+    // directions) to 1 (all blur). Barriers and cells without a direction are left out
+    // of the blur, and a cell whose blended direction would still step into a barrier
+    // keeps its raw one, so flow along a wall doesn't bend into it. Next cells and paths
+    // stay as they were.
+    pub fn smooth(&mut self, grid: &Grid, strength: f32) {
+        let raw = self.directions.clone();
+        let direction = |(col_x, row_y): (usize, usize)| {
+            if grid.grid[row_y][col_x].cell_type == CellType::Barrier {
+                Vec2::ZERO
+            } else {
+                raw[row_y][col_x]
+            }
+        };
+        for cell in grid.grid.iter().flatten() {
+            let position = (cell.x_position, cell.y_position);
+            let own = direction(position);
+            if own == Vec2::ZERO {
+                continue;
+            }
+            let sides = grid.neighbors(position).into_iter().map(direction).fold(Vec2::ZERO, |a, b| a + b);
+            let corners = grid.diagonal_neighbors(position).into_iter().map(direction).fold(Vec2::ZERO, |a, b| a + b);
+            let blurred = (own * 4.0 + sides * 2.0 + corners).normalize_or_zero();
+            let smoothed = own.lerp(blurred, strength.clamp(0.0, 1.0)).normalize_or_zero();
+            let stepped_into = grid.get_cell_at(grid.topology.cell_center(position) + smoothed * CELL_SIZE);
+            let into_barrier =
+                stepped_into.is_some_and(|(col_x, row_y)| grid.grid[row_y][col_x].cell_type == CellType::Barrier);
+            if smoothed != Vec2::ZERO && !into_barrier {
+                self.directions[cell.y_position][cell.x_position] = smoothed;
+            }
+        }
+    }
+
+    // Distances from the sources the field was computed with, empty when they aren't known
+    pub fn source_distances(&self) -> &[Vec<Option<f32>>] {
+        &self.source_distances
+    }
+
+    pub fn direction(&self, col_x: usize, row_y: usize) -> Vec2 {
+        self.directions[row_y][col_x]
+    }
+
+    // The neighbor the cell leads to, which can be a portal's twin
+    pub fn next_cell(&self, col_x: usize, row_y: usize) -> Option<(usize, usize)> {
+        self.next_cells[row_y][col_x]
+    }
+
+    // The source whose distance won the cell, which is the nearest one. Danger can bend
+    // the way there. None for cells no source reaches, and when the field wasn't built
+    // from a full integration (while stepping through one).
+    pub fn goal(&self, col_x: usize, row_y: usize) -> Option<(usize, usize)> {
+        self.source_origins.get(row_y).and_then(|row| row.get(col_x)).copied().flatten()
+    }
+
+    pub fn goal_at(&self, world_pos: Vec2) -> Option<(usize, usize)> {
+        let (col_x, row_y) = topology::resolve_cell(
+            self.topology.cell_at(world_pos),
+            self.column_count_x,
+            self.row_count_y,
+            self.wrap,
+        )?;
+        self.goal(col_x, row_y)
+    }
+
+    // Follows the directions from `start` until reaching a cell without one (normally a
+    // source). The visit limit stops the walk if the directions ever form a loop.
+    pub fn trace_path(&self, start: (usize, usize)) -> Vec<(usize, usize)> {
+        let mut path = vec![start];
+        let (mut col_x, mut row_y) = start;
+
+        while path.len() <= self.column_count_x * self.row_count_y {
+            let Some(next_cell) = self.next_cells[row_y][col_x] else {
+                break;
+            };
+            (col_x, row_y) = next_cell;
+            path.push(next_cell);
+        }
+
+        path
+    }
+
+    // Linearly interpolates the directions of the cells around `world_pos`, as picked
+    // by the topology (the four surrounding centers on a square grid). Cells off the
+    // grid or without a direction don't contribute, so agents hugging a wall or the
+    // edge of the map still get a usable heading. On a wrapping grid the cells across
+    // the edge are used instead.
+    pub fn sample(&self, world_pos: Vec2) -> Vec2 {
+        let mut blended = Vec2::ZERO;
+        for (cell, weight) in self.topology.interpolation_weights(world_pos) {
+            if let Some((col_x, row_y)) =
+                topology::resolve_cell(cell, self.column_count_x, self.row_count_y, self.wrap)
+            {
+                blended += self.directions[row_y][col_x] * weight;
+            }
+        }
+
+        blended.normalize_or_zero()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_interpolates_between_cell_centers() {
+        let grid = Grid::new(2, 2);
+        let mut flow_field = FlowField::empty(&grid);
+        flow_field.directions = vec![vec![Vec2::X, Vec2::Y], vec![Vec2::X, Vec2::Y]];
+        let center = |cell| grid.topology.cell_center(cell);
+
+        assert_eq!(flow_field.sample(center((0, 0))), Vec2::X);
+        assert_eq!(flow_field.sample(center((1, 1))), Vec2::Y);
+        let halfway = flow_field.sample((center((0, 0)) + center((1, 1))) / 2.0);
+        assert!(halfway.abs_diff_eq(Vec2::new(1.0, 1.0).normalize(), 1e-6));
+        let quarter = flow_field.sample(center((0, 0)) + Vec2::new(CELL_SIZE / 4.0, 0.0));
+        assert!(quarter.abs_diff_eq(Vec2::new(3.0, 1.0).normalize(), 1e-6));
+        // Past the edge only the cells on the grid count
+        assert_eq!(flow_field.sample(Vec2::ZERO), Vec2::X);
+    }
+
+    #[test]
+    fn sample_skips_cells_without_a_direction() {
+        let grid = Grid::new(1, 2);
+        let mut flow_field = FlowField::empty(&grid);
+        flow_field.directions[0][0] = Vec2::new(0.0, -1.0);
+        let between = (grid.topology.cell_center((0, 0)) + grid.topology.cell_center((1, 0))) / 2.0;
+        assert_eq!(flow_field.sample(between), Vec2::new(0.0, -1.0));
+    }
+
+    #[test]
+    fn smoothing_never_turns_into_a_barrier() {
+        let mut grid = Grid::new(3, 3);
+        let mut flow_field = FlowField::empty(&grid);
+        flow_field.directions = vec![vec![Vec2::new(0.0, -1.0); 3]; 3];
+        flow_field.directions[1][1] = Vec2::X;
+
+        // Blurring with its neighbors turns the middle cell mostly up
+        let mut smoothed = flow_field.clone();
+        smoothed.smooth(&grid, 1.0);
+        assert!(smoothed.direction(1, 1).x > 0.0 && smoothed.direction(1, 1).y < 0.0);
+
+        // Not when that's into a wall
+        grid.grid[0][1].cell_type = CellType::Barrier;
+        flow_field.directions[0][1] = Vec2::ZERO;
+        flow_field.smooth(&grid, 1.0);
+        assert_eq!(flow_field.direction(1, 1), Vec2::X);
+    }
+
+    fn layer(potentials: &[&[f32]]) -> FieldLayer {
+        FieldLayer::new(potentials.iter().map(|row| row.to_vec()).collect())
+    }
+
+    fn potentials(layer: &FieldLayer) -> Vec<f32> {
+        (0..2).map(|col_x| layer.potential(col_x, 0)).collect()
+    }
+
+    #[test]
+    fn layer_arithmetic_uses_weighted_potentials() {
+        let a = layer(&[&[1.0, 4.0]]).with_weight(2.0);
+        let b = layer(&[&[3.0, 1.0]]);
+        assert_eq!(potentials(&a), [2.0, 8.0]);
+        assert_eq!(potentials(&a.add(&b)), [5.0, 9.0]);
+        assert_eq!(potentials(&a.max(&b)), [3.0, 8.0]);
+        assert_eq!(potentials(&a.min(&b)), [2.0, 1.0]);
+        assert_eq!(potentials(&b.scale(-0.5)), [-1.5, -0.5]);
+        assert_eq!(a.add(&b).weight(), 1.0);
+    }
+
+    #[test]
+    fn unweighted_layers_count_for_nothing() {
+        let blocked = layer(&[&[f32::INFINITY, 2.0]]);
+        assert_eq!(potentials(&blocked.clone().with_weight(0.0)), [0.0, 0.0]);
+        assert_eq!(potentials(&blocked.scale(0.0)), [0.0, 0.0]);
+        assert_eq!(blocked.peak(), 2.0);
+    }
+
+    #[test]
+    fn sum_and_mask() {
+        let mut grid = Grid::new(1, 2);
+        assert_eq!(potentials(&FieldLayer::sum(&grid, &[])), [0.0, 0.0]);
+        let layers = [layer(&[&[1.0, 2.0]]), layer(&[&[3.0, 4.0]]).with_weight(0.5)];
+        assert_eq!(potentials(&FieldLayer::sum(&grid, &layers)), [2.5, 4.0]);
+
+        grid.grid[0][1].cell_type = CellType::Barrier;
+        assert_eq!(potentials(&layers[0].mask(&grid)), [1.0, f32::INFINITY]);
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+
+// Animated GIF writer. Every frame shares a fixed 6x7x6 color cube palette, which is
+// plenty for the flat colors the visualizer draws and saves quantizing per frame.
+#[derive(Debug)]
+pub struct GifEncoder {
+    width: u16,
+    height: u16,
+    bytes: Vec<u8>,
+}
+
+const CLEAR_CODE: u16 = 256;
+const END_CODE: u16 = 257;
+const MAX_CODE_SIZE: u8 = 12;
+
+impl GifEncoder {
+    pub fn new(width: u16, height: u16) -> Self {
+        let mut bytes = b"GIF89a".to_vec();
+        bytes.extend(width.to_le_bytes());
+        bytes.extend(height.to_le_bytes());
+        // Global color table of 256 entries, 8 bits per channel
+        bytes.extend([0xf7, 0, 0]);
+        for index in 0..=255u8 {
+            bytes.extend(palette_color(index));
+        }
+        // Loop forever
+        bytes.extend([0x21, 0xff, 0x0b]);
+        bytes.extend(b"NETSCAPE2.0");
+        bytes.extend([0x03, 0x01, 0x00, 0x00, 0x00]);
+
+        GifEncoder { width, height, bytes }
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
+
+    // `rgba` is width * height pixels, top row first. `delay` is in hundredths of a second.
+    pub fn add_frame(&mut self, rgba: &[u8], delay: u16) {
+        assert_eq!(rgba.len(), self.width as usize * self.height as usize * 4);
+
+        // Graphic control extension, just for the delay
+        self.bytes.extend([0x21, 0xf9, 0x04, 0x00]);
+        self.bytes.extend(delay.to_le_bytes());
+        self.bytes.extend([0x00, 0x00]);
+
+        // Image descriptor covering the whole canvas, no local color table
+        self.bytes.push(0x2c);
+        self.bytes.extend([0, 0, 0, 0]);
+        self.bytes.extend(self.width.to_le_bytes());
+        self.bytes.extend(self.height.to_le_bytes());
+        self.bytes.push(0);
+
+        let indices: Vec<u8> = rgba.chunks_exact(4).map(|pixel| palette_index(pixel[0], pixel[1], pixel[2])).collect();
+        self.bytes.push(8);
+        for block in compress(&indices).chunks(255) {
+            self.bytes.push(block.len() as u8);
+            self.bytes.extend(block);
+        }
+        self.bytes.push(0);
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.bytes.push(0x3b);
+        self.bytes
+    }
+}
+
+fn palette_index(r: u8, g: u8, b: u8) -> u8 {
+    let level = |value: u8, levels: u16| ((value as u16 * (levels - 1) + 127) / 255) as u8;
+    level(r, 6) * 42 + level(g, 7) * 6 + level(b, 6)
+}
+
+// Inverse of palette_index, the four entries past the cube are unused
+fn palette_color(index: u8) -> [u8; 3] {
+    if index >= 252 {
+        return [0, 0, 0];
+    }
+    let scale = |level: u8, levels: u8| (level as u16 * 255 / (levels as u16 - 1)) as u8;
+    [scale(index / 42, 6), scale(index / 6 % 7, 7), scale(index % 6, 6)]
+}
+
+// Variable width LZW as GIF wants it: codes start at 9 bits, grow up to 12, and the
+// table starts over with a clear code once it's full
+fn compress(indices: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::default();
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next_code = END_CODE + 1;
+    let mut code_size = 9;
+
+    writer.write(CLEAR_CODE, code_size);
+    let Some((&first, rest)) = indices.split_first() else {
+        writer.write(END_CODE, code_size);
+        return writer.finish();
+    };
+
+    let mut prefix = first as u16;
+    for &index in rest {
+        if let Some(&code) = table.get(&(prefix, index)) {
+            prefix = code;
+            continue;
+        }
+
+        writer.write(prefix, code_size);
+        if next_code < 1 << MAX_CODE_SIZE {
+            table.insert((prefix, index), next_code);
+            next_code += 1;
+            // The decoder adds its entries one code behind, so it widens one code later too
+            if next_code > 1 << code_size && code_size < MAX_CODE_SIZE {
+                code_size += 1;
+            }
+        } else {
+            writer.write(CLEAR_CODE, code_size);
+            table.clear();
+            next_code = END_CODE + 1;
+            code_size = 9;
+        }
+        prefix = index as u16;
+    }
+
+    writer.write(prefix, code_size);
+    writer.write(END_CODE, code_size);
+    writer.finish()
+}
+
+// Packs codes least significant bit first
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    bit_count: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u8) {
+        self.buffer |= (code as u32) << self.bit_count;
+        self.bit_count += size;
+        while self.bit_count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bit_count > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A plain GIF LZW decoder, widening codes as soon as the table fills each width.
+    // Also counts the clear codes it saw.
+    fn decompress(bytes: &[u8]) -> (Vec<u8>, usize) {
+        let (mut buffer, mut bit_count, mut position) = (0u32, 0u8, 0);
+        let mut code_size = 9;
+        let mut table: Vec<Vec<u8>> = Vec::new();
+        let mut previous: Option<Vec<u8>> = None;
+        let mut output = Vec::new();
+        let mut clears = 0;
+        loop {
+            while bit_count < code_size {
+                buffer |= (bytes[position] as u32) << bit_count;
+                position += 1;
+                bit_count += 8;
+            }
+            let code = (buffer & ((1 << code_size) - 1)) as u16;
+            buffer >>= code_size;
+            bit_count -= code_size;
+
+            if code == CLEAR_CODE {
+                table = (0..=255).map(|index| vec![index]).chain([vec![], vec![]]).collect();
+                code_size = 9;
+                previous = None;
+                clears += 1;
+                continue;
+            }
+            if code == END_CODE {
+                return (output, clears);
+            }
+            let entry = match (table.get(code as usize), &previous) {
+                (Some(entry), _) => entry.clone(),
+                (None, Some(previous)) => [previous.as_slice(), &previous[..1]].concat(),
+                (None, None) => panic!("code {} before anything to build it from", code),
+            };
+            output.extend(&entry);
+            if let Some(previous) = previous {
+                if table.len() < 1 << MAX_CODE_SIZE {
+                    table.push([previous.as_slice(), &entry[..1]].concat());
+                }
+            }
+            if table.len() == 1 << code_size && code_size < MAX_CODE_SIZE {
+                code_size += 1;
+            }
+            previous = Some(entry);
+        }
+    }
+
+    #[test]
+    fn lzw_round_trip() {
+        assert_eq!(decompress(&compress(&[])), (vec![], 1));
+        assert_eq!(decompress(&compress(&[7])), (vec![7], 1));
+        let repeated = vec![3; 10_000];
+        assert_eq!(decompress(&compress(&repeated)), (repeated, 1));
+    }
+
+    #[test]
+    fn lzw_round_trip_past_table_reset() {
+        // Noisy enough that the table fills up and starts over several times
+        let mut state = 12345u32;
+        let indices: Vec<u8> = (0..100_000)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                (state >> 16) as u8 % 40
+            })
+            .collect();
+        let (decompressed, clears) = decompress(&compress(&indices));
+        assert!(clears > 2);
+        assert_eq!(decompressed, indices);
+    }
+
+    #[test]
+    fn palette_round_trip() {
+        for index in 0..252u8 {
+            let [r, g, b] = palette_color(index);
+            assert_eq!(palette_index(r, g, b), index);
+        }
+    }
+}
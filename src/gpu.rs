@@ -0,0 +1,297 @@
+use macroquad::prelude::*;
+
+use flowfields::{
+    flow_field::{FlowField, PhaseTimings},
+    solver::Solver,
+    CellType, Grid, Heading,
+};
+
+// Relaxation passes between checks for whether the distances stopped changing
+const PASSES_PER_CHECK: usize = 32;
+// Distances and costs are stored as 24 bit fixed point numbers in the RGB channels,
+// so distances top out around 262000 and costs around 65000
+const DISTANCE_SCALE: f32 = 64.0;
+const COST_SCALE: f32 = 256.0;
+
+const VERTEX_SHADER: &str = "#version 100
+attribute vec3 position;
+attribute vec2 texcoord;
+varying vec2 uv;
+uniform mat4 Model;
+uniform mat4 Projection;
+
+void main() {
+    gl_Position = Projection * Model * vec4(position, 1.0);
+    uv = texcoord;
+}
+";
+
+// One pass of the integration: every cell takes the best distance it can be entered
+// with from its neighbors. Cells are addressed by fragment position, so it doesn't
+// matter which way up the render targets are. The alpha channel of the distances says
+// whether the cell was reached, the alpha channel of the costs holds the cell kind:
+// 0 for barriers, 1 for open cells and 2 to 5 for one-way cells heading east, south,
+// west and north. The scales are defined in front of it by `fragment_shader`.
+const FRAGMENT_SHADER: &str = "precision highp float;
+varying vec2 uv;
+uniform vec2 GridSize;
+uniform float Wrap;
+uniform sampler2D Distances;
+uniform sampler2D Costs;
+
+float decode(vec4 texel, float scale) {
+    vec3 bytes = floor(texel.rgb * 255.0 + 0.5);
+    return (bytes.r * 65536.0 + bytes.g * 256.0 + bytes.b) / scale;
+}
+
+vec4 encode(float value, float scale) {
+    float n = min(floor(value * scale + 0.5), 16777215.0);
+    float r = floor(n / 65536.0);
+    float g = floor((n - r * 65536.0) / 256.0);
+    float b = n - r * 65536.0 - g * 256.0;
+    return vec4(r, g, b, 255.0) / 255.0;
+}
+
+vec4 cell_texel(sampler2D source, vec2 cell) {
+    return texture2D(source, (cell + 0.5) / GridSize);
+}
+
+vec2 heading(float kind) {
+    if (kind < 2.5) return vec2(1.0, 0.0);
+    if (kind < 3.5) return vec2(0.0, 1.0);
+    if (kind < 4.5) return vec2(-1.0, 0.0);
+    return vec2(0.0, -1.0);
+}
+
+// Best of `best` and the distance offered by the neighbor at `offset`
+float offer(vec2 cell, vec2 offset, float cost, float best) {
+    vec2 from = cell + offset;
+    if (Wrap > 0.5) {
+        from = mod(from + GridSize, GridSize);
+    } else if (from.x < 0.0 || from.y < 0.0 || from.x >= GridSize.x || from.y >= GridSize.y) {
+        return best;
+    }
+    // The neighbor is stepped onto from here, which one-way cells only allow going their way
+    float kind = floor(cell_texel(Costs, from).a * 255.0 + 0.5);
+    if (kind > 1.5 && dot(offset, heading(kind)) <= 0.001) {
+        return best;
+    }
+    vec4 neighbor = cell_texel(Distances, from);
+    if (neighbor.a < 0.5) {
+        return best;
+    }
+    return min(best, decode(neighbor, DISTANCE_SCALE) + cost);
+}
+
+void main() {
+    vec2 cell = floor(gl_FragCoord.xy);
+    vec4 own = cell_texel(Distances, cell);
+    vec4 terrain = cell_texel(Costs, cell);
+    float kind = floor(terrain.a * 255.0 + 0.5);
+    if (kind < 0.5) {
+        gl_FragColor = vec4(0.0);
+        return;
+    }
+    float cost = decode(terrain, COST_SCALE);
+    float best = own.a > 0.5 ? decode(own, DISTANCE_SCALE) : 1e9;
+    best = offer(cell, vec2(-1.0, 0.0), cost, best);
+    best = offer(cell, vec2(1.0, 0.0), cost, best);
+    best = offer(cell, vec2(0.0, -1.0), cost, best);
+    best = offer(cell, vec2(0.0, 1.0), cost, best);
+    gl_FragColor = best < 1e8 ? encode(best, DISTANCE_SCALE) : vec4(0.0);
+}
+";
+
+// The shader with the scales it shares with this side
+fn fragment_shader() -> String {
+    format!(
+        "#version 100\n#define DISTANCE_SCALE {:.1}\n#define COST_SCALE {:.1}\n{}",
+        DISTANCE_SCALE, COST_SCALE, FRAGMENT_SHADER
+    )
+}
+
+fn encode(value: f32, scale: f32) -> [u8; 3] {
+    let n = ((value * scale).round() as u32).min(0xff_ffff);
+    [(n >> 16) as u8, (n >> 8) as u8, n as u8]
+}
+
+fn decode(bytes: &[u8], scale: f32) -> f32 {
+    ((bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32) as f32 / scale
+}
+
+// Integrates on the graphics card instead, by running relaxation passes over the grid
+// until nothing changes. Much slower to converge than Dijkstra in cell count, but every
+// cell is updated at once, which wins on big open grids. Handles flat square grids
+// without portals or diagonal steps, with BFS or Dijkstra costs.
+pub struct GpuSolver {
+    material: Material,
+    // Ping-pong targets the passes read from and write to, kept while the grid size stays
+    targets: Vec<RenderTarget>,
+    costs: Option<Texture2D>,
+}
+
+impl GpuSolver {
+    pub fn new() -> Result<Self, String> {
+        let material = load_material(
+            VERTEX_SHADER,
+            &fragment_shader(),
+            MaterialParams {
+                uniforms: vec![
+                    ("GridSize".to_owned(), UniformType::Float2),
+                    ("Wrap".to_owned(), UniformType::Float1),
+                ],
+                textures: vec!["Distances".to_owned(), "Costs".to_owned()],
+                ..Default::default()
+            },
+        )
+        .map_err(|error| format!("{:?}", error))?;
+        Ok(GpuSolver {
+            material,
+            targets: Vec::new(),
+            costs: None,
+        })
+    }
+
+    pub fn supports(grid: &Grid) -> bool {
+        let square = grid
+            .topology
+            .axis_neighbors((0, 0), grid.column_count_x, grid.row_count_y, grid.wrap)
+            .is_some();
+        square
+            && matches!(grid.solver, Solver::Bfs | Solver::Dijkstra)
+            && grid.grid.iter().flatten().all(|cell| cell.portal.is_none())
+            && !grid.has_slopes()
+            && !grid.metric.allows_diagonals()
+            && grid.levels == 1
+    }
+
+    // Same as `FlowField::compute_timed`, or None when the grid isn't supported. Leaves
+    // the default camera set.
+    pub fn compute(&mut self, grid: &mut Grid, danger_weight: f32) -> Option<(FlowField, PhaseTimings)> {
+        if !Self::supports(grid) {
+            return None;
+        }
+        let started = get_time();
+        self.prepare(grid);
+        let source_distances = self.integrate(grid, &grid.get_coordinates_of_type(CellType::Source));
+        let danger_distances = self.integrate(grid, &grid.get_coordinates_of_type(CellType::Danger));
+        grid.set_cell_numbers(&source_distances);
+        let integrated = get_time();
+        let flow_field = FlowField::from_danger_distances(grid, danger_distances, danger_weight);
+        let timings = PhaseTimings {
+            integration: integrated - started,
+            flow: get_time() - integrated,
+        };
+        Some((flow_field, timings))
+    }
+
+    // Uploads the costs and makes sure the targets fit the grid
+    fn prepare(&mut self, grid: &Grid) {
+        let (width, height) = (grid.column_count_x as u16, grid.row_count_y as u16);
+        let mut image = Image::gen_image_color(width, height, Color::from_rgba(0, 0, 0, 0));
+        for (row_y, row) in grid.grid.iter().enumerate() {
+            for (col_x, cell) in row.iter().enumerate() {
+                let cost = match grid.solver {
+                    Solver::Bfs => 1.0,
+                    _ => grid.step_cost((col_x, row_y)),
+                };
+                let kind = match (cell.cell_type, cell.one_way) {
+                    (CellType::Barrier, _) => 0,
+                    (_, None) => 1,
+                    (_, Some(Heading::East)) => 2,
+                    (_, Some(Heading::South)) => 3,
+                    (_, Some(Heading::West)) => 4,
+                    (_, Some(Heading::North)) => 5,
+                };
+                let [r, g, b] = encode(cost, COST_SCALE);
+                // Straight to the bytes, going through a color can round them off
+                image.get_image_data_mut()[row_y * grid.column_count_x + col_x] = [r, g, b, kind];
+            }
+        }
+
+        match self.costs {
+            Some(costs) if (costs.width(), costs.height()) == (width as f32, height as f32) => costs.update(&image),
+            _ => {
+                if let Some(old) = self.costs.take() {
+                    old.delete();
+                }
+                let costs = Texture2D::from_image(&image);
+                costs.set_filter(FilterMode::Nearest);
+                self.costs = Some(costs);
+            }
+        }
+
+        let fits = self.targets.first().map(|target| (target.texture.width(), target.texture.height()));
+        if fits != Some((width as f32, height as f32)) {
+            for target in self.targets.drain(..) {
+                target.delete();
+            }
+            for _ in 0..2 {
+                let target = render_target(width as u32, height as u32);
+                target.texture.set_filter(FilterMode::Nearest);
+                self.targets.push(target);
+            }
+        }
+    }
+
+    fn integrate(&mut self, grid: &Grid, seed_coordinates: &[(usize, usize)]) -> Vec<Vec<Option<f32>>> {
+        let (width, height) = (grid.column_count_x, grid.row_count_y);
+        let mut seeds = Image::gen_image_color(width as u16, height as u16, Color::from_rgba(0, 0, 0, 0));
+        for &(col_x, row_y) in seed_coordinates {
+            let [r, g, b] = encode(1.0, DISTANCE_SCALE);
+            seeds.get_image_data_mut()[row_y * width + col_x] = [r, g, b, 255];
+        }
+        self.targets[0].texture.update(&seeds);
+
+        self.material.set_uniform("GridSize", vec2(width as f32, height as f32));
+        self.material.set_uniform("Wrap", if grid.wrap { 1.0f32 } else { 0.0 });
+        if let Some(costs) = self.costs {
+            self.material.set_texture("Costs", costs);
+        }
+
+        // Relaxing can't take more passes than there are cells, but it's normally done
+        // long before that
+        let mut current = 0;
+        let mut bytes = seeds.bytes;
+        for _ in 0..(width * height).div_ceil(PASSES_PER_CHECK) {
+            for _ in 0..PASSES_PER_CHECK {
+                let (from, to) = (&self.targets[current], &self.targets[1 - current]);
+                let mut camera = Camera2D::from_display_rect(Rect::new(0.0, 0.0, width as f32, height as f32));
+                camera.render_target = Some(*to);
+                set_camera(&camera);
+                self.material.set_texture("Distances", from.texture);
+                gl_use_material(self.material);
+                draw_rectangle(0.0, 0.0, width as f32, height as f32, WHITE);
+                gl_use_default_material();
+                current = 1 - current;
+            }
+            set_default_camera();
+            let result = self.targets[current].texture.get_texture_data().bytes;
+            if result == bytes {
+                break;
+            }
+            bytes = result;
+        }
+
+        bytes
+            .chunks(width * 4)
+            .map(|row| {
+                row.chunks(4)
+                    .map(|texel| (texel[3] > 127).then(|| decode(texel, DISTANCE_SCALE)))
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+impl Drop for GpuSolver {
+    fn drop(&mut self) {
+        self.material.delete();
+        for target in &self.targets {
+            target.delete();
+        }
+        if let Some(costs) = self.costs {
+            costs.delete();
+        }
+    }
+}
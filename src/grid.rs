@@ -0,0 +1,820 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use glam::{vec2, Vec2};
+
+use crate::{
+    mapgen::{self, MapKind},
+    metric::Metric,
+    solver::{self, Origins, Solver},
+    topology::{self, SquareTopology, Topology},
+    MAX_ELEVATION, SLOPE_COST,
+};
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CellType {
+    Barrier,
+    #[default]
+    Inactive,
+    Active,
+    Source,
+    Danger,
+    // Emits agents, see spawner::Spawning. Otherwise just open ground.
+    Spawner,
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Terrain {
+    Road,
+    #[default]
+    Grass,
+    Mud,
+    Water,
+}
+
+impl Terrain {
+    // Cost of stepping onto a cell with this terrain
+    pub fn cost(self) -> f32 {
+        match self {
+            Terrain::Road => 1.0,
+            Terrain::Grass => 2.0,
+            Terrain::Mud => 4.0,
+            Terrain::Water => 8.0,
+        }
+    }
+}
+
+// The way a one-way cell has to be entered, like a conveyor belt or a cliff that can
+// only be dropped off. Steps into the cell count when they go at least partly this way.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Heading {
+    East,
+    South,
+    West,
+    North,
+}
+
+impl Heading {
+    // World space, so y points down
+    pub fn vector(self) -> Vec2 {
+        match self {
+            Heading::East => vec2(1.0, 0.0),
+            Heading::South => vec2(0.0, 1.0),
+            Heading::West => vec2(-1.0, 0.0),
+            Heading::North => vec2(0.0, -1.0),
+        }
+    }
+
+    pub fn rotated_clockwise(self) -> Self {
+        match self {
+            Heading::East => Heading::South,
+            Heading::South => Heading::West,
+            Heading::West => Heading::North,
+            Heading::North => Heading::East,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaintTool {
+    Barrier,
+    Danger,
+    Spawner,
+    Terrain(Terrain),
+    OneWay(Heading),
+    // A level up or down, see Cell::elevation
+    Raise,
+    Lower,
+}
+
+impl PaintTool {
+    pub const ALL: [PaintTool; 13] = [
+        PaintTool::Barrier,
+        PaintTool::Danger,
+        PaintTool::Spawner,
+        PaintTool::Terrain(Terrain::Road),
+        PaintTool::Terrain(Terrain::Grass),
+        PaintTool::Terrain(Terrain::Mud),
+        PaintTool::Terrain(Terrain::Water),
+        PaintTool::OneWay(Heading::East),
+        PaintTool::OneWay(Heading::South),
+        PaintTool::OneWay(Heading::West),
+        PaintTool::OneWay(Heading::North),
+        PaintTool::Raise,
+        PaintTool::Lower,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        PaintTool::ALL
+            .into_iter()
+            .find(|tool| tool.name().eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            PaintTool::Barrier => "Barrier",
+            PaintTool::Danger => "Danger",
+            PaintTool::Spawner => "Spawner",
+            PaintTool::Terrain(Terrain::Road) => "Road",
+            PaintTool::Terrain(Terrain::Grass) => "Grass",
+            PaintTool::Terrain(Terrain::Mud) => "Mud",
+            PaintTool::Terrain(Terrain::Water) => "Water",
+            PaintTool::OneWay(Heading::East) => "OneWayEast",
+            PaintTool::OneWay(Heading::South) => "OneWaySouth",
+            PaintTool::OneWay(Heading::West) => "OneWayWest",
+            PaintTool::OneWay(Heading::North) => "OneWayNorth",
+            PaintTool::Raise => "Raise",
+            PaintTool::Lower => "Lower",
+        }
+    }
+
+    // Whether painting with this tool would take it back off the cell
+    pub fn is_applied(self, cell: &Cell) -> bool {
+        match self {
+            PaintTool::Barrier => cell.cell_type == CellType::Barrier,
+            PaintTool::Danger => cell.cell_type == CellType::Danger,
+            PaintTool::Spawner => cell.cell_type == CellType::Spawner,
+            PaintTool::Terrain(terrain) => cell.terrain == terrain,
+            PaintTool::OneWay(heading) => cell.one_way == Some(heading),
+            // These stack instead of toggling
+            PaintTool::Raise | PaintTool::Lower => false,
+        }
+    }
+}
+
+// A single change to the map. Everything that edits the grid goes through these so
+// sessions can be recorded and replayed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Edit {
+    Paint(PaintTool, (usize, usize)),
+    ToggleSource((usize, usize)),
+    // Seeding the generator makes replays rebuild the exact same map
+    Generate {
+        kind: MapKind,
+        source_count: usize,
+        seed: u64,
+    },
+    ToggleWrap,
+    // Turns wrapping on or off outright, so replays don't depend on the grid's setting
+    SetWrap(bool),
+    // Back to a blank map of the same size and topology
+    Clear,
+    // Grows or shrinks the map from its bottom right corner, keeping the cells that fit
+    Resize { row_count: usize, column_count: usize },
+    // Links two cells as portals, or unlinks them if they already are
+    TogglePortal((usize, usize), (usize, usize)),
+    // Overwrites a cell outright, for pasting
+    SetCell((usize, usize), CellContents),
+    // Sets a cell's elevation outright, for heightmaps. Clamped to 0..=MAX_ELEVATION.
+    SetElevation((usize, usize), i32),
+    // Stacks this many levels, adding empty ones on top or taking the top ones away
+    SetLevels(usize),
+    // Resizes to `row_count` rows in all split into `levels` levels, whatever the split
+    // was before, so snapshots replay the same into any grid
+    Reshape {
+        levels: usize,
+        row_count: usize,
+        column_count: usize,
+    },
+}
+
+// What a cell holds apart from its position, distance and portal link, which is what
+// gets copied and pasted
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CellContents {
+    pub cell_type: CellType,
+    pub terrain: Terrain,
+    pub one_way: Option<Heading>,
+    pub elevation: i32,
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub struct Cell {
+    pub cell_type: CellType,
+    pub terrain: Terrain,
+    pub cell_number: Option<f32>,
+    // Only enterable from one side when set
+    pub one_way: Option<Heading>,
+    // The other end of a portal, which counts as a neighbor wherever it is
+    pub portal: Option<(usize, usize)>,
+    // Height in levels from 0 to MAX_ELEVATION, stepping between levels costs extra
+    pub elevation: i32,
+    pub x_position: usize,
+    pub y_position: usize,
+    pub highlighted: bool,
+}
+
+#[derive(Debug)]
+pub struct Grid {
+    pub grid: Vec<Vec<Cell>>,
+    pub row_count_y: usize,
+    pub column_count_x: usize,
+    pub topology: &'static dyn Topology,
+    pub solver: Solver,
+    pub metric: Metric,
+    // Neighbors wrap across the edges, turning the map into a torus
+    pub wrap: bool,
+    // Floors stacked in the rows, each `level_rows` high with the ground floor at the top.
+    // Cells only neighbor cells on their own level, stairs are portals between levels.
+    pub levels: usize,
+    // Per cell step costs (from a cost_script::CostScript) used instead of the terrain
+    // costs, ignored if they're not the size of the grid
+    pub cell_costs: Option<Vec<Vec<f32>>>,
+    // Costs added on top of the step costs, for ones that keep changing like crowding.
+    // Also ignored if they're not the size of the grid, and they don't change the version.
+    pub extra_costs: Option<Vec<Vec<f32>>>,
+    // Changes whenever the map does, see `version`
+    version: u64,
+}
+
+// Versions come from one counter, so two grids never share one
+static NEXT_VERSION: AtomicU64 = AtomicU64::new(0);
+
+fn next_version() -> u64 {
+    NEXT_VERSION.fetch_add(1, Ordering::Relaxed)
+}
+
+impl Cell {
+    pub fn contents(&self) -> CellContents {
+        CellContents {
+            cell_type: self.cell_type,
+            terrain: self.terrain,
+            one_way: self.one_way,
+            elevation: self.elevation,
+        }
+    }
+}
+
+impl Grid {
+    pub fn new(row_count: usize, column_count: usize) -> Self {
+        let mut grid = Vec::with_capacity(row_count);
+        for y in 0..row_count {
+            let mut row = Vec::with_capacity(column_count);
+            for x in 0..column_count {
+                row.push(Cell {
+                    x_position: x,
+                    y_position: y,
+                    ..Default::default()
+                });
+            }
+            grid.push(row);
+        }
+        
+        Grid {
+            grid,
+            row_count_y: row_count,
+            column_count_x: column_count,
+            topology: &SquareTopology,
+            solver: Solver::default(),
+            metric: Metric::default(),
+            wrap: false,
+            levels: 1,
+            cell_costs: None,
+            extra_costs: None,
+            version: next_version(),
+        }
+    }
+
+    fn usable_cell_costs(&self) -> Option<&Vec<Vec<f32>>> {
+        self.cell_costs
+            .as_ref()
+            .filter(|costs| costs.len() == self.row_count_y && costs.iter().all(|row| row.len() == self.column_count_x))
+    }
+
+    // What stepping onto the cell costs
+    pub fn step_cost(&self, (col_x, row_y): (usize, usize)) -> f32 {
+        let cost = match self.usable_cell_costs() {
+            Some(costs) => costs[row_y][col_x],
+            None => self.grid[row_y][col_x].terrain.cost(),
+        };
+        cost + self.extra_cost((col_x, row_y))
+    }
+
+    pub fn extra_cost(&self, (col_x, row_y): (usize, usize)) -> f32 {
+        self.extra_costs
+            .as_ref()
+            .filter(|costs| costs.len() == self.row_count_y && costs.iter().all(|row| row.len() == self.column_count_x))
+            .map_or(0.0, |costs| costs[row_y][col_x])
+    }
+
+    // What stepping between two neighboring cells costs: the step cost of the one
+    // stepped onto times the step's length, plus SLOPE_COST of that for every level of
+    // height between them. Going down is as steep as going up. Portal jumps don't climb.
+    pub fn move_cost(&self, from: (usize, usize), to: (usize, usize)) -> f32 {
+        let climb = match self.is_portal_jump(from, to) {
+            true => 0,
+            false => (self.grid[to.1][to.0].elevation - self.grid[from.1][from.0].elevation).abs(),
+        };
+        self.step_cost(to) * self.step_length(from, to) * (1.0 + SLOPE_COST * climb as f32)
+    }
+
+    // Length of a step between two neighboring cells under the grid's metric, 1 for
+    // a straight one
+    pub fn step_length(&self, from: (usize, usize), to: (usize, usize)) -> f32 {
+        self.metric.step_length(self.is_diagonal_step(from, to))
+    }
+
+    pub fn is_diagonal_step(&self, from: (usize, usize), to: (usize, usize)) -> bool {
+        self.metric.allows_diagonals() && self.diagonal_neighbors(from).contains(&to)
+    }
+
+    pub fn level_rows(&self) -> usize {
+        self.row_count_y / self.levels
+    }
+
+    pub fn level_of(&self, (_, row_y): (usize, usize)) -> usize {
+        row_y / self.level_rows()
+    }
+
+    // Top left corner and size of a level in world space
+    pub fn level_area(&self, level: usize) -> (Vec2, Vec2) {
+        let above = self.topology.world_size(self.column_count_x, level * self.level_rows());
+        (vec2(0.0, above.y), self.topology.world_size(self.column_count_x, self.level_rows()))
+    }
+
+    // Runs a topology lookup for `cell` limited to its own level. Wrapping has to go
+    // around the level rather than the whole map, so then the level is handed over
+    // as a grid of its own.
+    fn on_level(
+        &self,
+        cell: (usize, usize),
+        lookup: impl Fn((usize, usize), usize) -> Vec<(usize, usize)>,
+    ) -> Vec<(usize, usize)> {
+        if !self.wrap || self.levels == 1 {
+            let level = self.level_of(cell);
+            let mut found = lookup(cell, self.row_count_y);
+            found.retain(|&other| self.level_of(other) == level);
+            return found;
+        }
+        let first_row = self.level_of(cell) * self.level_rows();
+        lookup((cell.0, cell.1 - first_row), self.level_rows())
+            .into_iter()
+            .map(|(col_x, row_y)| (col_x, row_y + first_row))
+            .collect()
+    }
+
+    // Cells sharing an edge with `cell` on the same level
+    pub fn neighbors(&self, cell: (usize, usize)) -> Vec<(usize, usize)> {
+        self.on_level(cell, |cell, rows| self.topology.neighbors(cell, self.column_count_x, rows, self.wrap))
+    }
+
+    pub fn diagonal_neighbors(&self, cell: (usize, usize)) -> Vec<(usize, usize)> {
+        self.on_level(cell, |cell, rows| {
+            self.topology.diagonal_neighbors(cell, self.column_count_x, rows, self.wrap)
+        })
+    }
+
+    // Same as `Topology::axis_neighbors`, leaving out the sides on other levels
+    pub fn axis_neighbors(&self, cell: (usize, usize)) -> Option<[topology::AxisNeighbors; 2]> {
+        if !self.wrap || self.levels == 1 {
+            let level = self.level_of(cell);
+            let axes = self.topology.axis_neighbors(cell, self.column_count_x, self.row_count_y, self.wrap)?;
+            return Some(axes.map(|sides| sides.map(|side| side.filter(|&other| self.level_of(other) == level))));
+        }
+        let first_row = self.level_of(cell) * self.level_rows();
+        let axes = self.topology.axis_neighbors(
+            (cell.0, cell.1 - first_row),
+            self.column_count_x,
+            self.level_rows(),
+            true,
+        )?;
+        Some(axes.map(|sides| sides.map(|side| side.map(|(col_x, row_y)| (col_x, row_y + first_row)))))
+    }
+
+    // Same as `Topology::distance`, within a level when wrapping
+    pub fn distance(&self, from: (usize, usize), to: (usize, usize)) -> f32 {
+        let (columns, rows) = (self.column_count_x, self.row_count_y);
+        if !self.wrap || self.levels == 1 || self.level_of(from) != self.level_of(to) {
+            return self.topology.distance(from, to, columns, rows, self.wrap, self.metric);
+        }
+        let first_row = self.level_of(from) * self.level_rows();
+        let local = |(col_x, row_y): (usize, usize)| (col_x, row_y - first_row);
+        self.topology
+            .distance(local(from), local(to), columns, self.level_rows(), true, self.metric)
+    }
+
+    // Whether any two cells are at different heights
+    pub fn has_slopes(&self) -> bool {
+        let elevation = self.grid[0][0].elevation;
+        self.grid.iter().flatten().any(|cell| cell.elevation != elevation)
+    }
+
+    // Lowest step cost anywhere on the map
+    pub fn cheapest_step_cost(&self) -> f32 {
+        match self.usable_cell_costs() {
+            Some(costs) => costs.iter().flatten().copied().fold(f32::INFINITY, f32::min),
+            None => Terrain::Road.cost(),
+        }
+    }
+
+    pub fn get_neighbor_coordinates(&self, target: &Cell) -> Vec<(usize, usize)> {
+        let position = (target.x_position, target.y_position);
+        let mut neighbors = self.neighbors(position);
+        if self.metric.allows_diagonals() {
+            // No squeezing between two barriers that touch at a corner, or around the
+            // corner of one
+            let is_barrier = |col_x: usize, row_y: usize| self.grid[row_y][col_x].cell_type == CellType::Barrier;
+            let diagonals = self.diagonal_neighbors(position);
+            neighbors.extend(
+                diagonals
+                    .into_iter()
+                    .filter(|&(col_x, row_y)| !is_barrier(col_x, position.1) && !is_barrier(position.0, row_y)),
+            );
+        }
+        if let Some(twin) = target.portal.filter(|twin| !neighbors.contains(twin)) {
+            neighbors.push(twin);
+        }
+        neighbors
+    }
+
+    pub fn is_portal_jump(&self, from: (usize, usize), to: (usize, usize)) -> bool {
+        self.grid[from.1][from.0].portal == Some(to)
+    }
+
+    // Whether a step between two neighboring cells is allowed by the one-way cell it
+    // lands on, if any. Coming out of a portal is always allowed. Barriers are up to
+    // the caller.
+    pub fn can_step(&self, from: (usize, usize), to: (usize, usize)) -> bool {
+        match self.grid[to.1][to.0].one_way {
+            Some(_) if self.is_portal_jump(from, to) => true,
+            Some(heading) => self.get_direction(from, to).dot(heading.vector()) > 1e-3,
+            None => true,
+        }
+    }
+
+    // Neighbors the target can be left for in one step
+    pub fn get_step_neighbors(&self, target: &Cell) -> Vec<(usize, usize)> {
+        let position = (target.x_position, target.y_position);
+        let mut neighbors = self.get_neighbor_coordinates(target);
+        neighbors.retain(|&neighbor| self.can_step(position, neighbor));
+        neighbors
+    }
+
+    // Neighbors the target can be reached from in one step, which is the way distances
+    // spread out from the sources
+    pub fn get_entering_neighbors(&self, target: &Cell) -> Vec<(usize, usize)> {
+        let position = (target.x_position, target.y_position);
+        let mut neighbors = self.get_neighbor_coordinates(target);
+        neighbors.retain(|&neighbor| self.can_step(neighbor, position));
+        neighbors
+    }
+
+    pub fn get_world_size(&self) -> Vec2 {
+        self.topology.world_size(self.column_count_x, self.row_count_y)
+    }
+
+    // Cell under a world position, if it's on the grid
+    pub fn get_cell_at(&self, world_pos: Vec2) -> Option<(usize, usize)> {
+        topology::resolve_cell(self.topology.cell_at(world_pos), self.column_count_x, self.row_count_y, self.wrap)
+    }
+
+    // World space direction between two neighboring cells, taking the short way
+    // around when wrapping
+    pub fn get_direction(&self, from: (usize, usize), to: (usize, usize)) -> Vec2 {
+        let mut offset = self.topology.cell_center(to) - self.topology.cell_center(from);
+        if self.wrap {
+            // Levels wrap around on their own
+            let size = self.level_area(0).1;
+            if offset.x.abs() * 2.0 > size.x {
+                offset.x -= offset.x.signum() * size.x;
+            }
+            if offset.y.abs() * 2.0 > size.y {
+                offset.y -= offset.y.signum() * size.y;
+            }
+        }
+        offset.normalize_or_zero()
+    }
+
+    // Bresenham line between two cells, both ends included. On a wrapping grid it takes
+    // the short way around.
+    pub fn get_line_cells(&self, from: (usize, usize), to: (usize, usize)) -> Vec<(usize, usize)> {
+        let (column_count, row_count) = (self.column_count_x as i64, self.row_count_y as i64);
+        let shortest = |delta: i64, size: i64| {
+            if self.wrap && delta.abs() * 2 > size {
+                delta - delta.signum() * size
+            } else {
+                delta
+            }
+        };
+        let delta_x = shortest(to.0 as i64 - from.0 as i64, column_count);
+        let delta_y = shortest(to.1 as i64 - from.1 as i64, row_count);
+
+        let (mut x, mut y) = (from.0 as i64, from.1 as i64);
+        let (step_x, step_y) = (delta_x.signum(), delta_y.signum());
+        let (distance_x, distance_y) = (delta_x.abs(), -delta_y.abs());
+        let mut error = distance_x + distance_y;
+        let mut cells = Vec::new();
+        for _ in 0..=distance_x - distance_y {
+            cells.push((x.rem_euclid(column_count) as usize, y.rem_euclid(row_count) as usize));
+            if (x, y) == (from.0 as i64 + delta_x, from.1 as i64 + delta_y) {
+                break;
+            }
+            let doubled = 2 * error;
+            if doubled >= distance_y {
+                error += distance_y;
+                x += step_x;
+            }
+            if doubled <= distance_x {
+                error += distance_x;
+                y += step_y;
+            }
+        }
+        cells
+    }
+
+    pub fn get_cell_with_lowest_cell_number(cells: Vec<Cell>) -> Option<Cell> {
+        cells.into_iter().min_by(|a, b| a.cell_number.partial_cmp(&b.cell_number).unwrap_or(std::cmp::Ordering::Equal))
+    }
+
+
+    pub fn get_coordinates_of_type(&self, cell_type: CellType) -> Vec<(usize, usize)> {
+        self.grid
+            .iter()
+            .flatten()
+            .filter(|cell| cell.cell_type == cell_type)
+            .map(|cell| (cell.x_position, cell.y_position))
+            .collect()
+    }
+
+    // Distances from the nearest seed, using the grid's solver. Seeds start at 1.
+    pub fn integrate(&self, seed_coordinates: &[(usize, usize)]) -> Vec<Vec<Option<f32>>> {
+        solver::integrate(self, seed_coordinates, self.solver)
+    }
+
+    // Same, along with the seed each distance came from
+    pub fn integrate_with_origins(&self, seed_coordinates: &[(usize, usize)]) -> (Vec<Vec<Option<f32>>>, Origins) {
+        solver::integrate_with_origins(self, seed_coordinates, self.solver)
+    }
+
+    // Identifies the state of the map apart from where the sources are, for telling
+    // whether something worked out from it is stale. Edits change it, anything that
+    // changes the cells, costs or settings directly calls `mark_changed`.
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    pub fn mark_changed(&mut self) {
+        self.version = next_version();
+    }
+
+    pub fn apply_edit(&mut self, edit: Edit) {
+        // A source placed on open ground leaves it open again when it's taken away
+        let only_sources = match edit {
+            Edit::ToggleSource((col_x, row_y)) => {
+                matches!(self.grid[row_y][col_x].cell_type, CellType::Source | CellType::Inactive)
+            }
+            _ => false,
+        };
+        if !only_sources {
+            self.mark_changed();
+        }
+
+        match edit {
+            Edit::Paint(paint_tool, (col_x, row_y)) => {
+                let cell = &mut self.grid[row_y][col_x];
+                match paint_tool {
+                    PaintTool::Barrier => match cell.cell_type {
+                        CellType::Barrier => {
+                            cell.cell_type = CellType::Inactive;
+                        }
+
+                        _ => {
+                            cell.cell_type = CellType::Barrier;
+                        }
+                    },
+
+                    PaintTool::Danger => match cell.cell_type {
+                        CellType::Danger => {
+                            cell.cell_type = CellType::Inactive;
+                        }
+
+                        _ => {
+                            cell.cell_type = CellType::Danger;
+                        }
+                    },
+
+                    PaintTool::Spawner => match cell.cell_type {
+                        CellType::Spawner => {
+                            cell.cell_type = CellType::Inactive;
+                        }
+
+                        _ => {
+                            cell.cell_type = CellType::Spawner;
+                        }
+                    },
+
+                    // Painting the same terrain twice resets it, like barriers
+                    PaintTool::Terrain(terrain) if cell.terrain == terrain => {
+                        cell.terrain = Terrain::default();
+                    }
+
+                    PaintTool::Terrain(terrain) => {
+                        cell.terrain = terrain;
+                    }
+
+                    // Same here, painting the same heading again makes it two-way
+                    PaintTool::OneWay(heading) if cell.one_way == Some(heading) => {
+                        cell.one_way = None;
+                    }
+
+                    PaintTool::OneWay(heading) => {
+                        cell.one_way = Some(heading);
+                    }
+
+                    PaintTool::Raise => {
+                        cell.elevation = (cell.elevation + 1).min(MAX_ELEVATION);
+                    }
+
+                    PaintTool::Lower => {
+                        cell.elevation = (cell.elevation - 1).max(0);
+                    }
+                }
+            }
+
+            Edit::ToggleSource((col_x, row_y)) => {
+                let cell = &mut self.grid[row_y][col_x];
+                match cell.cell_type {
+                    CellType::Source => {
+                        cell.cell_type = CellType::Inactive;
+                    }
+
+                    _ => {
+                        cell.cell_number = Some(1.0);
+                        cell.cell_type = CellType::Source;
+                    }
+                }
+            }
+
+            Edit::Generate { kind, source_count, seed } => {
+                quad_rand::srand(seed);
+                let mut generated = mapgen::generate(kind, self.row_count_y, self.column_count_x, source_count);
+                generated.topology = self.topology;
+                generated.solver = self.solver;
+                generated.metric = self.metric;
+                generated.wrap = self.wrap;
+                generated.levels = self.levels;
+                *self = generated;
+            }
+
+            Edit::ToggleWrap => {
+                self.wrap = !self.wrap;
+            }
+
+            Edit::SetWrap(wrap) => {
+                self.wrap = wrap;
+            }
+
+            Edit::Clear => {
+                let mut cleared = Grid::new(self.row_count_y, self.column_count_x);
+                cleared.topology = self.topology;
+                cleared.solver = self.solver;
+                cleared.metric = self.metric;
+                cleared.levels = self.levels;
+                cleared.wrap = self.wrap;
+                *self = cleared;
+            }
+
+            Edit::Resize { row_count, column_count } => {
+                self.resize(row_count, column_count);
+            }
+
+            Edit::SetCell((col_x, row_y), contents) => {
+                let cell = &mut self.grid[row_y][col_x];
+                cell.cell_type = contents.cell_type;
+                cell.terrain = contents.terrain;
+                cell.one_way = contents.one_way;
+                cell.elevation = contents.elevation;
+                if contents.cell_type == CellType::Source {
+                    cell.cell_number = Some(1.0);
+                }
+            }
+
+            Edit::SetElevation((col_x, row_y), elevation) => {
+                self.grid[row_y][col_x].elevation = elevation.clamp(0, MAX_ELEVATION);
+            }
+
+            Edit::SetLevels(levels) => {
+                self.restack(levels.max(1), self.level_rows(), self.column_count_x);
+            }
+
+            Edit::Reshape { levels, row_count, column_count } => {
+                let levels = levels.clamp(1, row_count.max(1));
+                self.restack(levels, row_count / levels, column_count);
+            }
+
+            Edit::TogglePortal(from, to) => {
+                let linked = self.is_portal_jump(from, to);
+                // Cells have one twin at most, so any old links go first
+                self.unlink_portal(from);
+                self.unlink_portal(to);
+                if !linked && from != to {
+                    self.grid[from.1][from.0].portal = Some(to);
+                    self.grid[to.1][to.0].portal = Some(from);
+                }
+            }
+        }
+    }
+
+    fn unlink_portal(&mut self, (col_x, row_y): (usize, usize)) {
+        if let Some((twin_x, twin_y)) = self.grid[row_y][col_x].portal.take() {
+            self.grid[twin_y][twin_x].portal = None;
+        }
+    }
+
+    // Each linked pair once, as (first, second) in row order
+    pub fn portal_pairs(&self) -> Vec<((usize, usize), (usize, usize))> {
+        self.grid
+            .iter()
+            .flatten()
+            .filter_map(|cell| Some(((cell.x_position, cell.y_position), cell.portal?)))
+            .filter(|&((col_x, row_y), (twin_x, twin_y))| (row_y, col_x) < (twin_y, twin_x))
+            .collect()
+    }
+
+    // Every level keeps its top rows. Rows that don't make up a whole level are dropped.
+    pub fn resize(&mut self, row_count: usize, column_count: usize) {
+        let levels = self.levels.clamp(1, row_count.max(1));
+        self.restack(levels, row_count / levels, column_count);
+    }
+
+    // Rebuilds the grid as `levels` levels of `level_rows` × `column_count` cells, keeping
+    // what fits of the levels there were
+    fn restack(&mut self, levels: usize, level_rows: usize, column_count: usize) {
+        let old_level_rows = self.level_rows();
+        // Where a cell ends up, if it's kept
+        let moved = |(col_x, row_y): (usize, usize)| {
+            let (level, row_in_level) = (row_y / old_level_rows, row_y % old_level_rows);
+            (col_x < column_count && level < levels && row_in_level < level_rows)
+                .then_some((col_x, level * level_rows + row_in_level))
+        };
+        let mut resized = Grid::new(levels * level_rows, column_count);
+        for cell in self.grid.iter().flatten() {
+            let Some((col_x, row_y)) = moved((cell.x_position, cell.y_position)) else {
+                continue;
+            };
+            resized.grid[row_y][col_x] = Cell {
+                x_position: col_x,
+                y_position: row_y,
+                // Portals lose their other end when it's cut off
+                portal: cell.portal.and_then(moved),
+                ..*cell
+            };
+        }
+        resized.topology = self.topology;
+        resized.solver = self.solver;
+        resized.metric = self.metric;
+        resized.wrap = self.wrap;
+        resized.levels = levels;
+        *self = resized;
+    }
+
+    // Movement cost of walking a path, the starting cell is free
+    pub fn path_cost(&self, path: &[(usize, usize)]) -> f32 {
+        path.windows(2)
+            .map(|step| self.move_cost(step[0], step[1]))
+            .sum()
+    }
+
+    // Forgets every distance, before working them out again or once there's nothing
+    // left to work them out from. Sources go back to 1.
+    pub fn invalidate_field(&mut self) {
+        for cell in self.grid.iter_mut().flatten() {
+            cell.cell_number = (cell.cell_type == CellType::Source).then_some(1.0);
+        }
+    }
+
+    // Also returns which source each cell's distance came from. Cells no source reaches
+    // any more are cleared rather than keeping the numbers from before.
+    pub fn source_cells(&mut self, source_coordinates: &[(usize, usize)]) -> (Vec<Vec<Option<f32>>>, Origins) {
+        let (best_costs, origins) = self.integrate_with_origins(source_coordinates);
+        self.invalidate_field();
+
+        for (row_y, row) in self.grid.iter_mut().enumerate() {
+            for (col_x, cell) in row.iter_mut().enumerate() {
+                if let Some(cost) = best_costs[row_y][col_x] {
+                    cell.cell_number = Some(cost);
+                }
+            }
+        }
+
+        (best_costs, origins)
+    }
+
+    // Brings distances and origins from `integrate_with_origins` up to date after the cells in `changed` turned
+    // into barriers, opened back up or changed cost
+    pub fn repair_integration(
+        &self,
+        distances: &mut [Vec<Option<f32>>],
+        origins: &mut [Vec<Option<(usize, usize)>>],
+        seed_coordinates: &[(usize, usize)],
+        changed: &[(usize, usize)],
+    ) {
+        solver::repair(self, distances, origins, seed_coordinates, changed, self.solver);
+    }
+
+    // Replaces every cell number, cells without a distance get none
+    pub fn set_cell_numbers(&mut self, distances: &[Vec<Option<f32>>]) {
+        for (cell, distance) in self.grid.iter_mut().flatten().zip(distances.iter().flatten()) {
+            cell.cell_number = *distance;
+        }
+    }
+}
@@ -0,0 +1,116 @@
+use macroquad::prelude::*;
+
+use crate::keymap::{Action, Keymap};
+
+// How long a finger has to stay put to place a source instead of painting
+const LONG_PRESS_SECONDS: f64 = 0.5;
+// How far (in pixels) a finger can drift and still count as staying put
+const LONG_PRESS_TOLERANCE: f32 = 10.0;
+
+// What the pointer asks for this frame, whether it's a mouse or a finger. The mouse
+// buttons for each come from the keymap.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PointerActions {
+    pub position: Vec2,
+    // Held, or a one finger tap or drag
+    pub paint: bool,
+    // Held, or a long press
+    pub place_source: bool,
+    pub pin_path: bool,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum TouchGesture {
+    // Finger is down but hasn't moved or been held long enough to decide yet
+    Undecided { started_at: f64, start: Vec2 },
+    Dragging,
+    // The long press already placed its source, ignore the finger until it lifts
+    LongPressed,
+}
+
+// Turns mouse and touch input into the same set of actions, so the render loop
+// doesn't care which one it's getting
+#[derive(Debug)]
+pub struct Pointer {
+    position: Vec2,
+    // Where the mouse was last seen, so a finger's position sticks until the mouse moves
+    last_mouse: Vec2,
+    touch: Option<(u64, TouchGesture)>,
+}
+
+impl Pointer {
+    pub fn new() -> Self {
+        // Touches would otherwise also show up as left clicks and paint straight away
+        simulate_mouse_with_touch(false);
+        let mouse = Vec2::from(mouse_position());
+        Pointer {
+            position: mouse,
+            last_mouse: mouse,
+            touch: None,
+        }
+    }
+
+    pub fn update(&mut self, now: f64, keymap: &Keymap) -> PointerActions {
+        let touches = touches();
+        if touches.is_empty() {
+            self.touch = None;
+            return self.mouse_actions(keymap);
+        }
+
+        let mut actions = PointerActions::default();
+        // Only the first finger counts, extra ones are ignored
+        let touch = match self.touch.and_then(|(id, _)| touches.iter().find(|touch| touch.id == id)) {
+            Some(touch) => touch,
+            None => &touches[0],
+        };
+        self.position = touch.position;
+
+        let gesture = match self.touch {
+            Some((id, gesture)) if id == touch.id => gesture,
+            _ => TouchGesture::Undecided {
+                started_at: now,
+                start: touch.position,
+            },
+        };
+        let lifted = matches!(touch.phase, TouchPhase::Ended | TouchPhase::Cancelled);
+
+        let gesture = match gesture {
+            TouchGesture::Undecided { started_at, start } => {
+                if touch.position.distance(start) > LONG_PRESS_TOLERANCE {
+                    actions.paint = true;
+                    TouchGesture::Dragging
+                } else if now - started_at >= LONG_PRESS_SECONDS {
+                    actions.place_source = true;
+                    TouchGesture::LongPressed
+                } else {
+                    // A quick tap paints the cell it landed on
+                    actions.paint = touch.phase == TouchPhase::Ended;
+                    gesture
+                }
+            }
+            TouchGesture::Dragging => {
+                actions.paint = touch.phase != TouchPhase::Cancelled;
+                gesture
+            }
+            TouchGesture::LongPressed => gesture,
+        };
+
+        self.touch = if lifted { None } else { Some((touch.id, gesture)) };
+        actions.position = self.position;
+        actions
+    }
+
+    fn mouse_actions(&mut self, keymap: &Keymap) -> PointerActions {
+        let mouse = Vec2::from(mouse_position());
+        if mouse != self.last_mouse {
+            self.position = mouse;
+            self.last_mouse = mouse;
+        }
+        PointerActions {
+            position: self.position,
+            paint: keymap.down(Action::Paint),
+            place_source: keymap.down(Action::PlaceSource),
+            pin_path: keymap.pressed(Action::PinPath),
+        }
+    }
+}
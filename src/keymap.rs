@@ -0,0 +1,581 @@
+use macroquad::prelude::*;
+
+// Everything the keyboard and mouse can do, bound to keys in one place
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    ToolBarrier,
+    ToolRoad,
+    ToolGrass,
+    ToolMud,
+    ToolWater,
+    ToolDanger,
+    ToolOneWay,
+    ToolSpawner,
+    ToolRaise,
+    ToolLower,
+    TurnOneWay,
+    Paint,
+    PlaceSource,
+    PinPath,
+    PreviewPath,
+    Maze,
+    Caves,
+    Noise,
+    // Held while generating a map to scatter sources over it
+    ScatterSources,
+    // Takes away every source, which leaves the field empty
+    ClearField,
+    ClearMap,
+    Wrap,
+    Solver,
+    Metric,
+    Gpu,
+    Letterbox,
+    ZoomIn,
+    ZoomOut,
+    ResetView,
+    // Held while dragging to move the view instead of painting
+    Pan,
+    View,
+    Streamlines,
+    // Blurs the directions so agents turn smoothly, and how much
+    Smooth,
+    SmoothStrength,
+    Gradient,
+    Inspector,
+    Perf,
+    Astar,
+    StepMode,
+    StepRing,
+    AddColumn,
+    RemoveColumn,
+    AddRow,
+    RemoveRow,
+    // Moves between levels, going up past the top one adds a level
+    LevelUp,
+    LevelDown,
+    RemoveLevel,
+    // Links the hovered cell to the one above it, or below it on the top level
+    Stairs,
+    DangerDown,
+    DangerUp,
+    SpawnAgents,
+    ClearAgents,
+    SpawnSlower,
+    SpawnFaster,
+    // What agents do once they arrive: stay, despawn or start over
+    Arrival,
+    // Feeds how crowded cells are back into the directions
+    Crowding,
+    // Writes the run's trips out and starts a new run
+    EndRun,
+    AddWaypoint,
+    SpawnObstacle,
+    ClearObstacles,
+    LinkPortal,
+    // Dragging picks a rectangle instead of painting while this is on
+    SelectMode,
+    // Painting flood-fills the region under the pointer while this is on
+    FillMode,
+    Copy,
+    Cut,
+    Paste,
+    FlipHorizontal,
+    FlipVertical,
+    Symmetry,
+    Record,
+    Replay,
+    ReplayStep,
+    SaveMap,
+    LoadMap,
+    LoadHeightmap,
+    Screenshot,
+    ExportField,
+    CaptureGif,
+}
+
+impl Action {
+    pub const ALL: [Action; 78] = [
+        Action::ToolBarrier,
+        Action::ToolRoad,
+        Action::ToolGrass,
+        Action::ToolMud,
+        Action::ToolWater,
+        Action::ToolDanger,
+        Action::ToolOneWay,
+        Action::ToolSpawner,
+        Action::ToolRaise,
+        Action::ToolLower,
+        Action::TurnOneWay,
+        Action::Paint,
+        Action::PlaceSource,
+        Action::PinPath,
+        Action::PreviewPath,
+        Action::Maze,
+        Action::Caves,
+        Action::Noise,
+        Action::ScatterSources,
+        Action::ClearField,
+        Action::ClearMap,
+        Action::Wrap,
+        Action::Solver,
+        Action::Metric,
+        Action::Gpu,
+        Action::Letterbox,
+        Action::ZoomIn,
+        Action::ZoomOut,
+        Action::ResetView,
+        Action::Pan,
+        Action::View,
+        Action::Streamlines,
+        Action::Smooth,
+        Action::SmoothStrength,
+        Action::Gradient,
+        Action::Inspector,
+        Action::Perf,
+        Action::Astar,
+        Action::StepMode,
+        Action::StepRing,
+        Action::AddColumn,
+        Action::RemoveColumn,
+        Action::AddRow,
+        Action::RemoveRow,
+        Action::LevelUp,
+        Action::LevelDown,
+        Action::RemoveLevel,
+        Action::Stairs,
+        Action::DangerDown,
+        Action::DangerUp,
+        Action::SpawnAgents,
+        Action::ClearAgents,
+        Action::SpawnSlower,
+        Action::SpawnFaster,
+        Action::Arrival,
+        Action::Crowding,
+        Action::EndRun,
+        Action::AddWaypoint,
+        Action::SpawnObstacle,
+        Action::ClearObstacles,
+        Action::LinkPortal,
+        Action::SelectMode,
+        Action::FillMode,
+        Action::Copy,
+        Action::Cut,
+        Action::Paste,
+        Action::FlipHorizontal,
+        Action::FlipVertical,
+        Action::Symmetry,
+        Action::Record,
+        Action::Replay,
+        Action::ReplayStep,
+        Action::SaveMap,
+        Action::LoadMap,
+        Action::LoadHeightmap,
+        Action::Screenshot,
+        Action::ExportField,
+        Action::CaptureGif,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::ToolBarrier => "tool_barrier",
+            Action::ToolRoad => "tool_road",
+            Action::ToolGrass => "tool_grass",
+            Action::ToolMud => "tool_mud",
+            Action::ToolWater => "tool_water",
+            Action::ToolDanger => "tool_danger",
+            Action::ToolOneWay => "tool_one_way",
+            Action::ToolSpawner => "tool_spawner",
+            Action::ToolRaise => "tool_raise",
+            Action::ToolLower => "tool_lower",
+            Action::TurnOneWay => "turn_one_way",
+            Action::Paint => "paint",
+            Action::PlaceSource => "place_source",
+            Action::PinPath => "pin_path",
+            Action::PreviewPath => "preview_path",
+            Action::Maze => "maze",
+            Action::Caves => "caves",
+            Action::Noise => "noise",
+            Action::ScatterSources => "scatter_sources",
+            Action::ClearField => "clear_field",
+            Action::ClearMap => "clear_map",
+            Action::Wrap => "wrap",
+            Action::Solver => "solver",
+            Action::Metric => "metric",
+            Action::Gpu => "gpu",
+            Action::Letterbox => "letterbox",
+            Action::ZoomIn => "zoom_in",
+            Action::ZoomOut => "zoom_out",
+            Action::ResetView => "reset_view",
+            Action::Pan => "pan",
+            Action::View => "view",
+            Action::Streamlines => "streamlines",
+            Action::Smooth => "smooth",
+            Action::SmoothStrength => "smooth_strength",
+            Action::Gradient => "gradient",
+            Action::Inspector => "inspector",
+            Action::Perf => "perf",
+            Action::Astar => "astar",
+            Action::StepMode => "step_mode",
+            Action::StepRing => "step_ring",
+            Action::AddColumn => "add_column",
+            Action::RemoveColumn => "remove_column",
+            Action::AddRow => "add_row",
+            Action::RemoveRow => "remove_row",
+            Action::LevelUp => "level_up",
+            Action::LevelDown => "level_down",
+            Action::RemoveLevel => "remove_level",
+            Action::Stairs => "stairs",
+            Action::DangerDown => "danger_down",
+            Action::DangerUp => "danger_up",
+            Action::SpawnAgents => "spawn_agents",
+            Action::ClearAgents => "clear_agents",
+            Action::SpawnSlower => "spawn_slower",
+            Action::SpawnFaster => "spawn_faster",
+            Action::Arrival => "arrival",
+            Action::Crowding => "crowding",
+            Action::EndRun => "end_run",
+            Action::AddWaypoint => "add_waypoint",
+            Action::SpawnObstacle => "spawn_obstacle",
+            Action::ClearObstacles => "clear_obstacles",
+            Action::LinkPortal => "link_portal",
+            Action::SelectMode => "select_mode",
+            Action::FillMode => "fill_mode",
+            Action::Copy => "copy",
+            Action::Cut => "cut",
+            Action::Paste => "paste",
+            Action::FlipHorizontal => "flip_horizontal",
+            Action::FlipVertical => "flip_vertical",
+            Action::Symmetry => "symmetry",
+            Action::Record => "record",
+            Action::Replay => "replay",
+            Action::ReplayStep => "replay_step",
+            Action::SaveMap => "save_map",
+            Action::LoadMap => "load_map",
+            Action::LoadHeightmap => "load_heightmap",
+            Action::Screenshot => "screenshot",
+            Action::ExportField => "export_field",
+            Action::CaptureGif => "capture_gif",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+// Names used in the keys file, and in the side panel
+const BINDING_NAMES: [(&str, Binding); 82] = [
+    ("A", Binding::Key(KeyCode::A)),
+    ("B", Binding::Key(KeyCode::B)),
+    ("C", Binding::Key(KeyCode::C)),
+    ("D", Binding::Key(KeyCode::D)),
+    ("E", Binding::Key(KeyCode::E)),
+    ("F", Binding::Key(KeyCode::F)),
+    ("G", Binding::Key(KeyCode::G)),
+    ("H", Binding::Key(KeyCode::H)),
+    ("I", Binding::Key(KeyCode::I)),
+    ("J", Binding::Key(KeyCode::J)),
+    ("K", Binding::Key(KeyCode::K)),
+    ("L", Binding::Key(KeyCode::L)),
+    ("M", Binding::Key(KeyCode::M)),
+    ("N", Binding::Key(KeyCode::N)),
+    ("O", Binding::Key(KeyCode::O)),
+    ("P", Binding::Key(KeyCode::P)),
+    ("Q", Binding::Key(KeyCode::Q)),
+    ("R", Binding::Key(KeyCode::R)),
+    ("S", Binding::Key(KeyCode::S)),
+    ("T", Binding::Key(KeyCode::T)),
+    ("U", Binding::Key(KeyCode::U)),
+    ("V", Binding::Key(KeyCode::V)),
+    ("W", Binding::Key(KeyCode::W)),
+    ("X", Binding::Key(KeyCode::X)),
+    ("Y", Binding::Key(KeyCode::Y)),
+    ("Z", Binding::Key(KeyCode::Z)),
+    ("0", Binding::Key(KeyCode::Key0)),
+    ("1", Binding::Key(KeyCode::Key1)),
+    ("2", Binding::Key(KeyCode::Key2)),
+    ("3", Binding::Key(KeyCode::Key3)),
+    ("4", Binding::Key(KeyCode::Key4)),
+    ("5", Binding::Key(KeyCode::Key5)),
+    ("6", Binding::Key(KeyCode::Key6)),
+    ("7", Binding::Key(KeyCode::Key7)),
+    ("8", Binding::Key(KeyCode::Key8)),
+    ("9", Binding::Key(KeyCode::Key9)),
+    ("F1", Binding::Key(KeyCode::F1)),
+    ("F2", Binding::Key(KeyCode::F2)),
+    ("F3", Binding::Key(KeyCode::F3)),
+    ("F4", Binding::Key(KeyCode::F4)),
+    ("F5", Binding::Key(KeyCode::F5)),
+    ("F6", Binding::Key(KeyCode::F6)),
+    ("F7", Binding::Key(KeyCode::F7)),
+    ("F8", Binding::Key(KeyCode::F8)),
+    ("F9", Binding::Key(KeyCode::F9)),
+    ("F10", Binding::Key(KeyCode::F10)),
+    ("F11", Binding::Key(KeyCode::F11)),
+    ("F12", Binding::Key(KeyCode::F12)),
+    ("[", Binding::Key(KeyCode::LeftBracket)),
+    ("]", Binding::Key(KeyCode::RightBracket)),
+    ("-", Binding::Key(KeyCode::Minus)),
+    ("=", Binding::Key(KeyCode::Equal)),
+    (",", Binding::Key(KeyCode::Comma)),
+    (".", Binding::Key(KeyCode::Period)),
+    ("/", Binding::Key(KeyCode::Slash)),
+    (";", Binding::Key(KeyCode::Semicolon)),
+    ("'", Binding::Key(KeyCode::Apostrophe)),
+    ("\\", Binding::Key(KeyCode::Backslash)),
+    ("`", Binding::Key(KeyCode::GraveAccent)),
+    ("Space", Binding::Key(KeyCode::Space)),
+    ("Tab", Binding::Key(KeyCode::Tab)),
+    ("Enter", Binding::Key(KeyCode::Enter)),
+    ("Backspace", Binding::Key(KeyCode::Backspace)),
+    ("Delete", Binding::Key(KeyCode::Delete)),
+    ("Insert", Binding::Key(KeyCode::Insert)),
+    ("Home", Binding::Key(KeyCode::Home)),
+    ("End", Binding::Key(KeyCode::End)),
+    ("PageUp", Binding::Key(KeyCode::PageUp)),
+    ("PageDown", Binding::Key(KeyCode::PageDown)),
+    ("Left", Binding::Key(KeyCode::Left)),
+    ("Right", Binding::Key(KeyCode::Right)),
+    ("Up", Binding::Key(KeyCode::Up)),
+    ("Down", Binding::Key(KeyCode::Down)),
+    ("LeftShift", Binding::Key(KeyCode::LeftShift)),
+    ("RightShift", Binding::Key(KeyCode::RightShift)),
+    ("LeftControl", Binding::Key(KeyCode::LeftControl)),
+    ("RightControl", Binding::Key(KeyCode::RightControl)),
+    ("LeftAlt", Binding::Key(KeyCode::LeftAlt)),
+    ("RightAlt", Binding::Key(KeyCode::RightAlt)),
+    ("MouseLeft", Binding::Mouse(MouseButton::Left)),
+    ("MouseRight", Binding::Mouse(MouseButton::Right)),
+    ("MouseMiddle", Binding::Mouse(MouseButton::Middle)),
+];
+
+// Used for anything the keys file doesn't mention, in the same format. No key is
+// bound to two actions.
+const DEFAULT_KEYS: &str = "
+tool_barrier 1
+tool_road 2
+tool_grass 3
+tool_mud 4
+tool_water 5
+tool_danger 6
+tool_one_way 7
+tool_spawner 8
+tool_raise 9
+tool_lower 0
+turn_one_way `
+paint MouseLeft
+place_source MouseRight
+pin_path MouseMiddle
+preview_path LeftShift RightShift
+maze M
+caves V
+noise N
+scatter_sources LeftAlt RightAlt
+clear_field Space
+clear_map R
+wrap W
+solver S
+metric D
+gpu U
+letterbox L
+zoom_in =
+zoom_out -
+reset_view Home
+pan LeftControl RightControl
+view Tab
+streamlines Z
+smooth ;
+smooth_strength '
+gradient H
+inspector I
+perf J
+astar A
+step_mode P
+step_ring F1
+add_column Right
+remove_column Left
+add_row Down
+remove_row Up
+level_up PageUp
+level_down PageDown
+remove_level F11
+stairs End
+danger_down [
+danger_up ]
+spawn_agents G
+clear_agents C
+spawn_slower ,
+spawn_faster .
+arrival Insert
+crowding \\
+end_run F12
+add_waypoint O
+spawn_obstacle Enter
+clear_obstacles Backspace
+link_portal T
+select_mode B
+fill_mode /
+copy Y
+cut X
+paste E
+flip_horizontal F
+flip_vertical Q
+symmetry K
+record F5
+replay F6
+replay_step F7
+save_map F2
+load_map F3
+load_heightmap F8
+screenshot F9
+export_field F4
+capture_gif F10
+";
+
+#[derive(Debug)]
+pub struct Keymap {
+    bindings: Vec<(Action, Vec<Binding>)>,
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        let mut keymap = Keymap {
+            bindings: Action::ALL.iter().map(|&action| (action, Vec::new())).collect(),
+        };
+        keymap.apply(DEFAULT_KEYS).unwrap();
+        keymap
+    }
+}
+
+impl Keymap {
+    // The defaults with the bindings from a keys file on top. Every line is an action
+    // followed by the keys (or mouse buttons) for it, which replace its default ones.
+    // Blank lines and lines starting with `;` are skipped. A key left bound to two
+    // actions is an error, moving a key over means rebinding the action it came from.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut keymap = Keymap::default();
+        keymap.apply(text)?;
+        keymap.check_duplicates()?;
+        Ok(keymap)
+    }
+
+    fn check_duplicates(&self) -> Result<(), String> {
+        for (index, (action, bindings)) in self.bindings.iter().enumerate() {
+            for binding in bindings {
+                if let Some((other, _)) = self.bindings[index + 1..].iter().find(|(_, other)| other.contains(binding)) {
+                    return Err(format!(
+                        "{} is bound to both {} and {}",
+                        binding_name(*binding),
+                        action.name(),
+                        other.name()
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, text: &str) -> Result<(), String> {
+        for (line_number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let name = parts.next().unwrap_or_default();
+            let Some((_, bindings)) = self.bindings.iter_mut().find(|(action, _)| action.name() == name) else {
+                return Err(format!("line {}: unknown action \"{}\"", line_number + 1, name));
+            };
+            *bindings = parts
+                .map(|key| {
+                    BINDING_NAMES
+                        .into_iter()
+                        .find(|(binding_name, _)| binding_name.eq_ignore_ascii_case(key))
+                        .map(|(_, binding)| binding)
+                        .ok_or_else(|| format!("line {}: unknown key \"{}\"", line_number + 1, key))
+                })
+                .collect::<Result<_, _>>()?;
+        }
+        Ok(())
+    }
+
+    fn bindings(&self, action: Action) -> &[Binding] {
+        self.bindings
+            .iter()
+            .find(|(bound, _)| *bound == action)
+            .map_or(&[], |(_, bindings)| bindings)
+    }
+
+    // Went down this frame
+    pub fn pressed(&self, action: Action) -> bool {
+        self.bindings(action).iter().any(|binding| match *binding {
+            Binding::Key(key) => is_key_pressed(key),
+            Binding::Mouse(button) => is_mouse_button_pressed(button),
+        })
+    }
+
+    // Held down right now
+    pub fn down(&self, action: Action) -> bool {
+        self.bindings(action).iter().any(|binding| match *binding {
+            Binding::Key(key) => is_key_down(key),
+            Binding::Mouse(button) => is_mouse_button_down(button),
+        })
+    }
+
+    // The first key bound to the action, for hints in the side panel
+    pub fn label(&self, action: Action) -> &'static str {
+        self.bindings(action).first().map_or("unbound", |&binding| binding_name(binding))
+    }
+}
+
+fn binding_name(binding: Binding) -> &'static str {
+    BINDING_NAMES
+        .into_iter()
+        .find(|&(_, named)| named == binding)
+        .map_or("?", |(name, _)| name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bound(keymap: &Keymap, action: Action) -> Vec<&'static str> {
+        keymap.bindings(action).iter().map(|&binding| binding_name(binding)).collect()
+    }
+
+    #[test]
+    fn defaults_cover_every_action_once() {
+        let keymap = Keymap::default();
+        keymap.check_duplicates().unwrap();
+        for action in Action::ALL {
+            assert!(!keymap.bindings(action).is_empty(), "{} is unbound", action.name());
+        }
+        assert_eq!(bound(&keymap, Action::ClearField), ["Space"]);
+        assert_eq!(bound(&keymap, Action::ClearMap), ["R"]);
+        assert_eq!(bound(&keymap, Action::ToolBarrier), ["1"]);
+    }
+
+    #[test]
+    fn keys_file_replaces_defaults() {
+        let keymap = Keymap::parse("; comment\n\nclear_map Delete\nturn_one_way r\n").unwrap();
+        assert_eq!(bound(&keymap, Action::ClearMap), ["Delete"]);
+        assert_eq!(bound(&keymap, Action::TurnOneWay), ["R"]);
+        assert_eq!(bound(&keymap, Action::Paint), ["MouseLeft"]);
+    }
+
+    #[test]
+    fn duplicate_keys_are_rejected() {
+        let error = Keymap::parse("turn_one_way Q").unwrap_err();
+        assert!(error.contains("Q is bound to both turn_one_way and flip_vertical"), "{}", error);
+        assert!(Keymap::parse("turn_one_way Q\nflip_vertical `").is_ok());
+    }
+
+    #[test]
+    fn unknown_names_are_rejected() {
+        assert!(Keymap::parse("fly F").unwrap_err().contains("unknown action"));
+        assert!(Keymap::parse("paint Hyper").unwrap_err().contains("unknown key"));
+    }
+}
@@ -0,0 +1,41 @@
+pub mod agent;
+pub mod astar;
+pub mod colormap;
+pub mod cost_script;
+pub mod export;
+pub mod field_cache;
+pub mod fill;
+pub mod flow_field;
+pub mod gif;
+pub mod grid;
+pub mod map_file;
+pub mod mapgen;
+pub mod metric;
+pub mod obstacle;
+pub mod png;
+pub mod recording;
+pub mod region;
+pub mod run_stats;
+pub mod server;
+pub mod solver;
+pub mod spatial_hash;
+pub mod spawner;
+pub mod streamline;
+pub mod stroke;
+pub mod symmetry;
+pub mod topology;
+
+pub use grid::{Cell, CellContents, CellType, Edit, Grid, Heading, PaintTool, Terrain};
+
+// Size of each grid cell in world units, which are pixels when rendered
+pub const CELL_SIZE: f32 = 30.0;
+// How far (in movement cost) the repulsion from a danger cell reaches
+pub const DANGER_RADIUS: f32 = 8.0;
+// Extra cost per elevation level between two neighboring cells, as a fraction of the
+// cost of the cell stepped onto
+pub const SLOPE_COST: f32 = 0.5;
+// Elevations go from 0 to this
+pub const MAX_ELEVATION: i32 = 9;
+// Limits on each side of the grid, for resizing with the arrow keys or from a client
+pub const MIN_GRID_SIZE: usize = 2;
+pub const MAX_GRID_SIZE: usize = 1000;
@@ -0,0 +1,231 @@
+#[cfg(feature = "fs")]
+use std::{fs, io};
+
+use crate::{CellType, Edit, Grid, Heading, Terrain, MAX_ELEVATION};
+
+// Plain text maps, one character per cell and one line per row:
+//
+//   #  barrier        .  grass
+//   S  source         =  road
+//   !  danger         %  mud
+//   *  spawner        ~  water
+//   > v < ^  one-way cells entered heading east, south, west and north
+//
+// Barriers, sources, danger, spawners and one-way cells don't keep the terrain underneath them.
+// Portals are linked by lines starting with `@` and the two cells as column,row:
+//
+//   @ 3,4 17,12
+//
+// Elevation is optional, as lines starting with `h` and a digit per cell. The first
+// one is the top row, rows left out are flat:
+//
+//   h 0012321000
+//
+// Maps with several levels have a `levels` line saying how many. Their rows are split
+// evenly between them, ground floor first, and stairs are portals from one to another:
+//
+//   levels 2
+//
+// Blank lines and lines starting with `;` are skipped.
+pub fn parse_map(text: &str) -> Result<Grid, String> {
+    let (portals, rows): (Vec<&str>, Vec<&str>) = text
+        .lines()
+        .map(str::trim_end)
+        .filter(|line| !line.is_empty() && !line.starts_with(';'))
+        .partition(|line| line.starts_with('@'));
+    let (heights, rows): (Vec<&str>, Vec<&str>) = rows.into_iter().partition(|line| line.starts_with('h'));
+    let (levels, rows): (Vec<&str>, Vec<&str>) = rows.into_iter().partition(|line| line.starts_with("levels"));
+
+    let column_count = rows.first().map_or(0, |row| row.chars().count());
+    if column_count == 0 {
+        return Err("map is empty".to_string());
+    }
+
+    let mut grid = Grid::new(rows.len(), column_count);
+    for (row_y, row) in rows.iter().enumerate() {
+        if row.chars().count() != column_count {
+            return Err(format!(
+                "row {} is {} cells wide, expected {}",
+                row_y + 1,
+                row.chars().count(),
+                column_count
+            ));
+        }
+
+        for (col_x, symbol) in row.chars().enumerate() {
+            let cell = &mut grid.grid[row_y][col_x];
+            match symbol {
+                '#' => cell.cell_type = CellType::Barrier,
+                'S' => {
+                    cell.cell_type = CellType::Source;
+                    cell.cell_number = Some(1.0);
+                }
+                '!' => cell.cell_type = CellType::Danger,
+                '*' => cell.cell_type = CellType::Spawner,
+                '.' => cell.terrain = Terrain::Grass,
+                '=' => cell.terrain = Terrain::Road,
+                '%' => cell.terrain = Terrain::Mud,
+                '~' => cell.terrain = Terrain::Water,
+                '>' => cell.one_way = Some(Heading::East),
+                'v' => cell.one_way = Some(Heading::South),
+                '<' => cell.one_way = Some(Heading::West),
+                '^' => cell.one_way = Some(Heading::North),
+                _ => {
+                    return Err(format!(
+                        "unknown cell '{}' at row {}, column {}",
+                        symbol,
+                        row_y + 1,
+                        col_x + 1
+                    ))
+                }
+            }
+        }
+    }
+
+    if heights.len() > rows.len() {
+        return Err(format!("{} elevation rows for {} rows", heights.len(), rows.len()));
+    }
+    for (row_y, line) in heights.iter().enumerate() {
+        let levels: Vec<i32> = line[1..]
+            .trim_start()
+            .chars()
+            .map(|level| level.to_digit(10).map(|level| level as i32))
+            .collect::<Option<_>>()
+            .filter(|levels: &Vec<i32>| levels.len() == column_count)
+            .ok_or_else(|| format!("bad elevation row \"{}\"", line))?;
+        for (cell, level) in grid.grid[row_y].iter_mut().zip(levels) {
+            cell.elevation = level.min(MAX_ELEVATION);
+        }
+    }
+
+    if let Some(line) = levels.last() {
+        grid.levels = line["levels".len()..]
+            .trim()
+            .parse()
+            .ok()
+            .filter(|&levels| levels > 0 && rows.len() % levels == 0)
+            .ok_or_else(|| format!("can't split {} rows into \"{}\"", rows.len(), line))?;
+    }
+
+    for line in portals {
+        let ends: Vec<(usize, usize)> = line[1..]
+            .split_whitespace()
+            .map(|end| {
+                let (col_x, row_y) = end.split_once(',')?;
+                let end = (col_x.parse().ok()?, row_y.parse().ok()?);
+                Some(end).filter(|&(col_x, row_y)| col_x < column_count && row_y < rows.len())
+            })
+            .collect::<Option<_>>()
+            .ok_or_else(|| format!("bad portal \"{}\"", line))?;
+        match ends.as_slice() {
+            &[from, to] => grid.apply_edit(Edit::TogglePortal(from, to)),
+            _ => return Err(format!("portal \"{}\" needs two cells", line)),
+        }
+    }
+
+    Ok(grid)
+}
+
+pub fn format_map(grid: &Grid) -> String {
+    let mut text = String::with_capacity((grid.column_count_x + 1) * grid.row_count_y);
+    for row in &grid.grid {
+        for cell in row {
+            text.push(match (cell.cell_type, cell.one_way, cell.terrain) {
+                (CellType::Barrier, _, _) => '#',
+                (CellType::Source, _, _) => 'S',
+                (CellType::Danger, _, _) => '!',
+                (CellType::Spawner, _, _) => '*',
+                (_, Some(Heading::East), _) => '>',
+                (_, Some(Heading::South), _) => 'v',
+                (_, Some(Heading::West), _) => '<',
+                (_, Some(Heading::North), _) => '^',
+                (_, None, Terrain::Grass) => '.',
+                (_, None, Terrain::Road) => '=',
+                (_, None, Terrain::Mud) => '%',
+                (_, None, Terrain::Water) => '~',
+            });
+        }
+        text.push('\n');
+    }
+    if grid.grid.iter().flatten().any(|cell| cell.elevation != 0) {
+        for row in &grid.grid {
+            text.push_str("h ");
+            text.extend(row.iter().map(|cell| char::from_digit(cell.elevation as u32, 10).unwrap_or('0')));
+            text.push('\n');
+        }
+    }
+    if grid.levels > 1 {
+        text.push_str(&format!("levels {}\n", grid.levels));
+    }
+    for ((from_x, from_y), (to_x, to_y)) in grid.portal_pairs() {
+        text.push_str(&format!("@ {},{} {},{}\n", from_x, from_y, to_x, to_y));
+    }
+    text
+}
+
+#[cfg(feature = "fs")]
+pub fn load_map(path: &str) -> io::Result<Grid> {
+    let text = fs::read_to_string(path)?;
+    parse_map(&text).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+}
+
+#[cfg(feature = "fs")]
+pub fn save_map(grid: &Grid, path: &str) -> io::Result<()> {
+    fs::write(path, format_map(grid))
+}
+
+// Edits that set every cell's elevation from an RGBA image, stretched over the grid.
+// Black is 0 and white is MAX_ELEVATION.
+pub fn heightmap_edits(grid: &Grid, width: usize, height: usize, rgba: &[u8]) -> Vec<Edit> {
+    if width == 0 || height == 0 || rgba.len() < width * height * 4 {
+        return Vec::new();
+    }
+    let mut edits = Vec::with_capacity(grid.column_count_x * grid.row_count_y);
+    for row_y in 0..grid.row_count_y {
+        for col_x in 0..grid.column_count_x {
+            // Nearest pixel to the cell's center
+            let pixel_x = ((col_x as f32 + 0.5) * width as f32 / grid.column_count_x as f32) as usize;
+            let pixel_y = ((row_y as f32 + 0.5) * height as f32 / grid.row_count_y as f32) as usize;
+            let offset = (pixel_y.min(height - 1) * width + pixel_x.min(width - 1)) * 4;
+            let [r, g, b] = [rgba[offset], rgba[offset + 1], rgba[offset + 2]].map(f32::from);
+            let brightness = (0.299 * r + 0.587 * g + 0.114 * b) / 255.0;
+            let elevation = (brightness * MAX_ELEVATION as f32).round() as i32;
+            edits.push(Edit::SetElevation((col_x, row_y), elevation));
+        }
+    }
+    edits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_parses_back_the_same() {
+        let text = "S.=%~#\n!*><v^\n......\n..==.S\nh 012300\nh 000000\nh 900000\nh 000001\nlevels 2\n@ 1,0 4,3\n@ 2,1 0,2\n";
+        let grid = parse_map(text).unwrap();
+        assert_eq!(format_map(&grid), text);
+        assert_eq!(grid.levels, 2);
+        assert_eq!(grid.grid[0][3].elevation, 3);
+        assert_eq!(grid.grid[1][2].one_way, Some(Heading::East));
+        assert_eq!(grid.grid[3][4].portal, Some((1, 0)));
+        assert_eq!(grid.grid[0][0].cell_number, Some(1.0));
+    }
+
+    #[test]
+    fn flat_single_level_maps_leave_the_extras_out() {
+        let text = "S..\n.#.\n";
+        assert_eq!(format_map(&parse_map(text).unwrap()), text);
+        assert_eq!(format_map(&parse_map("; comment\n\nS..  \n.#.\nh 000\n").unwrap()), text);
+    }
+
+    #[test]
+    fn bad_maps() {
+        assert!(parse_map("").is_err());
+        assert!(parse_map("S..\n..").unwrap_err().contains("row 2"));
+        assert!(parse_map("S.x").unwrap_err().contains("unknown cell"));
+        assert!(parse_map("S..\n...\n...\nlevels 2").is_err());
+        assert!(parse_map("S..\n@ 0,0 5,0").is_err());
+        assert!(parse_map("S..\nh 01").is_err());
+    }
+}
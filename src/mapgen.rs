@@ -0,0 +1,256 @@
+use quad_rand as rand;
+
+use crate::{CellType, Grid};
+
+// Share of cells that start out as rock before the cave automaton runs
+const CAVE_FILL_CHANCE: f32 = 0.45;
+const CAVE_SMOOTHING_STEPS: usize = 5;
+// Noise frequency in cells and the value above which a cell becomes an obstacle
+const NOISE_SCALE: f32 = 0.18;
+const NOISE_THRESHOLD: f32 = 0.15;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MapKind {
+    Maze,
+    Caves,
+    Noise,
+}
+
+impl MapKind {
+    pub const ALL: [MapKind; 3] = [MapKind::Maze, MapKind::Caves, MapKind::Noise];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            MapKind::Maze => "maze",
+            MapKind::Caves => "caves",
+            MapKind::Noise => "noise",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        MapKind::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+// Builds a fresh grid filled by the chosen generator, with `source_count` sources
+// dropped on random open cells
+pub fn generate(kind: MapKind, row_count: usize, column_count: usize, source_count: usize) -> Grid {
+    let barriers = match kind {
+        MapKind::Maze => maze(row_count, column_count),
+        MapKind::Caves => caves(row_count, column_count),
+        MapKind::Noise => noise(row_count, column_count),
+    };
+
+    let mut grid = Grid::new(row_count, column_count);
+    for (row_y, row) in barriers.iter().enumerate() {
+        for (col_x, &is_barrier) in row.iter().enumerate() {
+            if is_barrier {
+                grid.grid[row_y][col_x].cell_type = CellType::Barrier;
+            }
+        }
+    }
+
+    place_random_sources(&mut grid, source_count);
+    grid
+}
+
+// Barriers dropped independently on each cell with probability `density`
+pub fn scatter(row_count: usize, column_count: usize, density: f32, source_count: usize) -> Grid {
+    let mut grid = Grid::new(row_count, column_count);
+    for cell in grid.grid.iter_mut().flatten() {
+        if rand::gen_range(0.0, 1.0) < density {
+            cell.cell_type = CellType::Barrier;
+        }
+    }
+
+    place_random_sources(&mut grid, source_count);
+    grid
+}
+
+fn place_random_sources(grid: &mut Grid, source_count: usize) {
+    let open_cells = grid.get_coordinates_of_type(CellType::Inactive);
+    for _ in 0..source_count.min(open_cells.len()) {
+        // Retry until an open cell comes up, open_cells being non-empty guarantees one
+        loop {
+            let (col_x, row_y) = open_cells[rand::gen_range(0, open_cells.len())];
+            let cell = &mut grid.grid[row_y][col_x];
+            if cell.cell_type == CellType::Inactive {
+                cell.cell_type = CellType::Source;
+                cell.cell_number = Some(1.0);
+                break;
+            }
+        }
+    }
+}
+
+// Recursive backtracker. Rooms sit on odd coordinates and the walls between them get
+// knocked out as the walk carves its way through, so every room stays reachable.
+fn maze(row_count: usize, column_count: usize) -> Vec<Vec<bool>> {
+    let mut barriers = vec![vec![true; column_count]; row_count];
+    if row_count < 2 || column_count < 2 {
+        return barriers;
+    }
+
+    let mut stack = vec![(1, 1)];
+    barriers[1][1] = false;
+
+    while let Some(&(col_x, row_y)) = stack.last() {
+        let mut unvisited = Vec::new();
+        for (offset_x, offset_y) in [(-2, 0), (2, 0), (0, -2), (0, 2)] {
+            let next_x = col_x as i32 + offset_x;
+            let next_y = row_y as i32 + offset_y;
+            if next_x < 1 || next_y < 1 || next_x >= column_count as i32 || next_y >= row_count as i32 {
+                continue;
+            }
+            if barriers[next_y as usize][next_x as usize] {
+                unvisited.push((next_x as usize, next_y as usize));
+            }
+        }
+
+        if unvisited.is_empty() {
+            stack.pop();
+            continue;
+        }
+
+        let (next_x, next_y) = unvisited[rand::gen_range(0, unvisited.len())];
+        barriers[(row_y + next_y) / 2][(col_x + next_x) / 2] = false;
+        barriers[next_y][next_x] = false;
+        stack.push((next_x, next_y));
+    }
+
+    barriers
+}
+
+// Random fill smoothed by the 4-5 rule: a cell becomes rock when at least five of the
+// nine cells around it (itself included) are rock. Off-grid counts as rock so caves
+// close off at the edges.
+fn caves(row_count: usize, column_count: usize) -> Vec<Vec<bool>> {
+    let mut barriers: Vec<Vec<bool>> = (0..row_count)
+        .map(|_| (0..column_count).map(|_| rand::gen_range(0.0, 1.0) < CAVE_FILL_CHANCE).collect())
+        .collect();
+
+    for _ in 0..CAVE_SMOOTHING_STEPS {
+        let mut smoothed = barriers.clone();
+        for (row_y, smoothed_row) in smoothed.iter_mut().enumerate() {
+            for (col_x, smoothed_cell) in smoothed_row.iter_mut().enumerate() {
+                let mut rock_count = 0;
+                for offset_y in -1..=1 {
+                    for offset_x in -1..=1 {
+                        let x = col_x as i32 + offset_x;
+                        let y = row_y as i32 + offset_y;
+                        let off_grid = x < 0 || y < 0 || x >= column_count as i32 || y >= row_count as i32;
+                        if off_grid || barriers[y as usize][x as usize] {
+                            rock_count += 1;
+                        }
+                    }
+                }
+                *smoothed_cell = rock_count >= 5;
+            }
+        }
+        barriers = smoothed;
+    }
+
+    barriers
+}
+
+fn noise(row_count: usize, column_count: usize) -> Vec<Vec<bool>> {
+    let perlin = Perlin::new();
+    (0..row_count)
+        .map(|row_y| {
+            (0..column_count)
+                .map(|col_x| perlin.sample(col_x as f32 * NOISE_SCALE, row_y as f32 * NOISE_SCALE) > NOISE_THRESHOLD)
+                .collect()
+        })
+        .collect()
+}
+
+// Classic 2D Perlin gradient noise over a randomly shuffled permutation table
+struct Perlin {
+    permutation: [u8; 512],
+}
+
+impl Perlin {
+    fn new() -> Self {
+        let mut table: Vec<u8> = (0..=255).collect();
+        for index in (1..table.len()).rev() {
+            table.swap(index, rand::gen_range(0, index + 1));
+        }
+
+        let mut permutation = [0; 512];
+        for (index, value) in permutation.iter_mut().enumerate() {
+            *value = table[index % 256];
+        }
+        Perlin { permutation }
+    }
+
+    fn gradient(&self, hash: u8, x: f32, y: f32) -> f32 {
+        match hash & 3 {
+            0 => x + y,
+            1 => -x + y,
+            2 => x - y,
+            _ => -x - y,
+        }
+    }
+
+    // Roughly in -1..1
+    fn sample(&self, x: f32, y: f32) -> f32 {
+        let fade = |t: f32| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+        let lerp = |a: f32, b: f32, t: f32| a + t * (b - a);
+
+        let cell_x = x.floor() as usize & 255;
+        let cell_y = y.floor() as usize & 255;
+        let local_x = x - x.floor();
+        let local_y = y - y.floor();
+        let u = fade(local_x);
+        let v = fade(local_y);
+
+        let p = &self.permutation;
+        let top_left = p[p[cell_x] as usize + cell_y];
+        let top_right = p[p[cell_x + 1] as usize + cell_y];
+        let bottom_left = p[p[cell_x] as usize + cell_y + 1];
+        let bottom_right = p[p[cell_x + 1] as usize + cell_y + 1];
+
+        lerp(
+            lerp(
+                self.gradient(top_left, local_x, local_y),
+                self.gradient(top_right, local_x - 1.0, local_y),
+                u,
+            ),
+            lerp(
+                self.gradient(bottom_left, local_x, local_y - 1.0),
+                self.gradient(bottom_right, local_x - 1.0, local_y - 1.0),
+                u,
+            ),
+            v,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::solver::{self, Solver};
+
+    #[test]
+    fn maze_rooms_are_all_connected() {
+        let grid = generate(MapKind::Maze, 15, 21, 0);
+        let distances = solver::integrate(&grid, &[(1, 1)], Solver::Bfs);
+        for row_y in (1..15).step_by(2) {
+            for col_x in (1..21).step_by(2) {
+                assert!(distances[row_y][col_x].is_some(), "room {},{} is cut off", col_x, row_y);
+            }
+        }
+    }
+
+    #[test]
+    fn sources_land_on_open_cells() {
+        for kind in MapKind::ALL {
+            let grid = generate(kind, 20, 30, 3);
+            let sources = grid.get_coordinates_of_type(CellType::Source);
+            assert_eq!(sources.len(), 3, "{}", kind.name());
+            for (col_x, row_y) in sources {
+                assert_eq!(grid.grid[row_y][col_x].cell_number, Some(1.0));
+            }
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use std::f32::consts::SQRT_2;
+
+// How units get from cell to cell on a square grid: which neighbors a step can go to and
+// what it costs on top of the terrain. Other topologies have their own neighbors and
+// always move like Manhattan.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Metric {
+    // Four neighbors, one across each side
+    #[default]
+    Manhattan,
+    // Eight neighbors, diagonal steps cost the same as straight ones
+    Chebyshev,
+    // Eight neighbors, diagonal steps cost √2 times as much
+    Octile,
+}
+
+impl Metric {
+    pub const ALL: [Metric; 3] = [Metric::Manhattan, Metric::Chebyshev, Metric::Octile];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Metric::Manhattan => "manhattan",
+            Metric::Chebyshev => "chebyshev",
+            Metric::Octile => "octile",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Metric::ALL.into_iter().find(|metric| metric.name() == name)
+    }
+
+    pub fn allows_diagonals(self) -> bool {
+        self != Metric::Manhattan
+    }
+
+    // How many times a straight step a step costs
+    pub fn step_length(self, diagonal: bool) -> f32 {
+        match self {
+            Metric::Octile if diagonal => SQRT_2,
+            _ => 1.0,
+        }
+    }
+
+    // Length of the shortest way across `steps_x` columns and `steps_y` rows of open
+    // ground, in straight steps
+    pub fn distance(self, steps_x: usize, steps_y: usize) -> f32 {
+        let (long, short) = (steps_x.max(steps_y) as f32, steps_x.min(steps_y) as f32);
+        match self {
+            Metric::Manhattan => long + short,
+            Metric::Chebyshev => long,
+            Metric::Octile => long + (SQRT_2 - 1.0) * short,
+        }
+    }
+}
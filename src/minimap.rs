@@ -0,0 +1,109 @@
+use macroquad::prelude::*;
+
+use crate::view::{self, View};
+use flowfields::{Cell, CellType, Grid};
+
+// Longest side of the minimap on screen
+const MINIMAP_SIZE: f32 = 160.0;
+// Gap between the minimap and the window corner
+const MINIMAP_MARGIN: f32 = 10.0;
+// Most texture pixels along a side, bigger grids are sampled in blocks of cells
+const MINIMAP_RESOLUTION: usize = 100;
+
+// Small picture of the whole grid in the bottom left corner, for finding your way
+// around while zoomed in. It's drawn from its own low resolution texture instead of
+// going through the cell by cell drawing.
+#[derive(Default)]
+pub struct Minimap {
+    texture: Option<Texture2D>,
+}
+
+// Which cell a block of cells shows, so a lone source doesn't get lost among the ground
+fn prominence(cell: &Cell) -> u8 {
+    match cell.cell_type {
+        CellType::Source => 4,
+        CellType::Spawner => 3,
+        CellType::Danger => 2,
+        CellType::Barrier => 1,
+        CellType::Active | CellType::Inactive => 0,
+    }
+}
+
+impl Minimap {
+    // Screen space, with the grid's proportions
+    pub fn rect(world_size: Vec2) -> Rect {
+        let size = world_size * (MINIMAP_SIZE / world_size.max_element());
+        Rect::new(MINIMAP_MARGIN, screen_height() - MINIMAP_MARGIN - size.y, size.x, size.y)
+    }
+
+    // World position shown under a screen position, if it's on the minimap
+    pub fn screen_to_world(world_size: Vec2, screen_pos: Vec2) -> Option<Vec2> {
+        let rect = Self::rect(world_size);
+        rect.contains(screen_pos)
+            .then(|| (screen_pos - rect.point()) / rect.size() * world_size)
+    }
+
+    // Redraws the texture from the cells on `level`, `color` is what each cell looks like
+    pub fn update(&mut self, grid: &Grid, level: usize, color: impl Fn(&Cell) -> Color) {
+        let (column_count, row_count) = (grid.column_count_x, grid.level_rows());
+        let first_row = level * row_count;
+        let block = column_count.max(row_count).div_ceil(MINIMAP_RESOLUTION);
+        let (width, height) = (column_count.div_ceil(block), row_count.div_ceil(block));
+
+        let mut image = Image::gen_image_color(width as u16, height as u16, WHITE);
+        for y in 0..height {
+            for x in 0..width {
+                let rows = y * block..((y + 1) * block).min(row_count);
+                let shown = rows
+                    .flat_map(|row_y| (x * block..((x + 1) * block).min(column_count)).map(move |col_x| (col_x, row_y)))
+                    .map(|(col_x, row_y)| &grid.grid[first_row + row_y][col_x])
+                    .max_by_key(|cell| prominence(cell));
+                if let Some(cell) = shown {
+                    image.set_pixel(x as u32, y as u32, color(cell));
+                }
+            }
+        }
+
+        match self.texture {
+            Some(texture) if (texture.width() as usize, texture.height() as usize) == (width, height) => {
+                texture.update(&image);
+            }
+            _ => {
+                if let Some(old) = self.texture.take() {
+                    old.delete();
+                }
+                let texture = Texture2D::from_image(&image);
+                texture.set_filter(FilterMode::Nearest);
+                self.texture = Some(texture);
+            }
+        }
+    }
+
+    // In screen space, with the part of the level the view shows outlined. `origin` is
+    // the level's top left corner in world space.
+    pub fn draw(&self, origin: Vec2, world_size: Vec2, view: &View) {
+        let Some(texture) = self.texture else {
+            return;
+        };
+        let rect = Self::rect(world_size);
+        draw_texture_ex(
+            texture,
+            rect.x,
+            rect.y,
+            WHITE,
+            DrawTextureParams {
+                dest_size: Some(rect.size()),
+                ..Default::default()
+            },
+        );
+        draw_rectangle_lines(rect.x, rect.y, rect.w, rect.h, 2.0, DARKGRAY);
+
+        let to_minimap = |world_pos: Vec2| {
+            (rect.point() + (world_pos - origin) / world_size * rect.size()).clamp(rect.point(), rect.point() + rect.size())
+        };
+        let top_left = to_minimap(view.screen_to_world(Vec2::ZERO));
+        let bottom_right = to_minimap(view.screen_to_world(view::grid_area()));
+        let size = bottom_right - top_left;
+        draw_rectangle_lines(top_left.x, top_left.y, size.x, size.y, 2.0, DARKBLUE);
+    }
+}
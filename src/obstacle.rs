@@ -0,0 +1,173 @@
+use crate::{astar, CellType, Grid};
+
+// A moving barrier that patrols between waypoints one cell at a time. The cell it's on
+// is turned into a barrier and goes back to what it was once the obstacle moves on.
+#[derive(Clone, Debug)]
+pub struct Obstacle {
+    route: Vec<(usize, usize)>,
+    next_waypoint: usize,
+    cell: (usize, usize),
+    // What the current cell was before the obstacle moved onto it
+    covered: CellType,
+    // Seconds per step
+    step_interval: f32,
+    step_timer: f32,
+}
+
+// Only plain cells can be taken over, turning sources or danger cells into barriers
+// would move the seeds of the integration around
+pub fn can_occupy(grid: &Grid, (col_x, row_y): (usize, usize)) -> bool {
+    matches!(grid.grid[row_y][col_x].cell_type, CellType::Inactive | CellType::Active)
+}
+
+impl Obstacle {
+    // Starts on the first waypoint, `speed` is in cells per second
+    pub fn new(grid: &mut Grid, route: Vec<(usize, usize)>, speed: f32) -> Option<Self> {
+        let &start = route.first()?;
+        if !can_occupy(grid, start) || speed <= 0.0 {
+            return None;
+        }
+
+        let mut obstacle = Obstacle {
+            route,
+            next_waypoint: 0,
+            cell: start,
+            covered: CellType::Inactive,
+            step_interval: 1.0 / speed,
+            step_timer: 0.0,
+        };
+        obstacle.enter(grid, start);
+        Some(obstacle)
+    }
+
+    pub fn cell(&self) -> (usize, usize) {
+        self.cell
+    }
+
+    pub fn route(&self) -> &[(usize, usize)] {
+        &self.route
+    }
+
+    // Moves along the route, returning every cell that turned into a barrier or opened
+    // back up. When the next cell is taken (by another obstacle, a source...) it waits
+    // there until the way clears.
+    pub fn update(&mut self, grid: &mut Grid, delta_time: f32) -> Vec<(usize, usize)> {
+        let mut changed = Vec::new();
+        self.step_timer += delta_time;
+        while self.step_timer >= self.step_interval {
+            self.step_timer -= self.step_interval;
+
+            for _ in 0..self.route.len() {
+                if self.route[self.next_waypoint] != self.cell {
+                    break;
+                }
+                self.next_waypoint = (self.next_waypoint + 1) % self.route.len();
+            }
+            let waypoint = self.route[self.next_waypoint];
+            if waypoint == self.cell {
+                continue;
+            }
+
+            // Waypoints that can't be reached at all are skipped rather than waited on
+            let Some(path) = astar::find_path(grid, self.cell, &[waypoint]) else {
+                self.next_waypoint = (self.next_waypoint + 1) % self.route.len();
+                continue;
+            };
+            let next_cell = path[1];
+            if !can_occupy(grid, next_cell) {
+                continue;
+            }
+
+            changed.push(self.cell);
+            changed.push(next_cell);
+            self.leave(grid);
+            self.enter(grid, next_cell);
+        }
+        changed
+    }
+
+    // Takes the obstacle off the map, returning the cell it opened up
+    pub fn remove(mut self, grid: &mut Grid) -> (usize, usize) {
+        self.leave(grid);
+        self.cell
+    }
+
+    fn enter(&mut self, grid: &mut Grid, (col_x, row_y): (usize, usize)) {
+        let cell = &mut grid.grid[row_y][col_x];
+        self.covered = cell.cell_type;
+        cell.cell_type = CellType::Barrier;
+        self.cell = (col_x, row_y);
+        grid.mark_changed();
+    }
+
+    fn leave(&mut self, grid: &mut Grid) {
+        let (col_x, row_y) = self.cell;
+        let cell = &mut grid.grid[row_y][col_x];
+        // Something painted over it in the meantime, that wins
+        if cell.cell_type == CellType::Barrier {
+            cell.cell_type = self.covered;
+        }
+        grid.mark_changed();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{flow_field::FlowField, map_file};
+
+    #[test]
+    fn patrols_back_and_forth_leaving_cells_as_they_were() {
+        let mut grid = map_file::parse_map("....\n").unwrap();
+        let mut obstacle = Obstacle::new(&mut grid, vec![(0, 0), (3, 0)], 2.0).unwrap();
+        assert_eq!(grid.grid[0][0].cell_type, CellType::Barrier);
+
+        assert!(obstacle.update(&mut grid, 0.25).is_empty());
+        assert_eq!(obstacle.update(&mut grid, 0.25), [(0, 0), (1, 0)]);
+        assert_eq!(grid.grid[0][0].cell_type, CellType::Inactive);
+        assert_eq!(grid.grid[0][1].cell_type, CellType::Barrier);
+
+        // Out to the far end and back again
+        obstacle.update(&mut grid, 1.0);
+        assert_eq!(obstacle.cell(), (3, 0));
+        obstacle.update(&mut grid, 0.5);
+        assert_eq!(obstacle.cell(), (2, 0));
+
+        assert_eq!(obstacle.remove(&mut grid), (2, 0));
+        assert!(grid.grid[0].iter().all(|cell| cell.cell_type == CellType::Inactive));
+    }
+
+    #[test]
+    fn cant_start_on_sources() {
+        let mut grid = map_file::parse_map("S.\n").unwrap();
+        assert!(Obstacle::new(&mut grid, vec![(0, 0), (1, 0)], 1.0).is_none());
+        assert!(Obstacle::new(&mut grid, vec![(1, 0)], 0.0).is_none());
+        assert!(Obstacle::new(&mut grid, Vec::new(), 1.0).is_none());
+    }
+
+    #[test]
+    fn repaired_field_matches_computing_it_again() {
+        let text = "S....=....\n..~~.=.%%.\n..........\n####..####\n....!.....\n.%%..=....\n.........S";
+        let mut grid = map_file::parse_map(text).unwrap();
+        let mut flow_field = FlowField::compute(&mut grid, 1.0);
+        let mut obstacles = [
+            Obstacle::new(&mut grid, vec![(4, 3), (5, 3)], 3.0).unwrap(),
+            Obstacle::new(&mut grid, vec![(0, 2), (9, 2)], 5.0).unwrap(),
+        ];
+        let started: Vec<_> = obstacles.iter().map(Obstacle::cell).collect();
+        flow_field.update(&mut grid, &started, 1.0);
+        for _ in 0..40 {
+            let changed: Vec<_> = obstacles.iter_mut().flat_map(|obstacle| obstacle.update(&mut grid, 0.1)).collect();
+            flow_field.update(&mut grid, &changed, 1.0);
+
+            let mut fresh = map_file::parse_map(&map_file::format_map(&grid)).unwrap();
+            FlowField::compute(&mut fresh, 1.0);
+            for (repaired, computed) in grid.grid.iter().flatten().zip(fresh.grid.iter().flatten()) {
+                match (repaired.cell_number, computed.cell_number) {
+                    (Some(repaired), Some(computed)) => assert!((repaired - computed).abs() < 1e-4),
+                    (repaired, computed) => assert_eq!(repaired, computed),
+                }
+            }
+        }
+    }
+}
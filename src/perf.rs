@@ -0,0 +1,95 @@
+use std::collections::VecDeque;
+
+use macroquad::prelude::*;
+
+use flowfields::flow_field::PhaseTimings;
+
+// Frames the frame time graph goes back
+const HISTORY_LENGTH: usize = 120;
+// Frame time at the top of the graph, in seconds. Longer frames are cut off.
+const GRAPH_CEILING: f32 = 1.0 / 20.0;
+const GRAPH_HEIGHT: f32 = 40.0;
+const PANEL_WIDTH: f32 = 260.0;
+const LINE_HEIGHT: f32 = 18.0;
+const MARGIN: f32 = 10.0;
+
+// Where the last field came from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldWork {
+    Computed,
+    Repaired,
+    Cached,
+    Gpu,
+}
+
+impl FieldWork {
+    fn name(self) -> &'static str {
+        match self {
+            FieldWork::Computed => "computed",
+            FieldWork::Repaired => "repaired",
+            FieldWork::Cached => "cached",
+            FieldWork::Gpu => "gpu",
+        }
+    }
+}
+
+// Timings for the overlay toggled with J, so solver slowdowns show up as numbers
+// before they show up as hitches
+#[derive(Default)]
+pub struct PerfOverlay {
+    frame_times: VecDeque<f32>,
+    last_field: Option<(FieldWork, PhaseTimings)>,
+    // Seconds spent drawing the map last frame, on the CPU side
+    drawing: f64,
+}
+
+impl PerfOverlay {
+    pub fn record_frame(&mut self, frame_time: f32) {
+        if self.frame_times.len() == HISTORY_LENGTH {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(frame_time);
+    }
+
+    pub fn record_field(&mut self, work: FieldWork, timings: PhaseTimings) {
+        self.last_field = Some((work, timings));
+    }
+
+    pub fn record_drawing(&mut self, seconds: f64) {
+        self.drawing = seconds;
+    }
+
+    // Top left of the screen, over the map
+    pub fn draw(&self, cell_count: usize, agent_count: usize) {
+        let milliseconds = |seconds: f64| format!("{:.2} ms", seconds * 1000.0);
+        let mut lines = vec![format!("FPS: {}", get_fps())];
+        match self.last_field {
+            Some((work, timings)) => {
+                lines.push(format!("Last field: {}", work.name()));
+                lines.push(format!("  integration: {}", milliseconds(timings.integration)));
+                lines.push(format!("  flow: {}", milliseconds(timings.flow)));
+            }
+            None => lines.push("Last field: none yet".to_string()),
+        }
+        lines.push(format!("Drawing: {}", milliseconds(self.drawing)));
+        lines.push(format!("Cells: {}, agents: {}", cell_count, agent_count));
+
+        let height = lines.len() as f32 * LINE_HEIGHT + GRAPH_HEIGHT + MARGIN * 2.0;
+        draw_rectangle(MARGIN, MARGIN, PANEL_WIDTH, height, Color::new(0.0, 0.0, 0.0, 0.7));
+        for (index, line) in lines.iter().enumerate() {
+            let y = MARGIN * 2.0 + index as f32 * LINE_HEIGHT + LINE_HEIGHT / 2.0;
+            draw_text(line, MARGIN * 2.0, y, 18.0, WHITE);
+        }
+
+        // One bar per frame, newest on the right, red past 30 FPS
+        let bottom = MARGIN + height - MARGIN;
+        let bar_width = (PANEL_WIDTH - MARGIN * 2.0) / HISTORY_LENGTH as f32;
+        let offset = HISTORY_LENGTH - self.frame_times.len();
+        for (index, &frame_time) in self.frame_times.iter().enumerate() {
+            let bar_height = (frame_time / GRAPH_CEILING).min(1.0) * GRAPH_HEIGHT;
+            let color = if frame_time > 1.0 / 30.0 { RED } else { GREEN };
+            let x = MARGIN * 2.0 + (offset + index) as f32 * bar_width;
+            draw_rectangle(x, bottom - bar_height, bar_width, bar_height, color);
+        }
+    }
+}
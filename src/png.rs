@@ -0,0 +1,128 @@
+// Minimal PNG writer for RGBA images. The pixel data goes into uncompressed deflate
+// blocks, so files are big but it needs nothing beyond the standard library.
+
+// `rgba` is width * height pixels, top row first
+pub fn encode_rgba(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    assert_eq!(rgba.len(), width as usize * height as usize * 4);
+
+    let mut bytes = b"\x89PNG\r\n\x1a\n".to_vec();
+
+    let mut header = Vec::new();
+    header.extend(width.to_be_bytes());
+    header.extend(height.to_be_bytes());
+    // 8 bits per channel, RGBA, default compression, filtering and no interlacing
+    header.extend([8, 6, 0, 0, 0]);
+    write_chunk(&mut bytes, b"IHDR", &header);
+
+    // Every row starts with its filter type, always none here
+    let mut scanlines = Vec::with_capacity(rgba.len() + height as usize);
+    for row in rgba.chunks_exact(width as usize * 4) {
+        scanlines.push(0);
+        scanlines.extend(row);
+    }
+    write_chunk(&mut bytes, b"IDAT", &zlib_stored(&scanlines));
+    write_chunk(&mut bytes, b"IEND", &[]);
+    bytes
+}
+
+fn write_chunk(bytes: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    bytes.extend((data.len() as u32).to_be_bytes());
+    let start = bytes.len();
+    bytes.extend(kind);
+    bytes.extend(data);
+    let crc = crc32(&bytes[start..]);
+    bytes.extend(crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // Deflate with a 32K window, no preset dictionary
+    let mut bytes = vec![0x78, 0x01];
+    let mut blocks = data.chunks(u16::MAX as usize).peekable();
+    if blocks.peek().is_none() {
+        bytes.extend([1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let is_last = blocks.peek().is_none();
+        let length = block.len() as u16;
+        bytes.push(is_last as u8);
+        bytes.extend(length.to_le_bytes());
+        bytes.extend((!length).to_le_bytes());
+        bytes.extend(block);
+    }
+    bytes.extend(adler32(data).to_be_bytes());
+    bytes
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn adler32_check_value() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+    // Reads the stored blocks back, checking each header along the way
+    fn unstore(bytes: &[u8]) -> Vec<u8> {
+        assert_eq!(&bytes[..2], [0x78, 0x01]);
+        assert_eq!(u16::from_be_bytes([bytes[0], bytes[1]]) % 31, 0);
+        let mut data = Vec::new();
+        let mut offset = 2;
+        loop {
+            let is_last = bytes[offset] == 1;
+            let length = u16::from_le_bytes([bytes[offset + 1], bytes[offset + 2]]);
+            let inverse = u16::from_le_bytes([bytes[offset + 3], bytes[offset + 4]]);
+            assert_eq!(length, !inverse);
+            offset += 5;
+            data.extend(&bytes[offset..offset + length as usize]);
+            offset += length as usize;
+            if is_last {
+                break;
+            }
+        }
+        assert_eq!(bytes[offset..], adler32(&data).to_be_bytes());
+        data
+    }
+
+    #[test]
+    fn zlib_stored_round_trip() {
+        for length in [0, 1, 1000, u16::MAX as usize, u16::MAX as usize + 1, 200_000] {
+            let data: Vec<u8> = (0..length).map(|index| (index * 7 % 256) as u8).collect();
+            assert_eq!(unstore(&zlib_stored(&data)), data);
+        }
+    }
+
+    #[test]
+    fn chunks_carry_their_crc() {
+        let bytes = encode_rgba(2, 1, &[255, 0, 0, 255, 0, 255, 0, 255]);
+        assert_eq!(&bytes[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&bytes[12..16], b"IHDR");
+        assert_eq!(bytes[29..33], crc32(&bytes[12..29]).to_be_bytes());
+        assert_eq!(&bytes[bytes.len() - 12..], [0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]);
+    }
+}
@@ -0,0 +1,346 @@
+#[cfg(feature = "fs")]
+use std::{fs, io};
+
+use crate::{mapgen::MapKind, CellContents, CellType, Edit, Grid, Heading, PaintTool, Terrain};
+
+const RECORDING_HEADER: &str = "# flowfields recording v1";
+
+// Logs every edit with the time since recording started. The grid as it was when
+// recording started is captured as edits at time zero, starting with a clear and a
+// reshape, so a replay rebuilds it no matter what's on screen.
+#[derive(Debug)]
+pub struct Recorder {
+    started_at: f64,
+    entries: Vec<(f64, Edit)>,
+}
+
+impl Recorder {
+    pub fn start(grid: &Grid, now: f64) -> Self {
+        let mut recorder = Recorder {
+            started_at: now,
+            entries: Vec::new(),
+        };
+        recorder.record_snapshot(grid, now);
+        recorder
+    }
+
+    // Records the whole grid as a clear followed by the edits that rebuild it, for
+    // changes that replace the map wholesale (like loading one from a file)
+    pub fn record_snapshot(&mut self, grid: &Grid, now: f64) {
+        self.record(Edit::Clear, now);
+        self.record(
+            Edit::Reshape {
+                levels: grid.levels,
+                row_count: grid.row_count_y,
+                column_count: grid.column_count_x,
+            },
+            now,
+        );
+        self.record(Edit::SetWrap(grid.wrap), now);
+        for cell in grid.grid.iter().flatten() {
+            let position = (cell.x_position, cell.y_position);
+            if cell.terrain != Terrain::default() {
+                self.record(Edit::Paint(PaintTool::Terrain(cell.terrain), position), now);
+            }
+            if let Some(heading) = cell.one_way {
+                self.record(Edit::Paint(PaintTool::OneWay(heading), position), now);
+            }
+            if cell.elevation != 0 {
+                self.record(Edit::SetElevation(position, cell.elevation), now);
+            }
+            match cell.cell_type {
+                CellType::Barrier => self.record(Edit::Paint(PaintTool::Barrier, position), now),
+                CellType::Danger => self.record(Edit::Paint(PaintTool::Danger, position), now),
+                CellType::Spawner => self.record(Edit::Paint(PaintTool::Spawner, position), now),
+                CellType::Source => self.record(Edit::ToggleSource(position), now),
+                CellType::Inactive | CellType::Active => {}
+            }
+        }
+        for (from, to) in grid.portal_pairs() {
+            self.record(Edit::TogglePortal(from, to), now);
+        }
+    }
+
+    pub fn record(&mut self, edit: Edit, now: f64) {
+        self.entries.push((now - self.started_at, edit));
+    }
+
+    pub fn edit_count(&self) -> usize {
+        self.entries.len()
+    }
+
+    // One edit per line, prefixed with its timestamp in seconds
+    pub fn to_text(&self) -> String {
+        let mut contents = String::from(RECORDING_HEADER);
+        contents.push('\n');
+        for (time, edit) in &self.entries {
+            contents.push_str(&format!("{:.3} {}\n", time, format_edit(*edit)));
+        }
+        contents
+    }
+
+    #[cfg(feature = "fs")]
+    pub fn save(&self, path: &str) -> io::Result<()> {
+        fs::write(path, self.to_text())
+    }
+}
+
+// Plays a saved recording back, handing out edits once their timestamp has passed.
+// Stepping hands them out one at a time instead and pauses the real-time playback.
+#[derive(Debug)]
+pub struct Replay {
+    started_at: f64,
+    entries: Vec<(f64, Edit)>,
+    next: usize,
+    paused: bool,
+}
+
+impl Replay {
+    #[cfg(feature = "fs")]
+    pub fn load(path: &str, now: f64) -> io::Result<Self> {
+        let contents = fs::read_to_string(path)?;
+        Replay::parse(&contents, now).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error))
+    }
+
+    pub fn parse(contents: &str, now: f64) -> Result<Self, String> {
+        let mut entries = Vec::new();
+
+        for (line_number, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let entry = line.split_once(' ').and_then(|(time, edit)| {
+                Some((time.parse::<f64>().ok()?, parse_edit(edit)?))
+            });
+            match entry {
+                Some(entry) => entries.push(entry),
+                None => return Err(format!("line {}: can't parse \"{}\"", line_number + 1, line)),
+            }
+        }
+
+        Ok(Replay {
+            started_at: now,
+            entries,
+            next: 0,
+            paused: false,
+        })
+    }
+
+    pub fn due_edits(&mut self, now: f64) -> Vec<Edit> {
+        let mut due = Vec::new();
+        if self.paused {
+            return due;
+        }
+        while let Some(&(time, edit)) = self.entries.get(self.next) {
+            if time > now - self.started_at {
+                break;
+            }
+            due.push(edit);
+            self.next += 1;
+        }
+        due
+    }
+
+    pub fn step(&mut self) -> Option<Edit> {
+        self.paused = true;
+        let (_, edit) = *self.entries.get(self.next)?;
+        self.next += 1;
+        Some(edit)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next >= self.entries.len()
+    }
+
+    pub fn position(&self) -> usize {
+        self.next
+    }
+
+    pub fn edit_count(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+// One line of a recording, without the timestamp
+pub fn format_edit(edit: Edit) -> String {
+    match edit {
+        Edit::Paint(tool, (col_x, row_y)) => format!("paint {} {} {}", tool.name().to_lowercase(), col_x, row_y),
+        Edit::ToggleSource((col_x, row_y)) => format!("source {} {}", col_x, row_y),
+        Edit::Generate { kind, source_count, seed } => format!("generate {} {} {}", kind.name(), source_count, seed),
+        Edit::ToggleWrap => "wrap".to_string(),
+        Edit::SetWrap(wrap) => format!("wrap {}", if wrap { "on" } else { "off" }),
+        Edit::Clear => "clear".to_string(),
+        Edit::Resize { row_count, column_count } => format!("resize {} {}", row_count, column_count),
+        Edit::TogglePortal((from_x, from_y), (to_x, to_y)) => format!("portal {} {} {} {}", from_x, from_y, to_x, to_y),
+        Edit::SetCell((col_x, row_y), contents) => format!(
+            "set {} {} {} {} {} {}",
+            col_x,
+            row_y,
+            name_of(&CELL_TYPE_NAMES, contents.cell_type),
+            name_of(&TERRAIN_NAMES, contents.terrain),
+            name_of(&ONE_WAY_NAMES, contents.one_way),
+            contents.elevation,
+        ),
+        Edit::SetElevation((col_x, row_y), elevation) => format!("elevation {} {} {}", col_x, row_y, elevation),
+        Edit::SetLevels(levels) => format!("levels {}", levels),
+        Edit::Reshape { levels, row_count, column_count } => {
+            format!("reshape {} {} {}", levels, row_count, column_count)
+        }
+    }
+}
+
+const CELL_TYPE_NAMES: [(&str, CellType); 6] = [
+    ("barrier", CellType::Barrier),
+    ("inactive", CellType::Inactive),
+    ("active", CellType::Active),
+    ("source", CellType::Source),
+    ("danger", CellType::Danger),
+    ("spawner", CellType::Spawner),
+];
+
+const TERRAIN_NAMES: [(&str, Terrain); 4] = [
+    ("road", Terrain::Road),
+    ("grass", Terrain::Grass),
+    ("mud", Terrain::Mud),
+    ("water", Terrain::Water),
+];
+
+const ONE_WAY_NAMES: [(&str, Option<Heading>); 5] = [
+    ("-", None),
+    ("east", Some(Heading::East)),
+    ("south", Some(Heading::South)),
+    ("west", Some(Heading::West)),
+    ("north", Some(Heading::North)),
+];
+
+fn name_of<T: PartialEq>(names: &[(&'static str, T)], value: T) -> &'static str {
+    names.iter().find(|(_, known)| *known == value).unwrap().0
+}
+
+fn lookup<T: Copy>(names: &[(&str, T)], name: &str) -> Option<T> {
+    names.iter().find(|(known, _)| *known == name).map(|(_, value)| *value)
+}
+
+pub fn parse_edit(text: &str) -> Option<Edit> {
+    let parts: Vec<&str> = text.split_whitespace().collect();
+    let edit = match parts.as_slice() {
+        ["paint", tool, col_x, row_y] => Edit::Paint(PaintTool::from_name(tool)?, (col_x.parse().ok()?, row_y.parse().ok()?)),
+        ["source", col_x, row_y] => Edit::ToggleSource((col_x.parse().ok()?, row_y.parse().ok()?)),
+        ["generate", kind, source_count, seed] => Edit::Generate {
+            kind: MapKind::from_name(kind)?,
+            source_count: source_count.parse().ok()?,
+            seed: seed.parse().ok()?,
+        },
+        ["wrap"] => Edit::ToggleWrap,
+        ["wrap", "on"] => Edit::SetWrap(true),
+        ["wrap", "off"] => Edit::SetWrap(false),
+        ["clear"] => Edit::Clear,
+        ["resize", row_count, column_count] => Edit::Resize {
+            row_count: row_count.parse().ok()?,
+            column_count: column_count.parse().ok()?,
+        },
+        // Recordings from before elevation leave it off
+        ["set", col_x, row_y, cell_type, terrain, one_way, elevation @ ..] if elevation.len() <= 1 => Edit::SetCell(
+            (col_x.parse().ok()?, row_y.parse().ok()?),
+            CellContents {
+                cell_type: lookup(&CELL_TYPE_NAMES, cell_type)?,
+                terrain: lookup(&TERRAIN_NAMES, terrain)?,
+                one_way: lookup(&ONE_WAY_NAMES, one_way)?,
+                elevation: elevation.first().map_or(Some(0), |level| level.parse().ok())?,
+            },
+        ),
+        ["elevation", col_x, row_y, elevation] => {
+            Edit::SetElevation((col_x.parse().ok()?, row_y.parse().ok()?), elevation.parse().ok()?)
+        }
+        ["levels", levels] => Edit::SetLevels(levels.parse().ok()?),
+        ["reshape", levels, row_count, column_count] => Edit::Reshape {
+            levels: levels.parse().ok()?,
+            row_count: row_count.parse().ok()?,
+            column_count: column_count.parse().ok()?,
+        },
+        ["portal", from_x, from_y, to_x, to_y] => Edit::TogglePortal(
+            (from_x.parse().ok()?, from_y.parse().ok()?),
+            (to_x.parse().ok()?, to_y.parse().ok()?),
+        ),
+        _ => return None,
+    };
+    Some(edit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{map_file, server};
+
+    // Plays the whole recording back at once, skipping edits that don't fit like the
+    // visualizer does
+    fn replay_into(recorder: &Recorder, grid: &mut Grid) {
+        let mut replay = Replay::parse(&recorder.to_text(), 0.0).unwrap();
+        for edit in replay.due_edits(0.0) {
+            if server::fits(grid, edit) {
+                grid.apply_edit(edit);
+            }
+        }
+        assert!(replay.is_finished());
+    }
+
+    #[test]
+    fn edits_survive_the_text_format() {
+        let edits = [
+            Edit::Paint(PaintTool::OneWay(Heading::West), (3, 4)),
+            Edit::ToggleSource((0, 1)),
+            Edit::Generate { kind: MapKind::Caves, source_count: 2, seed: 99 },
+            Edit::ToggleWrap,
+            Edit::SetWrap(true),
+            Edit::SetWrap(false),
+            Edit::Clear,
+            Edit::Resize { row_count: 7, column_count: 9 },
+            Edit::TogglePortal((1, 2), (3, 4)),
+            Edit::SetCell(
+                (5, 6),
+                CellContents { cell_type: CellType::Danger, terrain: Terrain::Mud, one_way: None, elevation: 3 },
+            ),
+            Edit::SetElevation((2, 2), 7),
+            Edit::SetLevels(3),
+            Edit::Reshape { levels: 2, row_count: 8, column_count: 5 },
+        ];
+        for edit in edits {
+            assert_eq!(parse_edit(&format_edit(edit)), Some(edit), "{}", format_edit(edit));
+        }
+        assert_eq!(parse_edit("paint nothing 1 2"), None);
+        assert_eq!(parse_edit("wrap sideways"), None);
+    }
+
+    #[test]
+    fn replay_sets_wrapping_whatever_the_grid_had() {
+        for recorded_wrap in [false, true] {
+            let mut recorded = map_file::parse_map("S.#\n.~.\n").unwrap();
+            recorded.wrap = recorded_wrap;
+            let recorder = Recorder::start(&recorded, 5.0);
+            for replayed_wrap in [false, true] {
+                let mut grid = Grid::new(4, 4);
+                grid.wrap = replayed_wrap;
+                replay_into(&recorder, &mut grid);
+                assert_eq!(grid.wrap, recorded_wrap);
+                assert_eq!(map_file::format_map(&grid), map_file::format_map(&recorded));
+            }
+        }
+    }
+
+    #[test]
+    fn replay_keeps_the_recorded_levels_whatever_the_grid_size() {
+        let mut recorded = map_file::parse_map("S.#\n...\n.~.\n..S\nlevels 2\n@ 0,0 0,2\n").unwrap();
+        recorded.wrap = true;
+        let recorder = Recorder::start(&recorded, 0.0);
+        // Too tall to stack a second level on with its current split
+        for (row_count, column_count, levels) in [(600, 5, 1), (3, 2, 3), (1, 1, 1)] {
+            let mut grid = Grid::new(row_count, column_count);
+            grid.levels = levels;
+            replay_into(&recorder, &mut grid);
+            assert_eq!(grid.levels, 2);
+            assert_eq!((grid.row_count_y, grid.column_count_x), (4, 3));
+            assert_eq!(map_file::format_map(&grid), map_file::format_map(&recorded));
+        }
+    }
+}
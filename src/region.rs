@@ -0,0 +1,145 @@
+use crate::{CellContents, Edit, Grid, Heading};
+
+// A rectangle of cells lifted off the grid, for pasting elsewhere. Portal links aren't
+// part of it since they point at one particular place.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Region {
+    column_count: usize,
+    row_count: usize,
+    // Row by row, top row first
+    cells: Vec<CellContents>,
+}
+
+// Top left and bottom right of the rectangle spanned by two corner cells
+pub fn bounds(corner: (usize, usize), opposite: (usize, usize)) -> ((usize, usize), (usize, usize)) {
+    (
+        (corner.0.min(opposite.0), corner.1.min(opposite.1)),
+        (corner.0.max(opposite.0), corner.1.max(opposite.1)),
+    )
+}
+
+// Every cell of the rectangle spanned by two corner cells
+pub fn cells_between(corner: (usize, usize), opposite: (usize, usize)) -> impl Iterator<Item = (usize, usize)> {
+    let ((left, top), (right, bottom)) = bounds(corner, opposite);
+    (top..=bottom).flat_map(move |row_y| (left..=right).map(move |col_x| (col_x, row_y)))
+}
+
+impl Region {
+    pub fn copy(grid: &Grid, corner: (usize, usize), opposite: (usize, usize)) -> Self {
+        let ((left, top), (right, bottom)) = bounds(corner, opposite);
+        Region {
+            column_count: right - left + 1,
+            row_count: bottom - top + 1,
+            cells: cells_between(corner, opposite)
+                .map(|(col_x, row_y)| grid.grid[row_y][col_x].contents())
+                .collect(),
+        }
+    }
+
+    pub fn column_count(&self) -> usize {
+        self.column_count
+    }
+
+    pub fn row_count(&self) -> usize {
+        self.row_count
+    }
+
+    // Mirrored left to right, one-way cells turn around with it
+    pub fn flipped_horizontally(&self) -> Self {
+        let cells = self
+            .cells
+            .chunks(self.column_count)
+            .flat_map(|row| row.iter().rev())
+            .map(|&contents| CellContents {
+                one_way: contents.one_way.map(|heading| match heading {
+                    Heading::East => Heading::West,
+                    Heading::West => Heading::East,
+                    other => other,
+                }),
+                ..contents
+            })
+            .collect();
+        Region { cells, ..*self }
+    }
+
+    // Mirrored top to bottom
+    pub fn flipped_vertically(&self) -> Self {
+        let cells = self
+            .cells
+            .chunks(self.column_count)
+            .rev()
+            .flatten()
+            .map(|&contents| CellContents {
+                one_way: contents.one_way.map(|heading| match heading {
+                    Heading::North => Heading::South,
+                    Heading::South => Heading::North,
+                    other => other,
+                }),
+                ..contents
+            })
+            .collect();
+        Region { cells, ..*self }
+    }
+
+    // Edits that put the region down with its top left corner on `corner`. Whatever
+    // hangs off the grid, or off the level `corner` is on, is left out.
+    pub fn paste_edits(&self, grid: &Grid, corner: (usize, usize)) -> Vec<Edit> {
+        let level_end = (grid.level_of(corner) + 1) * grid.level_rows();
+        let mut edits = Vec::new();
+        for (row_offset, row) in self.cells.chunks(self.column_count).enumerate() {
+            for (col_offset, &contents) in row.iter().enumerate() {
+                let (col_x, row_y) = (corner.0 + col_offset, corner.1 + row_offset);
+                if col_x < grid.column_count_x && row_y < level_end {
+                    edits.push(Edit::SetCell((col_x, row_y), contents));
+                }
+            }
+        }
+        edits
+    }
+}
+
+// Edits that blank out the rectangle spanned by two corner cells
+pub fn clear_edits(corner: (usize, usize), opposite: (usize, usize)) -> Vec<Edit> {
+    cells_between(corner, opposite)
+        .map(|cell| Edit::SetCell(cell, CellContents::default()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_file;
+
+    fn pasted(grid: &mut Grid, region: &Region, corner: (usize, usize)) -> String {
+        for edit in region.paste_edits(grid, corner) {
+            grid.apply_edit(edit);
+        }
+        map_file::format_map(grid)
+    }
+
+    #[test]
+    fn pastes_a_copy_cut_off_at_the_edge() {
+        let mut grid = map_file::parse_map("#>.\n=%.\n...").unwrap();
+        let region = Region::copy(&grid, (1, 1), (0, 0));
+        assert_eq!((region.column_count(), region.row_count()), (2, 2));
+        assert_eq!(pasted(&mut grid, &region, (2, 1)), "#>.\n=%#\n..=\n");
+    }
+
+    #[test]
+    fn flips_turn_one_way_cells_around() {
+        let mut grid = map_file::parse_map(">v#\n...\n...").unwrap();
+        let region = Region::copy(&grid, (0, 0), (2, 0));
+        assert_eq!(pasted(&mut grid, &region.flipped_horizontally(), (0, 1)), ">v#\n#v<\n...\n");
+        let region = Region::copy(&grid, (0, 0), (0, 1));
+        assert_eq!(pasted(&mut grid, &region.flipped_vertically(), (2, 1)), ">v#\n#v#\n..>\n");
+    }
+
+    #[test]
+    fn clearing_leaves_plain_grass() {
+        let mut grid = map_file::parse_map("#~\n%S").unwrap();
+        for edit in clear_edits((1, 0), (0, 1)) {
+            grid.apply_edit(edit);
+        }
+        assert_eq!(map_file::format_map(&grid), "..\n..\n");
+    }
+}
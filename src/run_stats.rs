@@ -0,0 +1,229 @@
+use glam::Vec2;
+
+use crate::{
+    agent::{Agents, AGENT_SPEED},
+    metric::Metric,
+    solver::Solver,
+    Grid,
+};
+
+// Agents going slower than this fraction of top speed are stuck in a crowd
+const CONGESTED_SPEED: f32 = 0.25;
+
+// What happens to agents once they reach a source
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Arrival {
+    // They stay there, counted as arrived
+    #[default]
+    Stay,
+    // They're taken off the map
+    Despawn,
+    // They start over from where their trip began
+    Recycle,
+}
+
+impl Arrival {
+    pub const ALL: [Arrival; 3] = [Arrival::Stay, Arrival::Despawn, Arrival::Recycle];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Arrival::Stay => "stay",
+            Arrival::Despawn => "despawn",
+            Arrival::Recycle => "recycle",
+        }
+    }
+}
+
+// Component on every agent for how its trip has gone so far
+#[derive(Default, Clone, Copy, Debug)]
+pub struct Trip {
+    // Where the agent was the first frame it was tracked
+    pub origin: Option<Vec2>,
+    pub travel_time: f32,
+    pub path_length: f32,
+    pub congested_time: f32,
+    // Already in the run's trips, for agents that stay once they arrive
+    pub recorded: bool,
+}
+
+// A trip that reached a source, with the settings the field was made with when it did
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TripRecord {
+    pub solver: Solver,
+    pub metric: Metric,
+    pub goal: (usize, usize),
+    pub travel_time: f32,
+    pub path_length: f32,
+    // As the crow flies from the origin to the goal's center, to compare the path against
+    pub straight_distance: f32,
+    pub congested_time: f32,
+}
+
+// Averages over every trip in a run
+#[derive(Default, Clone, Copy, Debug, PartialEq)]
+pub struct Summary {
+    pub trips: usize,
+    pub travel_time: f32,
+    pub path_length: f32,
+    pub congested_time: f32,
+    pub longest_travel_time: f32,
+}
+
+// Trips finished since the run started, for comparing solvers by numbers instead of
+// by eye. Times are in seconds and lengths in world units.
+#[derive(Default, Clone, Debug)]
+pub struct RunStats {
+    pub trips: Vec<TripRecord>,
+}
+
+impl RunStats {
+    // Call once a frame after the agents moved. Trips under way get longer, and agents
+    // that arrived are recorded then dealt with as `arrival` says.
+    pub fn update(&mut self, agents: &mut Agents, grid: &Grid, arrival: Arrival, delta_time: f32) {
+        agents.add_component::<Trip>();
+        let positions = agents.positions().to_vec();
+        let speeds: Vec<f32> = agents.velocities().iter().map(|velocity| velocity.length()).collect();
+        let arrived = agents.arrived().to_vec();
+        let goals = agents.goals().to_vec();
+        let trips = agents.component_mut::<Trip>().unwrap();
+
+        let mut finished = Vec::new();
+        for (index, trip) in trips.iter_mut().enumerate() {
+            let origin = *trip.origin.get_or_insert(positions[index]);
+            if !arrived[index] {
+                trip.travel_time += delta_time;
+                trip.path_length += speeds[index] * delta_time;
+                if speeds[index] < CONGESTED_SPEED * AGENT_SPEED {
+                    trip.congested_time += delta_time;
+                }
+                continue;
+            }
+            let Some(goal) = goals[index].filter(|_| !trip.recorded) else {
+                continue;
+            };
+            trip.recorded = true;
+            self.trips.push(TripRecord {
+                solver: grid.solver,
+                metric: grid.metric,
+                goal,
+                travel_time: trip.travel_time,
+                path_length: trip.path_length,
+                straight_distance: grid.topology.cell_center(goal).distance(origin),
+                congested_time: trip.congested_time,
+            });
+            finished.push((index, origin));
+        }
+
+        match arrival {
+            Arrival::Stay => {}
+            Arrival::Despawn => agents.retain(|agents, index| !agents.arrived()[index]),
+            Arrival::Recycle => {
+                for (index, origin) in finished {
+                    agents.restart(index, origin);
+                    agents.component_mut::<Trip>().unwrap()[index] = Trip {
+                        origin: Some(origin),
+                        ..Trip::default()
+                    };
+                }
+            }
+        }
+    }
+
+    pub fn summary(&self) -> Summary {
+        let count = self.trips.len();
+        let mean = |value: fn(&TripRecord) -> f32| {
+            if count == 0 {
+                0.0
+            } else {
+                self.trips.iter().map(value).sum::<f32>() / count as f32
+            }
+        };
+        Summary {
+            trips: count,
+            travel_time: mean(|trip| trip.travel_time),
+            path_length: mean(|trip| trip.path_length),
+            congested_time: mean(|trip| trip.congested_time),
+            longest_travel_time: self.trips.iter().map(|trip| trip.travel_time).fold(0.0, f32::max),
+        }
+    }
+
+    // One line per trip under a header, so runs from different solvers can be pasted
+    // together and compared in a spreadsheet
+    pub fn to_csv(&self) -> String {
+        let mut csv =
+            String::from("solver,metric,goal_x,goal_y,travel_time,path_length,straight_distance,congested_time\n");
+        for trip in &self.trips {
+            csv.push_str(&format!(
+                "{},{},{},{},{:.3},{:.1},{:.1},{:.3}\n",
+                trip.solver.name(),
+                trip.metric.name(),
+                trip.goal.0,
+                trip.goal.1,
+                trip.travel_time,
+                trip.path_length,
+                trip.straight_distance,
+                trip.congested_time,
+            ));
+        }
+        csv
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{agent::update_agents, flow_field::FlowField, map_file, CELL_SIZE};
+
+    // Runs one agent from three cells away until its trip is recorded
+    fn run(arrival: Arrival) -> (Agents, RunStats, Vec2) {
+        let mut grid = map_file::parse_map("S...").unwrap();
+        let flow_field = FlowField::compute(&mut grid, 1.0);
+        let origin = grid.topology.cell_center((3, 0));
+        let mut agents = Agents::default();
+        agents.spawn(origin);
+        let mut run_stats = RunStats::default();
+        // Tracked before it moves, so the trip starts where it was spawned
+        run_stats.update(&mut agents, &grid, arrival, 0.0);
+        for _ in 0..200 {
+            update_agents(&mut agents, &flow_field, &grid, 0.05);
+            run_stats.update(&mut agents, &grid, arrival, 0.05);
+            if !run_stats.trips.is_empty() {
+                break;
+            }
+        }
+        (agents, run_stats, origin)
+    }
+
+    #[test]
+    fn records_trips_that_reach_a_source() {
+        let (agents, run_stats, _) = run(Arrival::Stay);
+        assert_eq!(run_stats.trips.len(), 1);
+        let trip = run_stats.trips[0];
+        assert_eq!(trip.goal, (0, 0));
+        assert_eq!(trip.straight_distance, 3.0 * CELL_SIZE);
+        // Entering the source's cell counts, so it's a little under the straight line
+        assert!(trip.path_length > 2.0 * CELL_SIZE && trip.path_length < 3.0 * CELL_SIZE);
+        assert!(agents.arrived()[0]);
+
+        let summary = run_stats.summary();
+        assert_eq!((summary.trips, summary.travel_time), (1, trip.travel_time));
+        assert_eq!(summary.longest_travel_time, trip.travel_time);
+        assert_eq!(run_stats.to_csv().lines().count(), 2);
+    }
+
+    #[test]
+    fn arrivals_are_despawned_or_sent_back() {
+        let (agents, _, _) = run(Arrival::Despawn);
+        assert!(agents.is_empty());
+
+        let (agents, _, origin) = run(Arrival::Recycle);
+        assert_eq!(agents.positions(), [origin]);
+        assert!(!agents.arrived()[0]);
+        assert_eq!(agents.component::<Trip>().unwrap()[0].travel_time, 0.0);
+    }
+
+    #[test]
+    fn empty_runs_average_to_nothing() {
+        assert_eq!(RunStats::default().summary(), Summary::default());
+    }
+}
@@ -0,0 +1,455 @@
+use std::{
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::{flow_field::FlowField, map_file, recording, Edit, Grid, MAX_GRID_SIZE};
+
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+// Clients that fall this far behind on reading are dropped rather than buffered for
+const MAX_PENDING_BYTES: usize = 64 * 1024 * 1024;
+// Longest message accepted from a client
+const MAX_MESSAGE_BYTES: usize = 1024 * 1024;
+
+// A small WebSocket server for watching and driving the visualizer from outside. It
+// never blocks: `poll` once a frame picks up new connections and whatever clients
+// sent, and messages go out as fast as the clients read them.
+//
+// Clients send text messages of edits, one per line in the recording format (like
+// `paint barrier 3 4` or `source 5 5`). The server sends JSON: `{"type":"edits",
+// "edits":[...]}` with the edits made, in the same format, and `{"type":"field", ...}`
+// with the map (in the map file format), the distances (null where unreached) and
+// the directions whenever the field changes.
+#[derive(Debug)]
+pub struct Server {
+    listener: TcpListener,
+    clients: Vec<Client>,
+}
+
+#[derive(Debug)]
+struct Client {
+    stream: TcpStream,
+    // Until the upgrade request has been answered, this is the HTTP request so far
+    received: Vec<u8>,
+    upgraded: bool,
+    // Fragments of a message that isn't finished yet
+    message: Vec<u8>,
+    pending: Vec<u8>,
+    closed: bool,
+}
+
+// What clients sent since the last poll
+#[derive(Debug, Default)]
+pub struct Incoming {
+    pub edits: Vec<Edit>,
+    // Clients that finished connecting, who haven't been sent anything yet
+    pub joined: usize,
+    // Lines that weren't edits, to be reported
+    pub errors: Vec<String>,
+}
+
+impl Server {
+    pub fn bind(address: &str) -> io::Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(Server {
+            listener,
+            clients: Vec::new(),
+        })
+    }
+
+    pub fn address(&self) -> io::Result<String> {
+        Ok(self.listener.local_addr()?.to_string())
+    }
+
+    pub fn client_count(&self) -> usize {
+        self.clients.iter().filter(|client| client.upgraded).count()
+    }
+
+    pub fn poll(&mut self) -> Incoming {
+        let mut incoming = Incoming::default();
+        while let Ok((stream, _)) = self.listener.accept() {
+            if stream.set_nonblocking(true).is_ok() {
+                self.clients.push(Client {
+                    stream,
+                    received: Vec::new(),
+                    upgraded: false,
+                    message: Vec::new(),
+                    pending: Vec::new(),
+                    closed: false,
+                });
+            }
+        }
+
+        for client in &mut self.clients {
+            let was_upgraded = client.upgraded;
+            for message in client.receive() {
+                for line in message.lines().map(str::trim).filter(|line| !line.is_empty()) {
+                    match recording::parse_edit(line) {
+                        Some(edit) => incoming.edits.push(edit),
+                        None => incoming.errors.push(format!("can't parse \"{}\"", line)),
+                    }
+                }
+            }
+            if client.upgraded && !was_upgraded {
+                incoming.joined += 1;
+            }
+            client.flush();
+        }
+        self.clients.retain(|client| !client.closed);
+        incoming
+    }
+
+    // Queues a text message for every connected client
+    pub fn broadcast(&mut self, message: &str) {
+        let frame = frame(0x1, message.as_bytes());
+        for client in self.clients.iter_mut().filter(|client| client.upgraded) {
+            client.send(&frame);
+        }
+    }
+}
+
+impl Client {
+    // Reads whatever is waiting, returning any complete text messages
+    fn receive(&mut self) -> Vec<String> {
+        let mut buffer = [0; 4096];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => {
+                    self.closed = true;
+                    break;
+                }
+                Ok(count) => self.received.extend_from_slice(&buffer[..count]),
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => break,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(_) => {
+                    self.closed = true;
+                    break;
+                }
+            }
+        }
+
+        if !self.upgraded {
+            self.upgrade();
+        }
+        let mut messages = Vec::new();
+        while self.upgraded && !self.closed {
+            let Some((opcode, fin, payload, length)) = parse_frame(&self.received) else {
+                break;
+            };
+            self.received.drain(..length);
+            match opcode {
+                0x0..=0x2 => {
+                    self.message.extend(payload);
+                    if self.message.len() > MAX_MESSAGE_BYTES {
+                        self.closed = true;
+                    } else if fin {
+                        messages.push(String::from_utf8_lossy(&self.message).into_owned());
+                        self.message.clear();
+                    }
+                }
+                0x8 => {
+                    self.send(&frame(0x8, &[]));
+                    self.flush();
+                    self.closed = true;
+                }
+                0x9 => self.send(&frame(0xA, &payload)),
+                _ => {}
+            }
+        }
+        // Whatever is left is an unfinished request or frame
+        if self.received.len() > MAX_MESSAGE_BYTES + 14 {
+            self.closed = true;
+        }
+        messages
+    }
+
+    // Answers the upgrade request once all of it is in
+    fn upgrade(&mut self) {
+        let Some(end) = self.received.windows(4).position(|window| window == b"\r\n\r\n") else {
+            return;
+        };
+        let request = String::from_utf8_lossy(&self.received[..end]).into_owned();
+        self.received.drain(..end + 4);
+        let key = request.lines().skip(1).find_map(|line| {
+            let (name, value) = line.split_once(':')?;
+            name.trim().eq_ignore_ascii_case("sec-websocket-key").then(|| value.trim().to_string())
+        });
+        match key {
+            Some(key) => {
+                let accept = base64(&sha1(format!("{}{}", key, WEBSOCKET_GUID).as_bytes()));
+                let response = format!(
+                    "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                    accept
+                );
+                self.send(response.as_bytes());
+                self.upgraded = true;
+            }
+            None => {
+                self.send(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n");
+                self.flush();
+                self.closed = true;
+            }
+        }
+    }
+
+    fn send(&mut self, bytes: &[u8]) {
+        if self.pending.len() + bytes.len() > MAX_PENDING_BYTES {
+            self.closed = true;
+            return;
+        }
+        self.pending.extend_from_slice(bytes);
+    }
+
+    // Writes as much of what's pending as the connection takes right now
+    fn flush(&mut self) {
+        while !self.pending.is_empty() {
+            match self.stream.write(&self.pending) {
+                Ok(0) => {
+                    self.closed = true;
+                    return;
+                }
+                Ok(count) => {
+                    self.pending.drain(..count);
+                }
+                Err(error) if error.kind() == io::ErrorKind::WouldBlock => return,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(_) => {
+                    self.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+// A whole unmasked frame, the way servers send them
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![0x80 | opcode];
+    match payload.len() {
+        length @ 0..=125 => bytes.push(length as u8),
+        length @ 126..=0xffff => {
+            bytes.push(126);
+            bytes.extend((length as u16).to_be_bytes());
+        }
+        length => {
+            bytes.push(127);
+            bytes.extend((length as u64).to_be_bytes());
+        }
+    }
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+// The opcode, whether it's the last fragment, the unmasked payload and the length of
+// the whole frame, once all of it has arrived
+fn parse_frame(bytes: &[u8]) -> Option<(u8, bool, Vec<u8>, usize)> {
+    let (&first, &second) = (bytes.first()?, bytes.get(1)?);
+    let masked = second & 0x80 != 0;
+    let (length, mut offset) = match second & 0x7f {
+        126 => (u16::from_be_bytes(bytes.get(2..4)?.try_into().ok()?) as usize, 4),
+        127 => (u64::from_be_bytes(bytes.get(2..10)?.try_into().ok()?) as usize, 10),
+        length => (length as usize, 2),
+    };
+    let mask = if masked {
+        offset += 4;
+        bytes.get(offset - 4..offset)?.to_vec()
+    } else {
+        vec![0; 4]
+    };
+    let payload = bytes.get(offset..offset.checked_add(length)?)?;
+    let payload = payload.iter().enumerate().map(|(index, byte)| byte ^ mask[index % 4]).collect();
+    Some((first & 0x0f, first & 0x80 != 0, payload, offset + length))
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend((data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks_exact(64) {
+        let mut words = [0u32; 80];
+        for (index, word) in block.chunks_exact(4).enumerate() {
+            words[index] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for index in 16..80 {
+            words[index] = (words[index - 3] ^ words[index - 8] ^ words[index - 14] ^ words[index - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (index, &word) in words.iter().enumerate() {
+            let (f, k) = match index {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (value, added) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(added);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (chunk, value) in digest.chunks_exact_mut(4).zip(state) {
+        chunk.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut text = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (index, &byte)| n | (byte as u32) << (16 - 8 * index));
+        for index in 0..4 {
+            if index <= chunk.len() {
+                text.push(ALPHABET[(n >> (18 - 6 * index) & 0x3f) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+fn json_string(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    for character in text.chars() {
+        match character {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            character if (character as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", character as u32)),
+            character => quoted.push(character),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Whether every cell the edit touches is on the grid, edits from clients that aren't
+// would take the grid down with them
+pub fn fits(grid: &Grid, edit: Edit) -> bool {
+    let on_grid = |(col_x, row_y): (usize, usize)| col_x < grid.column_count_x && row_y < grid.row_count_y;
+    match edit {
+        Edit::Paint(_, cell) | Edit::ToggleSource(cell) | Edit::SetCell(cell, _) | Edit::SetElevation(cell, _) => {
+            on_grid(cell)
+        }
+        Edit::TogglePortal(from, to) => on_grid(from) && on_grid(to),
+        Edit::Resize { row_count, column_count } => {
+            (1..=MAX_GRID_SIZE).contains(&row_count) && (1..=MAX_GRID_SIZE).contains(&column_count)
+        }
+        Edit::SetLevels(levels) => (1..=MAX_GRID_SIZE / grid.level_rows()).contains(&levels),
+        Edit::Reshape { levels, row_count, column_count } => {
+            (1..=row_count).contains(&levels)
+                && (1..=MAX_GRID_SIZE).contains(&row_count)
+                && (1..=MAX_GRID_SIZE).contains(&column_count)
+        }
+        Edit::Generate { .. } | Edit::ToggleWrap | Edit::SetWrap(_) | Edit::Clear => true,
+    }
+}
+
+pub fn edits_message(edits: &[Edit]) -> String {
+    let edits: Vec<String> = edits.iter().map(|&edit| json_string(&recording::format_edit(edit))).collect();
+    format!("{{\"type\":\"edits\",\"edits\":[{}]}}", edits.join(","))
+}
+
+// Distances come from the grid's cells, so it has to be the grid the field was
+// computed on
+pub fn field_message(grid: &Grid, flow_field: &FlowField) -> String {
+    let cells = || grid.grid.iter().flatten();
+    let distances: Vec<String> = cells()
+        .map(|cell| cell.cell_number.map_or("null".to_string(), |distance| format!("{:.3}", distance)))
+        .collect();
+    let directions: Vec<String> = cells()
+        .map(|cell| {
+            let direction = flow_field.direction(cell.x_position, cell.y_position);
+            format!("[{:.3},{:.3}]", direction.x, direction.y)
+        })
+        .collect();
+    format!(
+        "{{\"type\":\"field\",\"columns\":{},\"rows\":{},\"wrap\":{},\"map\":{},\"distances\":[{}],\"directions\":[{}]}}",
+        grid.column_count_x,
+        grid.row_count_y,
+        grid.wrap,
+        json_string(&map_file::format_map(grid)),
+        distances.join(","),
+        directions.join(",")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A frame the way a client sends it, with the length in the header and the payload masked
+    fn masked_frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x37, 0xfa, 0x21, 0x3d];
+        let mut bytes = frame(opcode, payload);
+        let header_length = bytes.len() - payload.len();
+        bytes[1] |= 0x80;
+        bytes.truncate(header_length);
+        bytes.extend(mask);
+        bytes.extend(payload.iter().enumerate().map(|(index, byte)| byte ^ mask[index % 4]));
+        bytes
+    }
+
+    #[test]
+    fn accept_key_matches_rfc_example() {
+        let key = "dGhlIHNhbXBsZSBub25jZQ==";
+        let accept = base64(&sha1(format!("{}{}", key, WEBSOCKET_GUID).as_bytes()));
+        assert_eq!(accept, "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn masked_frames_of_every_length_encoding() {
+        for (length, header_length) in [(5, 6), (125, 6), (126, 8), (300, 8), (0xffff, 8), (70000, 14)] {
+            let payload: Vec<u8> = (0..length).map(|index| (index % 251) as u8).collect();
+            let bytes = masked_frame(0x2, &payload);
+            assert_eq!(bytes.len(), header_length + length);
+            assert_eq!(parse_frame(&bytes), Some((0x2, true, payload, bytes.len())));
+        }
+    }
+
+    #[test]
+    fn close_frame() {
+        let bytes = masked_frame(0x8, &1000u16.to_be_bytes());
+        let (opcode, fin, payload, length) = parse_frame(&bytes).unwrap();
+        assert_eq!((opcode, fin, length), (0x8, true, bytes.len()));
+        assert_eq!(payload, 1000u16.to_be_bytes());
+    }
+
+    #[test]
+    fn incomplete_frames_wait_for_the_rest() {
+        let bytes = masked_frame(0x1, b"hello");
+        for end in 0..bytes.len() {
+            assert_eq!(parse_frame(&bytes[..end]), None);
+        }
+        let mut two = bytes.clone();
+        two.extend(masked_frame(0x1, b"world"));
+        assert_eq!(parse_frame(&two), Some((0x1, true, b"hello".to_vec(), bytes.len())));
+    }
+
+    #[test]
+    fn huge_resizes_dont_fit() {
+        let grid = Grid::new(4, 4);
+        assert!(fits(&grid, Edit::Resize { row_count: 10, column_count: MAX_GRID_SIZE }));
+        assert!(!fits(&grid, Edit::Resize { row_count: MAX_GRID_SIZE + 1, column_count: 10 }));
+        assert!(!fits(&grid, Edit::Resize { row_count: 10, column_count: usize::MAX }));
+        assert!(!fits(&grid, Edit::Resize { row_count: 0, column_count: 10 }));
+        assert!(!fits(&grid, Edit::SetLevels(usize::MAX / 2)));
+        assert!(fits(&grid, Edit::Reshape { levels: 4, row_count: MAX_GRID_SIZE, column_count: 10 }));
+        assert!(!fits(&grid, Edit::Reshape { levels: 11, row_count: 10, column_count: 10 }));
+    }
+}
@@ -0,0 +1,575 @@
+use std::{
+    cmp::Ordering,
+    collections::{BinaryHeap, VecDeque},
+};
+
+use crate::{metric::Metric, CellType, Grid};
+
+// Integration algorithms. All of them start seeds at 1 and never enter barriers, and
+// step between the neighbors the grid's metric allows.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Solver {
+    // Breadth first search, every step costs its length (1 unless it's an octile
+    // diagonal) no matter the terrain
+    Bfs,
+    // Stepping onto a cell costs its terrain cost, more on slopes
+    #[default]
+    Dijkstra,
+    // Fast marching: solves the eikonal equation with terrain costs as slowness, which
+    // gives straighter, less blocky distances than stepping cell to cell. Needs the
+    // axis-aligned neighbors of a square grid, other topologies use Dijkstra instead.
+    // Costs belong to cells rather than steps here, so elevation is left out, and the
+    // distances come out roughly straight line whatever the metric.
+    Fmm,
+}
+
+impl Solver {
+    pub const ALL: [Solver; 3] = [Solver::Bfs, Solver::Dijkstra, Solver::Fmm];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Solver::Bfs => "bfs",
+            Solver::Dijkstra => "dijkstra",
+            Solver::Fmm => "fmm",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Solver::ALL.into_iter().find(|solver| solver.name() == name)
+    }
+}
+
+// Heap entry that pops the lowest priority first out of a `BinaryHeap`
+#[derive(Debug)]
+pub(crate) struct Candidate<T> {
+    pub priority: f32,
+    pub item: T,
+}
+
+impl<T> PartialEq for Candidate<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.priority.total_cmp(&other.priority).is_eq()
+    }
+}
+
+impl<T> Eq for Candidate<T> {}
+
+impl<T> PartialOrd for Candidate<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Candidate<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.priority.total_cmp(&self.priority)
+    }
+}
+
+// For every cell, the seed its distance was carried over from
+pub type Origins = Vec<Vec<Option<(usize, usize)>>>;
+
+pub fn integrate(
+    grid: &Grid,
+    seed_coordinates: &[(usize, usize)],
+    solver: Solver,
+) -> Vec<Vec<Option<f32>>> {
+    integrate_with_origins(grid, seed_coordinates, solver).0
+}
+
+// Also keeps track of which seed won each cell. Ties go to whichever seed got there
+// first, which depends on the order they're settled in.
+pub fn integrate_with_origins(
+    grid: &Grid,
+    seed_coordinates: &[(usize, usize)],
+    solver: Solver,
+) -> (Vec<Vec<Option<f32>>>, Origins) {
+    let mut propagation = Propagation::new(grid, seed_coordinates, solver);
+    while propagation.settle_next(grid).is_some() {}
+    (propagation.best_costs, propagation.origins)
+}
+
+// Patches up `distances` and `origins` (as left by `integrate_with_origins` with the
+// same seeds and solver) after the cells in `changed` turned into barriers, opened back
+// up or changed cost, instead of integrating from scratch. Cells whose distance was
+// reached through a changed cell are cleared and filled back in from their neighbors,
+// and distances that a reopened (or cheaper) cell shortens are lowered. Fast marching
+// doesn't keep track of where its distances came from, so it integrates again. Seeds
+// can change cost but can't become barriers.
+pub fn repair(
+    grid: &Grid,
+    distances: &mut [Vec<Option<f32>>],
+    origins: &mut [Vec<Option<(usize, usize)>>],
+    seed_coordinates: &[(usize, usize)],
+    changed: &[(usize, usize)],
+    solver: Solver,
+) {
+    if solver == Solver::Fmm {
+        let (new_distances, new_origins) = integrate_with_origins(grid, seed_coordinates, solver);
+        distances.clone_from_slice(&new_distances);
+        origins.clone_from_slice(&new_origins);
+        return;
+    }
+    let step_cost = |from: (usize, usize), to: (usize, usize)| match solver {
+        Solver::Bfs => grid.step_length(from, to),
+        _ => grid.move_cost(from, to),
+    };
+    // Distances only ever pass into the cells that can step into the one they come from
+    let neighbors = |(col_x, row_y): (usize, usize)| grid.get_entering_neighbors(&grid.grid[row_y][col_x]);
+    let is_barrier = |(col_x, row_y): (usize, usize)| grid.grid[row_y][col_x].cell_type == CellType::Barrier;
+    // With diagonal steps a barrier also opens or closes the diagonals between the cells
+    // around it, so those cells count as changed too
+    let around = |cell: (usize, usize)| match grid.metric.allows_diagonals() {
+        true => grid.neighbors(cell),
+        false => Vec::new(),
+    };
+
+    // Follow the distances downhill from every changed cell, anything that could have
+    // gotten its distance through one of them has to be redone. Reopened cells don't
+    // have one to follow, and seeds keep theirs whatever they cost. The steps out of a
+    // changed cell may cost something else now (its elevation changed, say), so rather
+    // than matching them against the new costs, every neighbor further out counts.
+    let mut invalidated = vec![vec![false; grid.column_count_x]; grid.row_count_y];
+    let mut is_changed = vec![vec![false; grid.column_count_x]; grid.row_count_y];
+    let mut stack: Vec<(usize, usize)> = Vec::new();
+    for &cell in changed {
+        is_changed[cell.1][cell.0] = true;
+        if !seed_coordinates.contains(&cell) {
+            invalidated[cell.1][cell.0] = true;
+        }
+        stack.push(cell);
+        if is_barrier(cell) {
+            for (col_x, row_y) in around(cell).into_iter().filter(|cell| !seed_coordinates.contains(cell)) {
+                invalidated[row_y][col_x] = true;
+                stack.push((col_x, row_y));
+            }
+        }
+    }
+    while let Some((col_x, row_y)) = stack.pop() {
+        let Some(distance) = distances[row_y][col_x] else {
+            continue;
+        };
+        for (neighbor_x, neighbor_y) in neighbors((col_x, row_y)) {
+            let Some(neighbor_distance) = distances[neighbor_y][neighbor_x] else {
+                continue;
+            };
+            let through_cell = distance + step_cost((col_x, row_y), (neighbor_x, neighbor_y));
+            let reached_through = match is_changed[row_y][col_x] {
+                true => neighbor_distance > distance,
+                false => (neighbor_distance - through_cell).abs() <= 1e-4 * through_cell,
+            };
+            if !invalidated[neighbor_y][neighbor_x] && reached_through {
+                invalidated[neighbor_y][neighbor_x] = true;
+                stack.push((neighbor_x, neighbor_y));
+            }
+        }
+    }
+    for ((row, origin_row), invalidated_row) in distances.iter_mut().zip(origins.iter_mut()).zip(&invalidated) {
+        for ((distance, origin), &invalidated) in row.iter_mut().zip(origin_row).zip(invalidated_row) {
+            if invalidated {
+                *distance = None;
+                *origin = None;
+            }
+        }
+    }
+
+    // Restart from the cleared cells and the reopened ones, with whatever their
+    // remaining neighbors offer. Neighbors aren't always mutual (hex rows wrapping at an
+    // odd row count), so the offers come from a pass over every cell that kept its distance.
+    for &cell in changed.iter().filter(|&&cell| !is_barrier(cell)) {
+        for (col_x, row_y) in around(cell).into_iter().chain([cell]) {
+            invalidated[row_y][col_x] = true;
+        }
+    }
+    let mut frontier = Vec::new();
+    for (row_y, row) in distances.iter().enumerate() {
+        for (col_x, &distance) in row.iter().enumerate() {
+            let Some(distance) = distance else {
+                continue;
+            };
+            for (neighbor_x, neighbor_y) in neighbors((col_x, row_y)) {
+                if invalidated[neighbor_y][neighbor_x] && !is_barrier((neighbor_x, neighbor_y)) {
+                    let offered = distance + step_cost((col_x, row_y), (neighbor_x, neighbor_y));
+                    frontier.push(Offer {
+                        distance: offered,
+                        cell: (neighbor_x, neighbor_y),
+                        origin: origins[row_y][col_x],
+                    });
+                }
+            }
+        }
+    }
+
+    let mut propagation = Propagation::resume(grid, distances.to_vec(), origins.to_vec(), &frontier, solver);
+    while propagation.settle_next(grid).is_some() {}
+    distances.clone_from_slice(&propagation.best_costs);
+    origins.clone_from_slice(&propagation.origins);
+}
+
+// Distance a cell could get from one of its neighbors when resuming, along with the
+// seed the neighbor's distance came from
+#[derive(Clone, Copy, Debug)]
+struct Offer {
+    distance: f32,
+    cell: (usize, usize),
+    origin: Option<(usize, usize)>,
+}
+
+// An integration that can be paused after any cell and picked up again, so the
+// wavefront can be watched as it spreads. Cells are settled in order of distance;
+// the grid has to stay the same from `new` until the propagation finishes.
+#[derive(Debug)]
+pub struct Propagation {
+    solver: Solver,
+    best_costs: Vec<Vec<Option<f32>>>,
+    origins: Origins,
+    settled: Vec<Vec<bool>>,
+    // A fresh breadth first search only needs a queue, everything else needs a heap
+    uses_queue: bool,
+    queue: VecDeque<(f32, (usize, usize))>,
+    heap: BinaryHeap<Candidate<(usize, usize)>>,
+}
+
+impl Propagation {
+    pub fn new(grid: &Grid, seed_coordinates: &[(usize, usize)], solver: Solver) -> Self {
+        let supports_fmm = grid
+            .topology
+            .axis_neighbors((0, 0), grid.column_count_x, grid.row_count_y, grid.wrap)
+            .is_some();
+        let mut propagation = Propagation {
+            solver: if solver == Solver::Fmm && !supports_fmm { Solver::Dijkstra } else { solver },
+            best_costs: vec![vec![None; grid.column_count_x]; grid.row_count_y],
+            origins: vec![vec![None; grid.column_count_x]; grid.row_count_y],
+            settled: vec![vec![false; grid.column_count_x]; grid.row_count_y],
+            uses_queue: solver == Solver::Bfs && grid.metric != Metric::Octile,
+            queue: VecDeque::new(),
+            heap: BinaryHeap::new(),
+        };
+        for &(col_x, row_y) in seed_coordinates {
+            propagation.best_costs[row_y][col_x] = Some(1.0);
+            propagation.origins[row_y][col_x] = Some((col_x, row_y));
+            propagation.push(1.0, (col_x, row_y));
+        }
+        propagation
+    }
+
+    // Carries on from existing `distances`, visiting the `frontier` cells (with their new
+    // distances and the seeds those came from) first. Always goes through the heap, since a frontier with mixed
+    // distances would break the ring order a plain queue relies on.
+    fn resume(
+        grid: &Grid,
+        distances: Vec<Vec<Option<f32>>>,
+        origins: Origins,
+        frontier: &[Offer],
+        solver: Solver,
+    ) -> Self {
+        let mut propagation = Propagation {
+            solver,
+            best_costs: distances,
+            origins,
+            settled: vec![vec![false; grid.column_count_x]; grid.row_count_y],
+            uses_queue: false,
+            queue: VecDeque::new(),
+            heap: BinaryHeap::new(),
+        };
+        for &Offer { distance, cell: (col_x, row_y), origin } in frontier {
+            if propagation.best_costs[row_y][col_x].is_none_or(|best| distance < best) {
+                propagation.best_costs[row_y][col_x] = Some(distance);
+                propagation.origins[row_y][col_x] = origin;
+                propagation.push(distance, (col_x, row_y));
+            }
+        }
+        propagation
+    }
+
+    // Distances so far. Settled cells are final, the ones on the wavefront are the
+    // best guess yet.
+    pub fn distances(&self) -> &[Vec<Option<f32>>] {
+        &self.best_costs
+    }
+
+    pub fn into_distances(self) -> Vec<Vec<Option<f32>>> {
+        self.best_costs
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.queue.is_empty()
+            && self
+                .heap
+                .iter()
+                .all(|candidate| self.settled[candidate.item.1][candidate.item.0])
+    }
+
+    // Settles every cell closer than the next whole distance past the nearest cell on
+    // the wavefront, which for breadth first search is exactly one ring of cells.
+    // Returns how many cells got settled.
+    pub fn step_ring(&mut self, grid: &Grid) -> usize {
+        let Some(nearest) = self.peek_cost() else {
+            return 0;
+        };
+        let limit = nearest.floor() + 1.0;
+        let mut settled_count = 0;
+        while self.peek_cost().is_some_and(|cost| cost < limit) {
+            self.settle_next(grid);
+            settled_count += 1;
+        }
+        settled_count
+    }
+
+    fn push(&mut self, cost: f32, cell: (usize, usize)) {
+        if self.uses_queue {
+            self.queue.push_back((cost, cell));
+        } else {
+            self.heap.push(Candidate {
+                priority: cost,
+                item: cell,
+            });
+        }
+    }
+
+    // Cost of the next cell to settle. The heap can hold stale entries for cells that
+    // were already settled through a cheaper route, those get dropped on the way.
+    fn peek_cost(&mut self) -> Option<f32> {
+        if self.uses_queue {
+            return self.queue.front().map(|&(cost, _)| cost);
+        }
+        while let Some(candidate) = self.heap.peek() {
+            let (col_x, row_y) = candidate.item;
+            if !self.settled[row_y][col_x] {
+                return Some(candidate.priority);
+            }
+            self.heap.pop();
+        }
+        None
+    }
+
+    // Takes the nearest cell off the wavefront, settles it and updates its neighbors.
+    // None once there's nothing left to reach.
+    pub fn settle_next(&mut self, grid: &Grid) -> Option<(usize, usize)> {
+        self.peek_cost()?;
+        let (cost, (col_x, row_y)) = if self.uses_queue {
+            self.queue.pop_front()?
+        } else {
+            let candidate = self.heap.pop()?;
+            (candidate.priority, candidate.item)
+        };
+        self.settled[row_y][col_x] = true;
+
+        let cell = &grid.grid[row_y][col_x];
+        for (neighbor_x, neighbor_y) in grid.get_entering_neighbors(cell) {
+            let neighbor = &grid.grid[neighbor_y][neighbor_x];
+            if self.settled[neighbor_y][neighbor_x] || neighbor.cell_type == CellType::Barrier {
+                continue;
+            }
+
+            let new_cost = match self.solver {
+                // Costs are final as soon as a cell is reached when going in rings
+                Solver::Bfs if self.uses_queue && self.best_costs[neighbor_y][neighbor_x].is_some() => continue,
+                Solver::Bfs => cost + grid.step_length((col_x, row_y), (neighbor_x, neighbor_y)),
+                Solver::Dijkstra => cost + grid.move_cost((col_x, row_y), (neighbor_x, neighbor_y)),
+                Solver::Fmm => match self.eikonal_update(grid, (neighbor_x, neighbor_y)) {
+                    Some(new_cost) => new_cost,
+                    None => continue,
+                },
+            };
+
+            // With fast marching the cost can come from two cells, the one that was
+            // just settled is credited
+            if self.best_costs[neighbor_y][neighbor_x].is_none_or(|best| new_cost < best) {
+                self.best_costs[neighbor_y][neighbor_x] = Some(new_cost);
+                self.origins[neighbor_y][neighbor_x] = self.origins[row_y][col_x];
+                self.push(new_cost, (neighbor_x, neighbor_y));
+            }
+        }
+
+        Some((col_x, row_y))
+    }
+
+    // Fast marching: solves the eikonal equation with terrain costs as slowness, using
+    // the smallest settled value on either side of the cell along each axis
+    fn eikonal_update(&self, grid: &Grid, (col_x, row_y): (usize, usize)) -> Option<f32> {
+        let axes = grid.axis_neighbors((col_x, row_y))?;
+        let [horizontal, vertical] = axes.map(|sides| {
+            sides
+                .into_iter()
+                .flatten()
+                .filter(|&(x, y)| self.settled[y][x] && grid.can_step((col_x, row_y), (x, y)))
+                .filter_map(|(x, y)| self.best_costs[y][x])
+                .min_by(f32::total_cmp)
+        });
+
+        let cell = &grid.grid[row_y][col_x];
+        let slowness = grid.step_cost((col_x, row_y));
+        let solved = match (horizontal, vertical) {
+            (Some(a), Some(b)) if (a - b).abs() < slowness => {
+                Some((a + b + (2.0 * slowness * slowness - (a - b) * (a - b)).sqrt()) / 2.0)
+            }
+            (Some(a), Some(b)) => Some(a.min(b) + slowness),
+            (Some(a), None) | (None, Some(a)) => Some(a + slowness),
+            (None, None) => None,
+        };
+
+        // A portal's twin isn't on either axis, it's just one step away
+        let through_portal = cell
+            .portal
+            .filter(|&(x, y)| self.settled[y][x] && grid.can_step((col_x, row_y), (x, y)))
+            .and_then(|(x, y)| self.best_costs[y][x])
+            .map(|cost| cost + slowness);
+        match (solved, through_portal) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{map_file, Edit, PaintTool, Terrain};
+
+    fn map(text: &str) -> Grid {
+        map_file::parse_map(text).unwrap()
+    }
+
+    fn sources(grid: &Grid) -> Vec<(usize, usize)> {
+        grid.get_coordinates_of_type(CellType::Source)
+    }
+
+    #[test]
+    fn bfs_counts_steps_around_barriers() {
+        let grid = map("S..\n.#.\n...");
+        let distances = integrate(&grid, &sources(&grid), Solver::Bfs);
+        assert_eq!(
+            distances,
+            vec![
+                vec![Some(1.0), Some(2.0), Some(3.0)],
+                vec![Some(2.0), None, Some(4.0)],
+                vec![Some(3.0), Some(4.0), Some(5.0)],
+            ]
+        );
+    }
+
+    #[test]
+    fn dijkstra_charges_terrain() {
+        let grid = map("S.=~%");
+        let distances = integrate(&grid, &sources(&grid), Solver::Dijkstra);
+        assert_eq!(distances[0], vec![Some(1.0), Some(3.0), Some(4.0), Some(12.0), Some(16.0)]);
+        // BFS doesn't care
+        let distances = integrate(&grid, &sources(&grid), Solver::Bfs);
+        assert_eq!(distances[0], vec![Some(1.0), Some(2.0), Some(3.0), Some(4.0), Some(5.0)]);
+    }
+
+    #[test]
+    fn fmm_is_straighter_than_dijkstra() {
+        let grid = map("S====\n=====\n=====\n=====\n=====");
+        let fmm = integrate(&grid, &sources(&grid), Solver::Fmm);
+        let dijkstra = integrate(&grid, &sources(&grid), Solver::Dijkstra);
+        // Exact along the edges, where it's one dimensional
+        assert!((fmm[0][4].unwrap() - 5.0).abs() < 1e-4);
+        assert!((fmm[4][0].unwrap() - 5.0).abs() < 1e-4);
+        // Shorter than going round the corner, but never shorter than a straight line
+        let corner = fmm[4][4].unwrap();
+        assert!(corner < dijkstra[4][4].unwrap());
+        assert!(corner >= 1.0 + 4.0 * std::f32::consts::SQRT_2 - 1e-4);
+        for (row_y, row) in fmm.iter().enumerate() {
+            for (col_x, distance) in row.iter().enumerate() {
+                assert_eq!(distance, &fmm[col_x][row_y], "not symmetric at {},{}", col_x, row_y);
+            }
+        }
+    }
+
+    #[test]
+    fn repair_matches_integrating_again() {
+        let text = "S....=....\n..~~.=.%%.\n.....=....\n####.#####\n.....=..!.\n.%%..=....\n.....=...S";
+        for solver in Solver::ALL {
+            for metric in Metric::ALL {
+                let mut grid = map(text);
+                grid.metric = metric;
+                let seeds = sources(&grid);
+                let (mut distances, mut origins) = integrate_with_origins(&grid, &seeds, solver);
+                // Close the gap in the wall, open another, and change some terrain
+                let changed = [(4, 3), (1, 3), (5, 1), (2, 5)];
+                grid.apply_edit(Edit::Paint(PaintTool::Barrier, (4, 3)));
+                grid.apply_edit(Edit::Paint(PaintTool::Barrier, (1, 3)));
+                grid.apply_edit(Edit::Paint(PaintTool::Terrain(Terrain::Water), (5, 1)));
+                grid.apply_edit(Edit::Paint(PaintTool::Terrain(Terrain::Road), (2, 5)));
+                repair(&grid, &mut distances, &mut origins, &seeds, &changed, solver);
+
+                let expected = integrate(&grid, &seeds, solver);
+                for (row, expected_row) in distances.iter().zip(&expected) {
+                    for (distance, expected) in row.iter().zip(expected_row) {
+                        match (distance, expected) {
+                            (Some(distance), Some(expected)) => assert!((distance - expected).abs() < 1e-4),
+                            _ => assert_eq!(distance, expected, "{:?} {:?}", solver, metric),
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn repair_follows_steps_that_got_dearer() {
+        // The corridor's middle cell rises, so the slopes into and out of it cost more
+        // even though stepping onto it on the level doesn't
+        let text = "S=========\n=########=\n==========";
+        for metric in Metric::ALL {
+            for (cell, elevation) in [((5, 0), 4), ((1, 0), 9), ((9, 1), 2)] {
+                let mut grid = map(text);
+                grid.metric = metric;
+                let seeds = sources(&grid);
+                let (mut distances, mut origins) = integrate_with_origins(&grid, &seeds, Solver::Dijkstra);
+                grid.apply_edit(Edit::SetElevation(cell, elevation));
+                repair(&grid, &mut distances, &mut origins, &seeds, &[cell], Solver::Dijkstra);
+
+                let expected = integrate(&grid, &seeds, Solver::Dijkstra);
+                for (row, expected_row) in distances.iter().zip(&expected) {
+                    for (distance, expected) in row.iter().zip(expected_row) {
+                        match (distance, expected) {
+                            (Some(distance), Some(expected)) => assert!((distance - expected).abs() < 1e-4),
+                            _ => assert_eq!(distance, expected, "{:?} {:?}", metric, cell),
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn repair_follows_a_seed_that_rose() {
+        let mut grid = map("S....\n.....");
+        let seeds = sources(&grid);
+        let (mut distances, mut origins) = integrate_with_origins(&grid, &seeds, Solver::Dijkstra);
+        grid.apply_edit(Edit::SetElevation((0, 0), 3));
+        repair(&grid, &mut distances, &mut origins, &seeds, &[(0, 0)], Solver::Dijkstra);
+        assert_eq!(distances, integrate(&grid, &seeds, Solver::Dijkstra));
+    }
+
+    #[test]
+    fn one_way_cells_only_let_through_their_heading() {
+        // Distances spread from the source, so they cross a one-way cell against its heading
+        let grid = map("S<.\n###\nS>.");
+        let distances = integrate(&grid, &sources(&grid), Solver::Dijkstra);
+        assert!(distances[0][2].is_some());
+        assert!(distances[2][2].is_none());
+    }
+
+    #[test]
+    fn portals_link_their_ends() {
+        let mut grid = map("S.#..");
+        assert!(integrate(&grid, &sources(&grid), Solver::Bfs)[0][4].is_none());
+        grid.apply_edit(Edit::TogglePortal((1, 0), (3, 0)));
+        let distances = integrate(&grid, &sources(&grid), Solver::Bfs);
+        assert_eq!(distances[0][3], Some(3.0));
+        assert_eq!(distances[0][4], Some(4.0));
+    }
+
+    #[test]
+    fn bfs_octile_diagonals() {
+        let mut grid = map("S..\n...");
+        grid.metric = Metric::Octile;
+        let distances = integrate(&grid, &sources(&grid), Solver::Bfs);
+        assert_eq!(distances[1][1], Some(1.0 + std::f32::consts::SQRT_2));
+        assert_eq!(distances[1][2], Some(2.0 + std::f32::consts::SQRT_2));
+    }
+}
@@ -0,0 +1,79 @@
+use glam::Vec2;
+
+use crate::Grid;
+
+// Agent indices bucketed by the grid cell they're standing in, so finding the agents
+// near a point only looks at a handful of cells instead of every agent. Positions
+// change every frame, so it's rebuilt rather than kept up to date.
+#[derive(Clone, Debug)]
+pub struct SpatialHash {
+    column_count: usize,
+    // One bucket per cell, row by row
+    buckets: Vec<Vec<usize>>,
+    // Agents that are off the grid, which every query includes
+    outside: Vec<usize>,
+}
+
+impl SpatialHash {
+    pub fn build(grid: &Grid, positions: &[Vec2]) -> Self {
+        let mut hash = SpatialHash {
+            column_count: grid.column_count_x,
+            buckets: vec![Vec::new(); grid.column_count_x * grid.row_count_y],
+            outside: Vec::new(),
+        };
+        for (index, &position) in positions.iter().enumerate() {
+            match grid.get_cell_at(position) {
+                Some((col_x, row_y)) => hash.buckets[row_y * hash.column_count + col_x].push(index),
+                None => hash.outside.push(index),
+            }
+        }
+        hash
+    }
+
+    pub fn agents_in_cell(&self, (col_x, row_y): (usize, usize)) -> &[usize] {
+        &self.buckets[row_y * self.column_count + col_x]
+    }
+
+    // Agents in every cell within `radius` of `position` (plus any off the grid), some
+    // of which can be further away than that. The cells are the neighbors of the one
+    // under `position` along with whatever is under a few points around it, which only
+    // covers everything while the radius is under half a cell.
+    pub fn nearby<'a>(&'a self, grid: &Grid, position: Vec2, radius: f32) -> impl Iterator<Item = usize> + 'a {
+        let mut cells = Vec::with_capacity(16);
+        if let Some((col_x, row_y)) = grid.get_cell_at(position) {
+            cells.extend(grid.get_neighbor_coordinates(&grid.grid[row_y][col_x]));
+        }
+        for step_y in -1..=1 {
+            for step_x in -1..=1 {
+                let offset = Vec2::new(step_x as f32, step_y as f32) * radius;
+                cells.extend(grid.get_cell_at(position + offset));
+            }
+        }
+        cells.sort_unstable();
+        cells.dedup();
+        cells
+            .into_iter()
+            .flat_map(move |cell| self.agents_in_cell(cell).iter().copied())
+            .chain(self.outside.iter().copied())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::CELL_SIZE;
+
+    #[test]
+    fn finds_agents_in_neighboring_cells_and_off_the_grid() {
+        let grid = Grid::new(3, 3);
+        let center = |cell| grid.topology.cell_center(cell);
+        let positions = [center((0, 0)), center((1, 0)), center((2, 2)), Vec2::new(-100.0, -100.0), center((0, 0))];
+        let hash = SpatialHash::build(&grid, &positions);
+        assert_eq!(hash.agents_in_cell((0, 0)), [0, 4]);
+        assert_eq!(hash.agents_in_cell((1, 1)), []);
+
+        let mut nearby: Vec<usize> = hash.nearby(&grid, center((0, 0)), CELL_SIZE / 4.0).collect();
+        nearby.sort_unstable();
+        assert_eq!(nearby, [0, 1, 3, 4]);
+    }
+}
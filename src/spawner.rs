@@ -0,0 +1,48 @@
+use glam::vec2;
+use quad_rand as rand;
+
+use crate::{agent::Agents, CellType, Grid, CELL_SIZE};
+
+// Drops an agent on every spawner cell once every `interval` frames, for as long as
+// there are fewer than `cap` agents around. When the cap only leaves room for some
+// of them, the spawners take turns.
+#[derive(Clone, Copy, Debug)]
+pub struct Spawning {
+    pub interval: u32,
+    pub cap: usize,
+    frames_left: u32,
+    // Spawner the next wave starts at, counting in grid order
+    turn: usize,
+}
+
+impl Spawning {
+    pub fn new(interval: u32, cap: usize) -> Self {
+        Spawning {
+            interval,
+            cap,
+            frames_left: interval,
+            turn: 0,
+        }
+    }
+
+    // Call once a frame
+    pub fn update(&mut self, grid: &Grid, agents: &mut Agents) {
+        if self.frames_left > 1 {
+            self.frames_left -= 1;
+            return;
+        }
+        self.frames_left = self.interval.max(1);
+
+        let spawners = grid.get_coordinates_of_type(CellType::Spawner);
+        if spawners.is_empty() {
+            return;
+        }
+        let count = spawners.len().min(self.cap.saturating_sub(agents.len()));
+        let start = self.turn % spawners.len();
+        self.turn = start + count;
+        for &cell in spawners.iter().cycle().skip(start).take(count) {
+            let jitter = vec2(rand::gen_range(-0.3, 0.3), rand::gen_range(-0.3, 0.3)) * CELL_SIZE;
+            agents.spawn(grid.topology.cell_center(cell) + jitter);
+        }
+    }
+}
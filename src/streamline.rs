@@ -0,0 +1,88 @@
+use glam::Vec2;
+
+use crate::{flow_field::FlowField, CellType, Grid, CELL_SIZE};
+
+// Length of one integration step, in world units
+const STEP_LENGTH: f32 = CELL_SIZE * 0.25;
+// Turning further than this in one step means the curve ran into a ridge where two
+// directions meet, like the line between two sources' areas
+const MAX_TURN_COS: f32 = -0.5;
+
+// A curve following the field from a seed point, as points a step apart. Lines that
+// couldn't leave their seed (no direction there) are dead zones.
+#[derive(Clone, Debug)]
+pub struct Streamline {
+    pub seed: Vec2,
+    pub points: Vec<Vec2>,
+}
+
+impl Streamline {
+    pub fn is_dead(&self) -> bool {
+        self.points.len() < 2
+    }
+}
+
+// Seeds at the center of every `spacing`th cell on open ground
+pub fn seeds(grid: &Grid, spacing: usize) -> Vec<Vec2> {
+    let spacing = spacing.max(1);
+    grid.grid
+        .iter()
+        .skip(spacing / 2)
+        .step_by(spacing)
+        .flat_map(|row| row.iter().skip(spacing / 2).step_by(spacing))
+        .filter(|cell| !matches!(cell.cell_type, CellType::Barrier | CellType::Source))
+        .map(|cell| grid.topology.cell_center((cell.x_position, cell.y_position)))
+        .collect()
+}
+
+// Follows the interpolated directions from `seed` with midpoint steps until it stops at a
+// source, a barrier, the edge of the map or its level, or somewhere the field has no
+// direction.
+pub fn trace(grid: &Grid, flow_field: &FlowField, seed: Vec2) -> Streamline {
+    let world_size = grid.get_world_size();
+    // Enough steps to cross the map a few times over, nothing sensible winds more
+    let max_steps = (world_size.x + world_size.y) / STEP_LENGTH * 2.0;
+    let cell_type = |position: Vec2| {
+        let (col_x, row_y) = grid.get_cell_at(position)?;
+        Some(grid.grid[row_y][col_x].cell_type)
+    };
+    let level = grid.get_cell_at(seed).map(|cell| grid.level_of(cell));
+    let stops = |position: Vec2| {
+        position.cmplt(Vec2::ZERO).any()
+            || position.cmpge(world_size).any()
+            || matches!(cell_type(position), None | Some(CellType::Barrier | CellType::Source))
+            || grid.get_cell_at(position).map(|cell| grid.level_of(cell)) != level
+    };
+
+    let mut points = vec![seed];
+    let mut position = seed;
+    let mut heading = Vec2::ZERO;
+    for _ in 0..max_steps as usize {
+        let first = flow_field.sample(position);
+        let mut middle = flow_field.sample(position + first * STEP_LENGTH / 2.0);
+        // Blending cuts corners, near walls the cell's own direction goes around them
+        if cell_type(position + middle * STEP_LENGTH) == Some(CellType::Barrier) {
+            middle = grid
+                .get_cell_at(position)
+                .map_or(Vec2::ZERO, |(col_x, row_y)| flow_field.direction(col_x, row_y));
+        }
+        if middle == Vec2::ZERO || (heading != Vec2::ZERO && middle.dot(heading) < MAX_TURN_COS) {
+            break;
+        }
+        let next = position + middle * STEP_LENGTH;
+        points.push(next);
+        if stops(next) {
+            break;
+        }
+        position = next;
+        heading = middle;
+    }
+    Streamline { seed, points }
+}
+
+pub fn trace_all(grid: &Grid, flow_field: &FlowField, spacing: usize) -> Vec<Streamline> {
+    seeds(grid, spacing)
+        .into_iter()
+        .map(|seed| trace(grid, flow_field, seed))
+        .collect()
+}
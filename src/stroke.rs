@@ -0,0 +1,140 @@
+use std::collections::HashSet;
+
+use crate::{
+    symmetry::{Symmetry, Transform},
+    CellType, Edit, Grid, PaintTool,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StrokeKind {
+    Paint(PaintTool),
+    Source,
+}
+
+impl StrokeKind {
+    // Whether the cell already has what this stroke puts down
+    fn is_applied(self, grid: &Grid, (col_x, row_y): (usize, usize)) -> bool {
+        let cell = &grid.grid[row_y][col_x];
+        match self {
+            StrokeKind::Paint(tool) => tool.is_applied(cell),
+            StrokeKind::Source => cell.cell_type == CellType::Source,
+        }
+    }
+
+    fn edit(self, cell: (usize, usize)) -> Edit {
+        match self {
+            StrokeKind::Paint(tool) => Edit::Paint(tool, cell),
+            StrokeKind::Source => Edit::ToggleSource(cell),
+        }
+    }
+
+    fn transformed(self, transform: Transform) -> Self {
+        match self {
+            StrokeKind::Paint(tool) => StrokeKind::Paint(transform.apply_tool(tool)),
+            StrokeKind::Source => self,
+        }
+    }
+}
+
+// One press-drag-release of the pointer. The first cell decides whether the stroke
+// adds or erases, and every cell along the way is edited at most once, the same way.
+// Cells skipped between two frames are filled in with a line. With symmetry on, the
+// mirrored copies of every cell are edited along with it.
+#[derive(Debug)]
+pub struct Stroke {
+    kind: StrokeKind,
+    symmetry: Symmetry,
+    erasing: bool,
+    last_cell: (usize, usize),
+    visited: HashSet<(usize, usize)>,
+}
+
+impl Stroke {
+    pub fn start(grid: &Grid, kind: StrokeKind, symmetry: Symmetry, cell: (usize, usize)) -> (Self, Vec<Edit>) {
+        let mut stroke = Stroke {
+            kind,
+            symmetry,
+            erasing: kind.is_applied(grid, cell),
+            last_cell: cell,
+            visited: HashSet::new(),
+        };
+        let edits = stroke.visit(grid, &[cell]);
+        (stroke, edits)
+    }
+
+    pub fn kind(&self) -> StrokeKind {
+        self.kind
+    }
+
+    // Edits for the cells between the last one and `cell`. The grid is read as it was
+    // before any of the stroke's queued edits, which is fine since none of them touch
+    // the same cell twice.
+    pub fn extend(&mut self, grid: &Grid, cell: (usize, usize)) -> Vec<Edit> {
+        if cell == self.last_cell {
+            return Vec::new();
+        }
+        let line = grid.get_line_cells(self.last_cell, cell);
+        self.last_cell = cell;
+        self.visit(grid, &line)
+    }
+
+    fn visit(&mut self, grid: &Grid, cells: &[(usize, usize)]) -> Vec<Edit> {
+        let mut edits = Vec::new();
+        for &cell in cells {
+            for transform in self.symmetry.transforms() {
+                let Some(image) = transform.apply_on_level(grid, cell) else {
+                    continue;
+                };
+                let kind = self.kind.transformed(transform);
+                if self.visited.insert(image) && kind.is_applied(grid, image) == self.erasing {
+                    edits.push(kind.edit(image));
+                }
+            }
+        }
+        edits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{map_file, Heading};
+
+    fn barrier(cell: (usize, usize)) -> Edit {
+        Edit::Paint(PaintTool::Barrier, cell)
+    }
+
+    #[test]
+    fn fills_in_skipped_cells_once_each() {
+        let grid = map_file::parse_map(".....").unwrap();
+        let kind = StrokeKind::Paint(PaintTool::Barrier);
+        let (mut stroke, edits) = Stroke::start(&grid, kind, Symmetry::Off, (0, 0));
+        assert_eq!(edits, vec![barrier((0, 0))]);
+        assert_eq!(stroke.extend(&grid, (3, 0)), vec![barrier((1, 0)), barrier((2, 0)), barrier((3, 0))]);
+        // Going back over cells already visited does nothing
+        assert_eq!(stroke.extend(&grid, (1, 0)), Vec::new());
+    }
+
+    #[test]
+    fn starting_on_a_barrier_only_erases() {
+        let grid = map_file::parse_map("#.##.").unwrap();
+        let kind = StrokeKind::Paint(PaintTool::Barrier);
+        let (mut stroke, edits) = Stroke::start(&grid, kind, Symmetry::Off, (0, 0));
+        assert_eq!(edits, vec![barrier((0, 0))]);
+        assert_eq!(stroke.extend(&grid, (4, 0)), vec![barrier((2, 0)), barrier((3, 0))]);
+    }
+
+    #[test]
+    fn mirrored_strokes_edit_both_sides() {
+        let grid = map_file::parse_map(".....\n.....").unwrap();
+        let kind = StrokeKind::Paint(PaintTool::OneWay(Heading::East));
+        let (_, edits) = Stroke::start(&grid, kind, Symmetry::MirrorX, (1, 0));
+        assert_eq!(
+            edits,
+            vec![
+                Edit::Paint(PaintTool::OneWay(Heading::East), (1, 0)),
+                Edit::Paint(PaintTool::OneWay(Heading::West), (3, 0)),
+            ]
+        );
+    }
+}
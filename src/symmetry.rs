@@ -0,0 +1,150 @@
+use crate::{Grid, Heading, PaintTool};
+
+// Ways a paint stroke gets copied across the map (or its level) as it's drawn. Mirroring works on
+// column and row numbers, so on hex grids the copies are only close to mirror images.
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Symmetry {
+    #[default]
+    Off,
+    // Left to right, across the vertical center line
+    MirrorX,
+    // Top to bottom, across the horizontal center line
+    MirrorY,
+    // Four quarter turns around the center
+    Rotate4,
+}
+
+// How one copy is made from the original: flips first, then clockwise quarter turns
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Transform {
+    flip_x: bool,
+    flip_y: bool,
+    quarter_turns: u8,
+}
+
+impl Transform {
+    // None when the copy lands off the grid, which quarter turns do on maps that
+    // aren't square
+    pub fn apply_cell(self, (col_x, row_y): (usize, usize), column_count: usize, row_count: usize) -> Option<(usize, usize)> {
+        let (width, height) = (column_count as i64, row_count as i64);
+        let (mut x, mut y) = (col_x as i64, row_y as i64);
+        if self.flip_x {
+            x = width - 1 - x;
+        }
+        if self.flip_y {
+            y = height - 1 - y;
+        }
+        for _ in 0..self.quarter_turns {
+            // Around the center, which sits between cells when the two sizes differ by
+            // an odd amount and leaves no whole cell to turn onto
+            if (width + height) % 2 != 0 {
+                return None;
+            }
+            (x, y) = ((width + height - 2) / 2 - y, x + (height - width) / 2);
+        }
+        let inside = (0..width).contains(&x) && (0..height).contains(&y);
+        inside.then_some((x as usize, y as usize))
+    }
+
+    // Same as `apply_cell` within the level `cell` is on, so copies never land on
+    // another level
+    pub fn apply_on_level(self, grid: &Grid, (col_x, row_y): (usize, usize)) -> Option<(usize, usize)> {
+        let first_row = grid.level_of((col_x, row_y)) * grid.level_rows();
+        let (col_x, row_y) = self.apply_cell((col_x, row_y - first_row), grid.column_count_x, grid.level_rows())?;
+        Some((col_x, first_row + row_y))
+    }
+
+    pub fn apply_heading(self, mut heading: Heading) -> Heading {
+        heading = match heading {
+            Heading::East if self.flip_x => Heading::West,
+            Heading::West if self.flip_x => Heading::East,
+            Heading::North if self.flip_y => Heading::South,
+            Heading::South if self.flip_y => Heading::North,
+            _ => heading,
+        };
+        for _ in 0..self.quarter_turns {
+            heading = heading.rotated_clockwise();
+        }
+        heading
+    }
+
+    pub fn apply_tool(self, tool: PaintTool) -> PaintTool {
+        match tool {
+            PaintTool::OneWay(heading) => PaintTool::OneWay(self.apply_heading(heading)),
+            _ => tool,
+        }
+    }
+}
+
+impl Symmetry {
+    pub const ALL: [Symmetry; 4] = [Symmetry::Off, Symmetry::MirrorX, Symmetry::MirrorY, Symmetry::Rotate4];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Symmetry::Off => "off",
+            Symmetry::MirrorX => "mirror x",
+            Symmetry::MirrorY => "mirror y",
+            Symmetry::Rotate4 => "rotate 4",
+        }
+    }
+
+    // The original first
+    pub fn transforms(self) -> Vec<Transform> {
+        let identity = Transform::default();
+        match self {
+            Symmetry::Off => vec![identity],
+            Symmetry::MirrorX => vec![identity, Transform { flip_x: true, ..identity }],
+            Symmetry::MirrorY => vec![identity, Transform { flip_y: true, ..identity }],
+            Symmetry::Rotate4 => (0..4).map(|quarter_turns| Transform { quarter_turns, ..identity }).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::map_file;
+
+    fn images(symmetry: Symmetry, grid: &Grid, cell: (usize, usize)) -> Vec<Option<(usize, usize)>> {
+        symmetry.transforms().into_iter().map(|transform| transform.apply_on_level(grid, cell)).collect()
+    }
+
+    #[test]
+    fn mirrors_across_the_center_lines() {
+        let grid = map_file::parse_map("....\n....\n....").unwrap();
+        assert_eq!(images(Symmetry::MirrorX, &grid, (0, 1)), vec![Some((0, 1)), Some((3, 1))]);
+        assert_eq!(images(Symmetry::MirrorY, &grid, (1, 0)), vec![Some((1, 0)), Some((1, 2))]);
+        assert_eq!(images(Symmetry::Off, &grid, (1, 0)), vec![Some((1, 0))]);
+    }
+
+    #[test]
+    fn quarter_turns_go_around_the_center() {
+        let grid = map_file::parse_map("...\n...\n...").unwrap();
+        assert_eq!(
+            images(Symmetry::Rotate4, &grid, (0, 0)),
+            vec![Some((0, 0)), Some((2, 0)), Some((2, 2)), Some((0, 2))]
+        );
+        // No whole cell to turn onto when the sides differ by an odd amount
+        let grid = map_file::parse_map("...\n...").unwrap();
+        assert_eq!(images(Symmetry::Rotate4, &grid, (0, 0))[1], None);
+    }
+
+    #[test]
+    fn copies_stay_on_their_level() {
+        let grid = map_file::parse_map("...\n...\n...\n...\nlevels 2").unwrap();
+        assert_eq!(images(Symmetry::MirrorY, &grid, (1, 2)), vec![Some((1, 2)), Some((1, 3))]);
+    }
+
+    #[test]
+    fn one_way_tools_turn_with_the_copy() {
+        let east = PaintTool::OneWay(Heading::East);
+        let headings: Vec<PaintTool> = Symmetry::Rotate4.transforms().into_iter().map(|transform| transform.apply_tool(east)).collect();
+        assert_eq!(
+            headings,
+            [Heading::East, Heading::South, Heading::West, Heading::North].map(PaintTool::OneWay).to_vec()
+        );
+        let flip = Symmetry::MirrorY.transforms()[1];
+        assert_eq!(flip.apply_tool(east), east);
+        assert_eq!(flip.apply_tool(PaintTool::Barrier), PaintTool::Barrier);
+    }
+}
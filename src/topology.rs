@@ -0,0 +1,306 @@
+use std::fmt::Debug;
+
+use glam::{vec2, Vec2};
+
+use crate::{metric::Metric, CELL_SIZE};
+
+// How the cells of a grid connect and where they sit in world space. Cells are always
+// stored as rows of columns, the topology decides what that layout means, so the
+// solver, flow field and agents work the same on every kind of grid.
+pub trait Topology: Debug {
+    // Cells sharing an edge with `cell`. With `wrap` set, neighbors continue on the
+    // opposite edge of the grid.
+    fn neighbors(&self, cell: (usize, usize), columns: usize, rows: usize, wrap: bool) -> Vec<(usize, usize)>;
+
+    // Cells touching `cell` only at a corner, which metrics with diagonal steps also
+    // step to. Empty when the topology doesn't have any.
+    fn diagonal_neighbors(&self, _cell: (usize, usize), _columns: usize, _rows: usize, _wrap: bool) -> Vec<(usize, usize)> {
+        Vec::new()
+    }
+
+    // Shortest way between two cells on an empty grid moving the way `metric` does, in
+    // straight steps
+    fn distance(&self, from: (usize, usize), to: (usize, usize), columns: usize, rows: usize, wrap: bool, metric: Metric) -> f32;
+
+    fn cell_center(&self, cell: (usize, usize)) -> Vec2;
+
+    // Outline of the cell in world space, in drawing order
+    fn cell_corners(&self, cell: (usize, usize)) -> Vec<Vec2>;
+
+    // Cell containing `world_pos`. May lie off the grid, callers bounds check or wrap.
+    fn cell_at(&self, world_pos: Vec2) -> (i32, i32);
+
+    // Size of the area `columns` × `rows` cells repeat over when wrapping
+    fn world_size(&self, columns: usize, rows: usize) -> Vec2;
+
+    // Cells and weights for linearly interpolating a per-cell value at `world_pos`.
+    // The weights add up to one, the cells may lie off the grid.
+    fn interpolation_weights(&self, world_pos: Vec2) -> Vec<((i32, i32), f32)>;
+
+    // The cells on either side of `cell` along each of two perpendicular axes, for
+    // solvers that work with axis-aligned differences. None when the topology has no
+    // such axes.
+    fn axis_neighbors(
+        &self,
+        _cell: (usize, usize),
+        _columns: usize,
+        _rows: usize,
+        _wrap: bool,
+    ) -> Option<[AxisNeighbors; 2]> {
+        None
+    }
+}
+
+pub type AxisNeighbors = [Option<(usize, usize)>; 2];
+
+// Resolves a possibly off-grid cell, wrapping it around when the grid wraps
+pub fn resolve_cell(cell: (i32, i32), columns: usize, rows: usize, wrap: bool) -> Option<(usize, usize)> {
+    let (col_x, row_y) = cell;
+    if wrap {
+        return Some((
+            col_x.rem_euclid(columns as i32) as usize,
+            row_y.rem_euclid(rows as i32) as usize,
+        ));
+    }
+    if col_x < 0 || row_y < 0 || col_x >= columns as i32 || row_y >= rows as i32 {
+        return None;
+    }
+    Some((col_x as usize, row_y as usize))
+}
+
+#[derive(Debug)]
+pub struct SquareTopology;
+
+impl Topology for SquareTopology {
+    fn neighbors(&self, cell: (usize, usize), columns: usize, rows: usize, wrap: bool) -> Vec<(usize, usize)> {
+        let (col_x, row_y) = cell;
+        if wrap {
+            return vec![
+                ((col_x + columns - 1) % columns, row_y),
+                (col_x, (row_y + rows - 1) % rows),
+                ((col_x + 1) % columns, row_y),
+                (col_x, (row_y + 1) % rows),
+            ];
+        }
+
+        let mut adjacent = Vec::new();
+        if col_x > 0 {
+            adjacent.push((col_x - 1, row_y));
+        }
+        if row_y > 0 {
+            adjacent.push((col_x, row_y - 1));
+        }
+        if col_x + 1 < columns {
+            adjacent.push((col_x + 1, row_y));
+        }
+        if row_y + 1 < rows {
+            adjacent.push((col_x, row_y + 1));
+        }
+        adjacent
+    }
+
+    fn diagonal_neighbors(&self, cell: (usize, usize), columns: usize, rows: usize, wrap: bool) -> Vec<(usize, usize)> {
+        let (col_x, row_y) = (cell.0 as i32, cell.1 as i32);
+        [(-1, -1), (1, -1), (1, 1), (-1, 1)]
+            .into_iter()
+            .filter_map(|(step_x, step_y)| resolve_cell((col_x + step_x, row_y + step_y), columns, rows, wrap))
+            .collect()
+    }
+
+    fn distance(&self, from: (usize, usize), to: (usize, usize), columns: usize, rows: usize, wrap: bool, metric: Metric) -> f32 {
+        let mut distance_x = from.0.abs_diff(to.0);
+        let mut distance_y = from.1.abs_diff(to.1);
+        if wrap {
+            distance_x = distance_x.min(columns - distance_x);
+            distance_y = distance_y.min(rows - distance_y);
+        }
+        metric.distance(distance_x, distance_y)
+    }
+
+    fn cell_center(&self, cell: (usize, usize)) -> Vec2 {
+        vec2(cell.0 as f32 + 0.5, cell.1 as f32 + 0.5) * CELL_SIZE
+    }
+
+    fn cell_corners(&self, cell: (usize, usize)) -> Vec<Vec2> {
+        let top_left = vec2(cell.0 as f32, cell.1 as f32) * CELL_SIZE;
+        vec![
+            top_left,
+            top_left + vec2(CELL_SIZE, 0.0),
+            top_left + vec2(CELL_SIZE, CELL_SIZE),
+            top_left + vec2(0.0, CELL_SIZE),
+        ]
+    }
+
+    fn cell_at(&self, world_pos: Vec2) -> (i32, i32) {
+        (
+            (world_pos.x / CELL_SIZE).floor() as i32,
+            (world_pos.y / CELL_SIZE).floor() as i32,
+        )
+    }
+
+    fn world_size(&self, columns: usize, rows: usize) -> Vec2 {
+        vec2(columns as f32, rows as f32) * CELL_SIZE
+    }
+
+    // Bilinear over the four cells whose centers surround `world_pos`
+    fn interpolation_weights(&self, world_pos: Vec2) -> Vec<((i32, i32), f32)> {
+        let grid_x = world_pos.x / CELL_SIZE - 0.5;
+        let grid_y = world_pos.y / CELL_SIZE - 0.5;
+        let left = grid_x.floor();
+        let top = grid_y.floor();
+        let fraction_x = grid_x - left;
+        let fraction_y = grid_y - top;
+        let (left, top) = (left as i32, top as i32);
+
+        vec![
+            ((left, top), (1.0 - fraction_x) * (1.0 - fraction_y)),
+            ((left + 1, top), fraction_x * (1.0 - fraction_y)),
+            ((left, top + 1), (1.0 - fraction_x) * fraction_y),
+            ((left + 1, top + 1), fraction_x * fraction_y),
+        ]
+    }
+
+    fn axis_neighbors(
+        &self,
+        cell: (usize, usize),
+        columns: usize,
+        rows: usize,
+        wrap: bool,
+    ) -> Option<[AxisNeighbors; 2]> {
+        let (col_x, row_y) = (cell.0 as i32, cell.1 as i32);
+        let side = |offset_x, offset_y| {
+            resolve_cell((col_x + offset_x, row_y + offset_y), columns, rows, wrap)
+        };
+        Some([[side(-1, 0), side(1, 0)], [side(0, -1), side(0, 1)]])
+    }
+}
+
+// Pointy-top hexagons. Neighbor and distance math runs on axial coordinates (q, r),
+// storage uses "odd-r" offset coordinates where every odd row is pushed half a cell
+// to the right, so the map still fills a rectangle.
+#[derive(Debug)]
+pub struct HexTopology;
+
+// Distance from a hex center to its corners, chosen so a hex is CELL_SIZE wide
+const HEX_RADIUS: f32 = CELL_SIZE / 1.732_050_8;
+const HEX_ROW_HEIGHT: f32 = HEX_RADIUS * 1.5;
+
+// Axial neighbor offsets, ordered clockwise (y points down) starting east
+const HEX_DIRECTIONS: [(i32, i32); 6] = [(1, 0), (0, 1), (-1, 1), (-1, 0), (0, -1), (1, -1)];
+
+impl HexTopology {
+    fn to_axial((col_x, row_y): (i32, i32)) -> (i32, i32) {
+        (col_x - (row_y - (row_y & 1)) / 2, row_y)
+    }
+
+    fn to_offset((q, r): (i32, i32)) -> (i32, i32) {
+        (q + (r - (r & 1)) / 2, r)
+    }
+
+    fn axial_center((q, r): (i32, i32)) -> Vec2 {
+        vec2(
+            CELL_SIZE * (q as f32 + r as f32 / 2.0) + CELL_SIZE / 2.0,
+            HEX_ROW_HEIGHT * r as f32 + HEX_RADIUS,
+        )
+    }
+
+    fn axial_distance(from: (i32, i32), to: (i32, i32)) -> usize {
+        let delta_q = from.0 - to.0;
+        let delta_r = from.1 - to.1;
+        ((delta_q.abs() + delta_r.abs() + (delta_q + delta_r).abs()) / 2) as usize
+    }
+}
+
+impl Topology for HexTopology {
+    fn neighbors(&self, cell: (usize, usize), columns: usize, rows: usize, wrap: bool) -> Vec<(usize, usize)> {
+        let (q, r) = Self::to_axial((cell.0 as i32, cell.1 as i32));
+        HEX_DIRECTIONS
+            .iter()
+            .filter_map(|&(delta_q, delta_r)| {
+                resolve_cell(Self::to_offset((q + delta_q, r + delta_r)), columns, rows, wrap)
+            })
+            .collect()
+    }
+
+    // Hex cells have no diagonals, every metric is the same here
+    fn distance(&self, from: (usize, usize), to: (usize, usize), columns: usize, rows: usize, wrap: bool, _metric: Metric) -> f32 {
+        let from = Self::to_axial((from.0 as i32, from.1 as i32));
+        let shifts: &[i32] = if wrap { &[-1, 0, 1] } else { &[0] };
+
+        // When wrapping, the closest copy of `to` may sit one map over in any direction
+        let mut closest = usize::MAX;
+        for &shift_y in shifts {
+            for &shift_x in shifts {
+                let to = Self::to_axial((
+                    to.0 as i32 + shift_x * columns as i32,
+                    to.1 as i32 + shift_y * rows as i32,
+                ));
+                closest = closest.min(Self::axial_distance(from, to));
+            }
+        }
+        closest as f32
+    }
+
+    fn cell_center(&self, cell: (usize, usize)) -> Vec2 {
+        Self::axial_center(Self::to_axial((cell.0 as i32, cell.1 as i32)))
+    }
+
+    fn cell_corners(&self, cell: (usize, usize)) -> Vec<Vec2> {
+        let center = self.cell_center(cell);
+        (0..6)
+            .map(|corner| {
+                let angle = (30.0 + 60.0 * corner as f32).to_radians();
+                center + vec2(angle.cos(), angle.sin()) * HEX_RADIUS
+            })
+            .collect()
+    }
+
+    fn cell_at(&self, world_pos: Vec2) -> (i32, i32) {
+        let local = world_pos - vec2(CELL_SIZE / 2.0, HEX_RADIUS);
+        let q = (3.0_f32.sqrt() / 3.0 * local.x - local.y / 3.0) / HEX_RADIUS;
+        let r = (2.0 / 3.0 * local.y) / HEX_RADIUS;
+
+        // Round in cube coordinates, fixing up whichever axis rounded the furthest
+        let s = -q - r;
+        let (mut rounded_q, mut rounded_r, rounded_s) = (q.round(), r.round(), s.round());
+        let (error_q, error_r, error_s) = ((rounded_q - q).abs(), (rounded_r - r).abs(), (rounded_s - s).abs());
+        if error_q > error_r && error_q > error_s {
+            rounded_q = -rounded_r - rounded_s;
+        } else if error_r > error_s {
+            rounded_r = -rounded_q - rounded_s;
+        }
+
+        Self::to_offset((rounded_q as i32, rounded_r as i32))
+    }
+
+    fn world_size(&self, columns: usize, rows: usize) -> Vec2 {
+        vec2(columns as f32 * CELL_SIZE, rows as f32 * HEX_ROW_HEIGHT)
+    }
+
+    // Barycentric over the triangle formed by the nearest center and the two
+    // neighboring centers on either side of `world_pos`
+    fn interpolation_weights(&self, world_pos: Vec2) -> Vec<((i32, i32), f32)> {
+        let nearest = Self::to_axial(self.cell_at(world_pos));
+        let offset = world_pos - Self::axial_center(nearest);
+
+        let angle = offset.y.atan2(offset.x).rem_euclid(std::f32::consts::TAU);
+        let sector = ((angle / 60.0_f32.to_radians()) as usize).min(5);
+        let first = HEX_DIRECTIONS[sector];
+        let second = HEX_DIRECTIONS[(sector + 1) % 6];
+        let first = (nearest.0 + first.0, nearest.1 + first.1);
+        let second = (nearest.0 + second.0, nearest.1 + second.1);
+
+        // Solve offset = a * edge_first + b * edge_second
+        let edge_first = Self::axial_center(first) - Self::axial_center(nearest);
+        let edge_second = Self::axial_center(second) - Self::axial_center(nearest);
+        let determinant = edge_first.perp_dot(edge_second);
+        let weight_first = offset.perp_dot(edge_second) / determinant;
+        let weight_second = edge_first.perp_dot(offset) / determinant;
+
+        vec![
+            (Self::to_offset(nearest), 1.0 - weight_first - weight_second),
+            (Self::to_offset(first), weight_first),
+            (Self::to_offset(second), weight_second),
+        ]
+    }
+}
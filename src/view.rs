@@ -0,0 +1,134 @@
+use macroquad::prelude::*;
+
+use flowfields::CELL_SIZE;
+
+// Screen space kept free for the side panel on the right
+const HUD_WIDTH: f32 = 300.0;
+// Side panel text size, and the distance from one line's baseline to the next
+const HUD_FONT_SIZE: f32 = 16.0;
+const HUD_LINE_HEIGHT: f32 = 18.0;
+// Space between the side panel's edges and its text
+const HUD_MARGIN: f32 = 10.0;
+// Border around the grid in world units, also leaves room for the half cell hex rows stick out by
+const BORDER: f32 = CELL_SIZE / 2.0;
+
+// Maps world space (CELL_SIZE per cell) onto the window left of the side panel,
+// recomputed every frame so the grid follows the window size. Letterboxed views keep
+// cells square, otherwise the grid is stretched to fill the space. At zoom 1 the whole
+// grid fits, zooming in magnifies around `focus`, the world position kept in the middle.
+#[derive(Clone, Copy, Debug)]
+pub struct View {
+    scale: Vec2,
+    offset: Vec2,
+}
+
+impl View {
+    pub fn fit(world_size: Vec2, letterbox: bool, zoom: f32, focus: Vec2) -> Self {
+        let area = grid_area();
+        let framed_size = world_size + Vec2::splat(BORDER * 2.0);
+        let scale = area / framed_size;
+        let scale = if letterbox { Vec2::splat(scale.min_element()) } else { scale } * zoom;
+        let offset = area / 2.0 - focus * scale;
+        View { scale, offset }
+    }
+
+    pub fn screen_to_world(&self, screen_pos: Vec2) -> Vec2 {
+        (screen_pos - self.offset) / self.scale
+    }
+
+    // Camera for drawing in world space, covering the whole window so screen and
+    // world positions line up the same way `screen_to_world` maps them
+    pub fn camera(&self) -> Camera2D {
+        let top_left = self.screen_to_world(Vec2::ZERO);
+        let size = vec2(screen_width(), screen_height()) / self.scale;
+        Camera2D::from_display_rect(Rect::new(top_left.x, top_left.y, size.x, size.y))
+    }
+
+    // The world space shown left of the side panel
+    pub fn visible_area(&self) -> Rect {
+        let top_left = self.screen_to_world(Vec2::ZERO);
+        let size = grid_area() / self.scale;
+        Rect::new(top_left.x, top_left.y, size.x, size.y)
+    }
+
+    // Screen pixels per world unit, the smaller of the two when stretched
+    pub fn pixels_per_unit(&self) -> f32 {
+        self.scale.min_element()
+    }
+}
+
+// The part of the window the grid is drawn in, left of the side panel
+pub fn grid_area() -> Vec2 {
+    vec2((screen_width() - HUD_WIDTH).max(1.0), screen_height().max(1.0))
+}
+
+// Writes the side panel top down, every line moving the cursor below it so lines can be
+// added or left out without the rest shifting around by hand. Lines too wide for the
+// panel carry on indented underneath.
+pub struct Hud {
+    x: f32,
+    y: f32,
+}
+
+impl Hud {
+    // Zoomed in cells reach under the panel, so it gets a backdrop of its own
+    pub fn start() -> Self {
+        let left = screen_width() - HUD_WIDTH;
+        draw_rectangle(left, 0.0, HUD_WIDTH, screen_height(), WHITE);
+        Hud {
+            x: left + HUD_MARGIN,
+            y: HUD_LINE_HEIGHT,
+        }
+    }
+
+    pub fn line(&mut self, text: &str) {
+        let fits = |text: &str| measure_text(text, None, HUD_FONT_SIZE as u16, 1.0).width <= HUD_WIDTH - HUD_MARGIN * 2.0;
+        for wrapped in wrap(text, fits) {
+            draw_text(&wrapped, self.x, self.y, HUD_FONT_SIZE, DARKGRAY);
+            self.y += HUD_LINE_HEIGHT;
+        }
+    }
+}
+
+// Breaks `text` at spaces into lines `fits` takes, indenting all but the first. A word
+// that doesn't fit even on a line of its own gets one anyway.
+fn wrap(text: &str, fits: impl Fn(&str) -> bool) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for (index, word) in text.split(' ').enumerate() {
+        let joined = if index == 0 { word.to_string() } else { format!("{} {}", line, word) };
+        if index == 0 || line.trim().is_empty() || fits(&joined) {
+            line = joined;
+        } else {
+            lines.push(line);
+            line = format!("  {}", word);
+        }
+    }
+    lines.push(line);
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wrap_at(text: &str, width: usize) -> Vec<String> {
+        wrap(text, |line| line.chars().count() <= width)
+    }
+
+    #[test]
+    fn wraps_at_spaces_and_indents() {
+        assert_eq!(wrap_at("Wrap: off (W)", 20), ["Wrap: off (W)"]);
+        assert_eq!(
+            wrap_at("Solver: dijkstra (S), octile (D), terrain costs", 20),
+            ["Solver: dijkstra", "  (S), octile (D),", "  terrain costs"]
+        );
+        // Lines that start with spaces keep them
+        assert_eq!(wrap_at("  0.0 s, 0 long", 20), ["  0.0 s, 0 long"]);
+    }
+
+    #[test]
+    fn long_words_get_a_line_of_their_own() {
+        assert_eq!(wrap_at("Path: LeftShift, MouseMiddle", 8), ["Path:", "  LeftShift,", "  MouseMiddle"]);
+    }
+}